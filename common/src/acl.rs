@@ -1,31 +1,153 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use serde::{Deserialize, Serialize};
 use crate::command::CommandID;
 
+/// Incremented on every change of the ACLs. Shared with the connections, so they can tell whether their cached
+/// decisions are still valid without locking the ACLs
+#[derive(Debug, Default, Clone)]
+pub struct AclVersion(Arc<AtomicU64>);
+
+impl AclVersion {
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    fn bump(&self) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// The ACL decisions of a single connection. Only valid for its current user, so it has to be cleared when the user changes
+#[derive(Debug, Default)]
+pub struct AclDecisionCache {
+    version: AclVersion,
+    /// The version the decisions were made with
+    seen: u64,
+    decisions: HashMap<CommandID, bool>,
+}
+
+impl AclDecisionCache {
+    pub fn new(version: AclVersion) -> Self {
+        let seen = version.get();
+        Self {
+            version,
+            seen,
+            decisions: HashMap::new(),
+        }
+    }
+
+    /// None if the command wasn't checked yet, or the ACLs changed since
+    pub fn get(&self, command: CommandID) -> Option<bool> {
+        if self.version.get() != self.seen {
+            return None;
+        }
+        self.decisions.get(&command).copied()
+    }
+
+    /// Remembers a decision. version has to be read together with the decision, while the ACLs can't change
+    pub fn insert(&mut self, version: u64, command: CommandID, allowed: bool) {
+        if version != self.seen {
+            self.decisions.clear();
+            self.seen = version;
+        }
+        self.decisions.insert(command, allowed);
+    }
+
+    pub fn clear(&mut self) {
+        self.decisions.clear();
+    }
+}
+
+/// Everything the ACLs allow and deny, to copy them to another server with ACLImport. Sorted, so equal ACLs export equally
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AclExport {
+    pub users: BTreeMap<String, BTreeSet<CommandID>>,
+    pub anonymous: BTreeSet<CommandID>,
+    pub disabled: BTreeSet<CommandID>,
+}
 
 #[derive(Debug, Default, Clone)]
 pub struct ACL {
     map: HashMap<String, HashSet<CommandID>>,
+    /// Commands connections are allowed to execute without logging in
+    anonymous: HashSet<CommandID>,
+    /// Commands nobody is allowed to execute, whatever the other ACLs say
+    disabled: HashSet<CommandID>,
+    version: AclVersion,
 }
 
 impl ACL {
     pub fn add(&mut self, user: &str, command: CommandID) {
         self.map.entry(user.to_string()).or_default().insert(command);
+        self.version.bump();
     }
 
     pub fn remove(&mut self, user: &str, command: CommandID) {
         if let Some(set) = self.map.get_mut(user) {
             set.remove(&command);
         }
+        self.version.bump();
+    }
+
+    pub fn add_anonymous(&mut self, command: CommandID) {
+        self.anonymous.insert(command);
+        self.version.bump();
     }
 
+    /// Rejects the command for everyone. Takes precedence over everything else, even the always allowed commands
+    pub fn disable(&mut self, command: CommandID) {
+        self.disabled.insert(command);
+        self.version.bump();
+    }
+
+    /// An empty user is a connection that hasn't logged in, for which the anonymous ACLs apply
     pub fn is_allowed(&self, user: &str, command: CommandID) -> bool {
-        if command == CommandID::KEYEXCHANGE || command == CommandID::Login || command == CommandID::Heartbeat {
+        if self.disabled.contains(&command) {
+            return false;
+        }
+        if command == CommandID::KEYEXCHANGE || command == CommandID::Login || command == CommandID::Heartbeat || command == CommandID::CommandList || command == CommandID::ConnStats || command == CommandID::Time || command == CommandID::SessionCommands || command == CommandID::Features || command == CommandID::Role || command == CommandID::SecurityInfo {
             return true;
         }
+        if user.is_empty() {
+            return self.anonymous.contains(&command);
+        }
 
         self.map.get(user).map_or(false, |set| set.contains(&command))
     }
 
+    pub fn export(&self) -> AclExport {
+        AclExport {
+            users: self.map.iter()
+                .filter(|(_, commands)| !commands.is_empty())
+                .map(|(user, commands)| (user.clone(), commands.iter().copied().collect()))
+                .collect(),
+            anonymous: self.anonymous.iter().copied().collect(),
+            disabled: self.disabled.iter().copied().collect(),
+        }
+    }
+
+    /// Adds the exported ACLs to the existing ones, or replaces the user and anonymous ACLs with replace.
+    /// Disabled commands are only ever added, so an import can't enable what the config disabled
+    pub fn import(&mut self, acls: AclExport, replace: bool) {
+        if replace {
+            self.map.clear();
+            self.anonymous.clear();
+        }
+        for (user, commands) in acls.users {
+            self.map.entry(user).or_default().extend(commands);
+        }
+        self.anonymous.extend(acls.anonymous);
+        self.disabled.extend(acls.disabled);
+        self.version.bump();
+    }
+
+    /// Shared handle to the version, see AclDecisionCache
+    pub fn version(&self) -> AclVersion {
+        self.version.clone()
+    }
+
     pub fn list(&self, user: &str) -> Vec<CommandID> {
         self.map.get(user).map_or(Vec::new(), |set| set.iter().copied().collect())
     }
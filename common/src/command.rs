@@ -1,3 +1,4 @@
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Display;
 use serde::{Deserialize, Serialize};
 
@@ -26,6 +27,97 @@ pub enum CommandID {
     UserRemove = 20,
     ClientID = 21,
     Shutdown = 22,
+    LPUSHUNIQUE = 23,
+    DRAIN = 24,
+    JSONGET = 25,
+    JSONSET = 26,
+    COMPARESET = 27,
+    SlowLogGet = 28,
+    SlowLogReset = 29,
+    HKEYSMATCH = 30,
+    KEYINFO = 31,
+    HGETSORTED = 32,
+    CommandList = 33,
+    MULTIHSET = 34,
+    LGET = 35,
+    HDUMP = 36,
+    HRESTORE = 37,
+    LROTATE = 38,
+    MEMORYUSAGE = 39,
+    ConnStats = 40,
+    MULTILOCK = 41,
+    WATCH = 42,
+    UNWATCH = 43,
+    Time = 44,
+    TAG = 45,
+    UNTAG = 46,
+    KEYSBYTAG = 47,
+    Compact = 48,
+    HFILTER = 49,
+    HSWAP = 50,
+    LINTER = 51,
+    LDIFF = 52,
+    GETORSET = 53,
+    HTOP = 54,
+    ReKey = 55,
+    LMULTILEN = 56,
+    PIPELINE = 57,
+    DECRDEL = 58,
+    DebugCheck = 59,
+    SessionCommands = 60,
+    RecentlyModified = 61,
+    Features = 62,
+    Role = 63,
+    PauseWrites = 64,
+    ResumeWrites = 65,
+    Digest = 66,
+    AclExport = 67,
+    AclImport = 68,
+    APPENDIF = 69,
+    KEYSOFTYPE = 70,
+    PROTECT = 71,
+    UNPROTECT = 72,
+    LREPLACE = 73,
+    LPOPTOLIST = 74,
+    KEYPAGE = 75,
+    SETWITHVERSION = 76,
+    SecurityInfo = 77,
+    LLEN = 78,
+    LINDEX = 79,
+    LMOVE = 80,
+    LPOP = 81,
+    LPOS = 82,
+    LPUSH = 83,
+    LPUSHX = 84,
+    LRANGE = 85,
+    LREM = 86,
+    LSET = 87,
+    LTRIM = 88,
+    RPOP = 89,
+    RPUSH = 90,
+    RPUSHX = 91,
+    SIZEHISTOGRAM = 92,
+    CloseIdle = 93,
+    Expire = 94,
+    Ttl = 95,
+    Persist = 96,
+    RENAMETYPED = 97,
+    Exists = 98,
+    Type = 99,
+}
+
+impl CommandID {
+    /// Whether the command changes the keyspace. Users and ACLs don't count, they aren't part of it
+    pub fn is_write(self) -> bool {
+        matches!(self, CommandID::Set | CommandID::Delete | CommandID::HSET | CommandID::HDEL | CommandID::HINCRBY
+            | CommandID::HUPSERT | CommandID::LPUSHUNIQUE | CommandID::DRAIN | CommandID::JSONSET | CommandID::COMPARESET
+            | CommandID::MULTIHSET | CommandID::HRESTORE | CommandID::LROTATE | CommandID::MULTILOCK | CommandID::TAG
+            | CommandID::UNTAG | CommandID::HSWAP | CommandID::GETORSET | CommandID::DECRDEL | CommandID::APPENDIF
+            | CommandID::PROTECT | CommandID::UNPROTECT | CommandID::LREPLACE | CommandID::LPOPTOLIST
+            | CommandID::SETWITHVERSION | CommandID::LMOVE | CommandID::LPOP | CommandID::LPUSH | CommandID::LPUSHX
+            | CommandID::LREM | CommandID::LSET | CommandID::LTRIM | CommandID::RPOP | CommandID::RPUSH | CommandID::RPUSHX
+            | CommandID::Expire | CommandID::Persist | CommandID::RENAMETYPED)
+    }
 }
 
 impl Display for CommandID {
@@ -54,6 +146,83 @@ impl Display for CommandID {
             CommandID::UserRemove => { "UserRemove".to_string() }
             CommandID::ClientID => { "ClientID".to_string() }
             CommandID::Shutdown => { "Shutdown".to_string() }
+            CommandID::LPUSHUNIQUE => { "LPUSHUNIQUE".to_string() }
+            CommandID::DRAIN => { "DRAIN".to_string() }
+            CommandID::JSONGET => { "JSONGET".to_string() }
+            CommandID::JSONSET => { "JSONSET".to_string() }
+            CommandID::COMPARESET => { "COMPARESET".to_string() }
+            CommandID::SlowLogGet => { "SlowLogGet".to_string() }
+            CommandID::SlowLogReset => { "SlowLogReset".to_string() }
+            CommandID::HKEYSMATCH => { "HKEYSMATCH".to_string() }
+            CommandID::KEYINFO => { "KEYINFO".to_string() }
+            CommandID::HGETSORTED => { "HGETSORTED".to_string() }
+            CommandID::CommandList => { "CommandList".to_string() }
+            CommandID::MULTIHSET => { "MULTIHSET".to_string() }
+            CommandID::LGET => { "LGET".to_string() }
+            CommandID::HDUMP => { "HDUMP".to_string() }
+            CommandID::HRESTORE => { "HRESTORE".to_string() }
+            CommandID::LROTATE => { "LROTATE".to_string() }
+            CommandID::MEMORYUSAGE => { "MEMORYUSAGE".to_string() }
+            CommandID::ConnStats => { "ConnStats".to_string() }
+            CommandID::MULTILOCK => { "MULTILOCK".to_string() }
+            CommandID::WATCH => { "WATCH".to_string() }
+            CommandID::UNWATCH => { "UNWATCH".to_string() }
+            CommandID::Time => { "Time".to_string() }
+            CommandID::TAG => { "TAG".to_string() }
+            CommandID::UNTAG => { "UNTAG".to_string() }
+            CommandID::KEYSBYTAG => { "KEYSBYTAG".to_string() }
+            CommandID::Compact => { "Compact".to_string() }
+            CommandID::HFILTER => { "HFILTER".to_string() }
+            CommandID::HSWAP => { "HSWAP".to_string() }
+            CommandID::LINTER => { "LINTER".to_string() }
+            CommandID::LDIFF => { "LDIFF".to_string() }
+            CommandID::GETORSET => { "GETORSET".to_string() }
+            CommandID::HTOP => { "HTOP".to_string() }
+            CommandID::ReKey => { "ReKey".to_string() }
+            CommandID::LMULTILEN => { "LMULTILEN".to_string() }
+            CommandID::PIPELINE => { "PIPELINE".to_string() }
+            CommandID::DECRDEL => { "DECRDEL".to_string() }
+            CommandID::DebugCheck => { "DebugCheck".to_string() }
+            CommandID::SessionCommands => { "SessionCommands".to_string() }
+            CommandID::RecentlyModified => { "RecentlyModified".to_string() }
+            CommandID::Features => { "Features".to_string() }
+            CommandID::Role => { "Role".to_string() }
+            CommandID::PauseWrites => { "PauseWrites".to_string() }
+            CommandID::ResumeWrites => { "ResumeWrites".to_string() }
+            CommandID::Digest => { "Digest".to_string() }
+            CommandID::AclExport => { "ACLExport".to_string() }
+            CommandID::AclImport => { "ACLImport".to_string() }
+            CommandID::APPENDIF => { "APPENDIF".to_string() }
+            CommandID::KEYSOFTYPE => { "KEYSOFTYPE".to_string() }
+            CommandID::PROTECT => { "PROTECT".to_string() }
+            CommandID::UNPROTECT => { "UNPROTECT".to_string() }
+            CommandID::LREPLACE => { "LREPLACE".to_string() }
+            CommandID::LPOPTOLIST => { "LPOPTOLIST".to_string() }
+            CommandID::KEYPAGE => { "KEYPAGE".to_string() }
+            CommandID::SETWITHVERSION => { "SETWITHVERSION".to_string() }
+            CommandID::SecurityInfo => { "SecurityInfo".to_string() }
+            CommandID::LLEN => { "LLEN".to_string() }
+            CommandID::LINDEX => { "LINDEX".to_string() }
+            CommandID::LMOVE => { "LMOVE".to_string() }
+            CommandID::LPOP => { "LPOP".to_string() }
+            CommandID::LPOS => { "LPOS".to_string() }
+            CommandID::LPUSH => { "LPUSH".to_string() }
+            CommandID::LPUSHX => { "LPUSHX".to_string() }
+            CommandID::LRANGE => { "LRANGE".to_string() }
+            CommandID::LREM => { "LREM".to_string() }
+            CommandID::LSET => { "LSET".to_string() }
+            CommandID::LTRIM => { "LTRIM".to_string() }
+            CommandID::RPOP => { "RPOP".to_string() }
+            CommandID::RPUSH => { "RPUSH".to_string() }
+            CommandID::RPUSHX => { "RPUSHX".to_string() }
+            CommandID::SIZEHISTOGRAM => { "SIZEHISTOGRAM".to_string() }
+            CommandID::CloseIdle => { "CloseIdle".to_string() }
+            CommandID::Expire => { "EXPIRE".to_string() }
+            CommandID::Ttl => { "TTL".to_string() }
+            CommandID::Persist => { "PERSIST".to_string() }
+            CommandID::RENAMETYPED => { "RENAMETYPED".to_string() }
+            CommandID::Exists => { "EXISTS".to_string() }
+            CommandID::Type => { "TYPE".to_string() }
         };
         write!(f, "{}", str)
     }
@@ -86,6 +255,83 @@ impl TryFrom<u32> for CommandID {
             20 => Ok(CommandID::UserRemove),
             21 => Ok(CommandID::ClientID),
             22 => Ok(CommandID::Shutdown),
+            23 => Ok(CommandID::LPUSHUNIQUE),
+            24 => Ok(CommandID::DRAIN),
+            25 => Ok(CommandID::JSONGET),
+            26 => Ok(CommandID::JSONSET),
+            27 => Ok(CommandID::COMPARESET),
+            28 => Ok(CommandID::SlowLogGet),
+            29 => Ok(CommandID::SlowLogReset),
+            30 => Ok(CommandID::HKEYSMATCH),
+            31 => Ok(CommandID::KEYINFO),
+            32 => Ok(CommandID::HGETSORTED),
+            33 => Ok(CommandID::CommandList),
+            34 => Ok(CommandID::MULTIHSET),
+            35 => Ok(CommandID::LGET),
+            36 => Ok(CommandID::HDUMP),
+            37 => Ok(CommandID::HRESTORE),
+            38 => Ok(CommandID::LROTATE),
+            39 => Ok(CommandID::MEMORYUSAGE),
+            40 => Ok(CommandID::ConnStats),
+            41 => Ok(CommandID::MULTILOCK),
+            42 => Ok(CommandID::WATCH),
+            43 => Ok(CommandID::UNWATCH),
+            44 => Ok(CommandID::Time),
+            45 => Ok(CommandID::TAG),
+            46 => Ok(CommandID::UNTAG),
+            47 => Ok(CommandID::KEYSBYTAG),
+            48 => Ok(CommandID::Compact),
+            49 => Ok(CommandID::HFILTER),
+            50 => Ok(CommandID::HSWAP),
+            51 => Ok(CommandID::LINTER),
+            52 => Ok(CommandID::LDIFF),
+            53 => Ok(CommandID::GETORSET),
+            54 => Ok(CommandID::HTOP),
+            55 => Ok(CommandID::ReKey),
+            56 => Ok(CommandID::LMULTILEN),
+            57 => Ok(CommandID::PIPELINE),
+            58 => Ok(CommandID::DECRDEL),
+            59 => Ok(CommandID::DebugCheck),
+            60 => Ok(CommandID::SessionCommands),
+            61 => Ok(CommandID::RecentlyModified),
+            62 => Ok(CommandID::Features),
+            63 => Ok(CommandID::Role),
+            64 => Ok(CommandID::PauseWrites),
+            65 => Ok(CommandID::ResumeWrites),
+            66 => Ok(CommandID::Digest),
+            67 => Ok(CommandID::AclExport),
+            68 => Ok(CommandID::AclImport),
+            69 => Ok(CommandID::APPENDIF),
+            70 => Ok(CommandID::KEYSOFTYPE),
+            71 => Ok(CommandID::PROTECT),
+            72 => Ok(CommandID::UNPROTECT),
+            73 => Ok(CommandID::LREPLACE),
+            74 => Ok(CommandID::LPOPTOLIST),
+            75 => Ok(CommandID::KEYPAGE),
+            76 => Ok(CommandID::SETWITHVERSION),
+            77 => Ok(CommandID::SecurityInfo),
+            78 => Ok(CommandID::LLEN),
+            79 => Ok(CommandID::LINDEX),
+            80 => Ok(CommandID::LMOVE),
+            81 => Ok(CommandID::LPOP),
+            82 => Ok(CommandID::LPOS),
+            83 => Ok(CommandID::LPUSH),
+            84 => Ok(CommandID::LPUSHX),
+            85 => Ok(CommandID::LRANGE),
+            86 => Ok(CommandID::LREM),
+            87 => Ok(CommandID::LSET),
+            88 => Ok(CommandID::LTRIM),
+            89 => Ok(CommandID::RPOP),
+            90 => Ok(CommandID::RPUSH),
+            91 => Ok(CommandID::RPUSHX),
+            92 => Ok(CommandID::SIZEHISTOGRAM),
+            93 => Ok(CommandID::CloseIdle),
+            94 => Ok(CommandID::Expire),
+            95 => Ok(CommandID::Ttl),
+            96 => Ok(CommandID::Persist),
+            97 => Ok(CommandID::RENAMETYPED),
+            98 => Ok(CommandID::Exists),
+            99 => Ok(CommandID::Type),
             _ => Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid command id")),
         }
     }
@@ -116,6 +362,116 @@ pub fn str_to_command_id(value: String) -> Result<CommandID, std::io::Error> {
         "UserRemove" => Ok(CommandID::UserRemove),
         "ClientID" => Ok(CommandID::ClientID),
         "Shutdown" => Ok(CommandID::Shutdown),
+        "LPUSHUNIQUE" => Ok(CommandID::LPUSHUNIQUE),
+        "DRAIN" => Ok(CommandID::DRAIN),
+        "JSONGET" => Ok(CommandID::JSONGET),
+        "JSONSET" => Ok(CommandID::JSONSET),
+        "COMPARESET" => Ok(CommandID::COMPARESET),
+        "SlowLogGet" => Ok(CommandID::SlowLogGet),
+        "SlowLogReset" => Ok(CommandID::SlowLogReset),
+        "HKEYSMATCH" => Ok(CommandID::HKEYSMATCH),
+        "KEYINFO" => Ok(CommandID::KEYINFO),
+        "HGETSORTED" => Ok(CommandID::HGETSORTED),
+        "CommandList" => Ok(CommandID::CommandList),
+        "MULTIHSET" => Ok(CommandID::MULTIHSET),
+        "LGET" => Ok(CommandID::LGET),
+        "HDUMP" => Ok(CommandID::HDUMP),
+        "HRESTORE" => Ok(CommandID::HRESTORE),
+        "LROTATE" => Ok(CommandID::LROTATE),
+        "MEMORYUSAGE" => Ok(CommandID::MEMORYUSAGE),
+        "ConnStats" => Ok(CommandID::ConnStats),
+        "MULTILOCK" => Ok(CommandID::MULTILOCK),
+        "WATCH" => Ok(CommandID::WATCH),
+        "UNWATCH" => Ok(CommandID::UNWATCH),
+        "Time" => Ok(CommandID::Time),
+        "TAG" => Ok(CommandID::TAG),
+        "UNTAG" => Ok(CommandID::UNTAG),
+        "KEYSBYTAG" => Ok(CommandID::KEYSBYTAG),
+        "Compact" => Ok(CommandID::Compact),
+        "HFILTER" => Ok(CommandID::HFILTER),
+        "HSWAP" => Ok(CommandID::HSWAP),
+        "LINTER" => Ok(CommandID::LINTER),
+        "LDIFF" => Ok(CommandID::LDIFF),
+        "GETORSET" => Ok(CommandID::GETORSET),
+        "HTOP" => Ok(CommandID::HTOP),
+        "ReKey" => Ok(CommandID::ReKey),
+        "LMULTILEN" => Ok(CommandID::LMULTILEN),
+        "PIPELINE" => Ok(CommandID::PIPELINE),
+        "DECRDEL" => Ok(CommandID::DECRDEL),
+        "DebugCheck" => Ok(CommandID::DebugCheck),
+        "SessionCommands" => Ok(CommandID::SessionCommands),
+        "RecentlyModified" => Ok(CommandID::RecentlyModified),
+        "Features" => Ok(CommandID::Features),
+        "Role" => Ok(CommandID::Role),
+        "PauseWrites" => Ok(CommandID::PauseWrites),
+        "ResumeWrites" => Ok(CommandID::ResumeWrites),
+        "Digest" => Ok(CommandID::Digest),
+        "ACLExport" => Ok(CommandID::AclExport),
+        "ACLImport" => Ok(CommandID::AclImport),
+        "APPENDIF" => Ok(CommandID::APPENDIF),
+        "KEYSOFTYPE" => Ok(CommandID::KEYSOFTYPE),
+        "PROTECT" => Ok(CommandID::PROTECT),
+        "UNPROTECT" => Ok(CommandID::UNPROTECT),
+        "LREPLACE" => Ok(CommandID::LREPLACE),
+        "LPOPTOLIST" => Ok(CommandID::LPOPTOLIST),
+        "KEYPAGE" => Ok(CommandID::KEYPAGE),
+        "SETWITHVERSION" => Ok(CommandID::SETWITHVERSION),
+        "SecurityInfo" => Ok(CommandID::SecurityInfo),
+        "LLEN" => Ok(CommandID::LLEN),
+        "LINDEX" => Ok(CommandID::LINDEX),
+        "LMOVE" => Ok(CommandID::LMOVE),
+        "LPOP" => Ok(CommandID::LPOP),
+        "LPOS" => Ok(CommandID::LPOS),
+        "LPUSH" => Ok(CommandID::LPUSH),
+        "LPUSHX" => Ok(CommandID::LPUSHX),
+        "LRANGE" => Ok(CommandID::LRANGE),
+        "LREM" => Ok(CommandID::LREM),
+        "LSET" => Ok(CommandID::LSET),
+        "LTRIM" => Ok(CommandID::LTRIM),
+        "RPOP" => Ok(CommandID::RPOP),
+        "RPUSH" => Ok(CommandID::RPUSH),
+        "RPUSHX" => Ok(CommandID::RPUSHX),
+        "SIZEHISTOGRAM" => Ok(CommandID::SIZEHISTOGRAM),
+        "CloseIdle" => Ok(CommandID::CloseIdle),
+        "EXPIRE" => Ok(CommandID::Expire),
+        "TTL" => Ok(CommandID::Ttl),
+        "PERSIST" => Ok(CommandID::Persist),
+        "RENAMETYPED" => Ok(CommandID::RENAMETYPED),
+        "EXISTS" => Ok(CommandID::Exists),
+        "TYPE" => Ok(CommandID::Type),
         _ => Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid command id"))
     }
-}
\ No newline at end of file
+}
+
+/// Alternative names for commands, resolved next to the canonical names of str_to_command_id
+#[derive(Debug, Default, Clone)]
+pub struct CommandAliases {
+    aliases: HashMap<String, CommandID>,
+}
+
+impl CommandAliases {
+    /// Maps each alias to the canonical command name it stands for. Fails if an alias is a command name itself,
+    /// or points to something that isn't a command name. So aliases of aliases, and with them cycles, are rejected
+    pub fn new(aliases: &BTreeMap<String, String>) -> Result<Self, std::io::Error> {
+        let mut resolved = HashMap::new();
+        for (alias, target) in aliases {
+            if str_to_command_id(alias.clone()).is_ok() {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Alias {} is already the name of a command", alias)));
+            }
+            let command = str_to_command_id(target.clone()).map_err(|_| {
+                let reason = if aliases.contains_key(target) { "an alias, aliases have to point to a command" } else { "not a command" };
+                std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Alias {} points to {}, which is {}", alias, target, reason))
+            })?;
+            resolved.insert(alias.clone(), command);
+        }
+        Ok(Self { aliases: resolved })
+    }
+
+    /// Resolves an alias or a canonical command name
+    pub fn resolve(&self, name: &str) -> Result<CommandID, std::io::Error> {
+        match self.aliases.get(name) {
+            Some(command) => Ok(*command),
+            None => str_to_command_id(name.to_string()),
+        }
+    }
+}
@@ -1,6 +1,8 @@
 use bson::Bson;
 use serde::{Deserialize, Serialize};
+use crate::acl::AclExport;
 use crate::command::CommandID;
+use crate::message::Command;
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct AclSetCommandInput {
@@ -57,10 +59,59 @@ impl TryFrom<Bson> for DeleteCommandInput {
     }
 }
 
+/// Normalization applied to a value before it's stored
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transform {
+    Trim,
+    Lowercase,
+    Uppercase,
+}
+
+impl Transform {
+    pub fn apply(self, value: String) -> String {
+        match self {
+            Transform::Trim => value.trim().to_string(),
+            Transform::Lowercase => value.to_lowercase(),
+            Transform::Uppercase => value.to_uppercase(),
+        }
+    }
+}
+
+/// Condition on a value, written as `{ "op": "prefix", "value": "abc" }`
+/// gt and lt compare numerically, values that aren't numbers never match them
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "op", content = "value", rename_all = "lowercase")]
+pub enum Predicate {
+    Eq(String),
+    Ne(String),
+    Prefix(String),
+    Suffix(String),
+    Contains(String),
+    Gt(f64),
+    Lt(f64),
+}
+
+impl Predicate {
+    pub fn matches(&self, value: &str) -> bool {
+        match self {
+            Predicate::Eq(expected) => value == expected,
+            Predicate::Ne(expected) => value != expected,
+            Predicate::Prefix(prefix) => value.starts_with(prefix.as_str()),
+            Predicate::Suffix(suffix) => value.ends_with(suffix.as_str()),
+            Predicate::Contains(part) => value.contains(part.as_str()),
+            Predicate::Gt(bound) => value.trim().parse::<f64>().is_ok_and(|value| value > *bound),
+            Predicate::Lt(bound) => value.trim().parse::<f64>().is_ok_and(|value| value < *bound),
+        }
+    }
+}
+
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 pub struct SetCommandInput {
     pub key: String,
     pub value: String,
+    /// Stored as is if not set
+    pub transform: Option<Transform>,
 }
 
 impl TryFrom<Bson> for SetCommandInput {
@@ -88,6 +139,9 @@ impl TryFrom<Bson> for LoginCommandInput {
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 pub struct KeyExchangeCommandInput {
     pub pub_key: String,
+    /// Asks for the flag byte and CRC32 checksum on every frame after the key exchange response
+    #[serde(default)]
+    pub checksum: bool,
 }
 
 impl TryFrom<Bson> for KeyExchangeCommandInput {
@@ -482,3 +536,749 @@ impl TryFrom<Bson> for RPushxCommandInput {
         bson::from_bson(bson)
     }
 }
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct LPushUniqueCommandInput {
+    pub list: String,
+    pub values: Vec<String>,
+    /// When set the list is trimmed to this many elements after pushing
+    pub max_len: Option<usize>,
+}
+
+impl TryFrom<Bson> for LPushUniqueCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct DrainCommandInput {
+    pub list: String,
+}
+
+impl TryFrom<Bson> for DrainCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct JsonGetCommandInput {
+    pub key: String,
+    /// Dotted path into the document, e.g. `user.addresses.0.city`. An empty path addresses the whole document
+    pub path: String,
+}
+
+impl TryFrom<Bson> for JsonGetCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct JsonSetCommandInput {
+    pub key: String,
+    /// Dotted path into the document, e.g. `user.addresses.0.city`. An empty path addresses the whole document
+    pub path: String,
+    /// The new value as JSON text
+    pub value: String,
+}
+
+impl TryFrom<Bson> for JsonSetCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct CompareSetCommandInput {
+    pub key: String,
+    /// The value the key currently needs to have. When omitted the key must be absent for the set to happen
+    pub expected: Option<String>,
+    pub value: String,
+}
+
+impl TryFrom<Bson> for CompareSetCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct SlowLogGetCommandInput {
+    /// How many of the newest entries to return. Returns all entries if not set
+    pub count: Option<usize>,
+}
+
+impl TryFrom<Bson> for SlowLogGetCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct HKeysMatchCommandInput {
+    pub key: String,
+    /// Glob pattern the field names have to match, e.g. `user:*`
+    pub pattern: String,
+}
+
+impl TryFrom<Bson> for HKeysMatchCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct KeyInfoCommandInput {
+    pub keys: Vec<String>,
+}
+
+impl TryFrom<Bson> for KeyInfoCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct HGetSortedCommandInput {
+    pub key: String,
+    /// How many fields to skip, after sorting
+    pub offset: Option<usize>,
+    /// Returns all remaining fields if not set
+    pub count: Option<usize>,
+    /// Sorts from z to a instead of a to z
+    #[serde(default)]
+    pub descending: bool,
+}
+
+impl TryFrom<Bson> for HGetSortedCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct MultiHSetCommandInput {
+    /// Hash key -> fields to set in that hash
+    pub hashes: std::collections::HashMap<String, std::collections::HashMap<String, String>>,
+}
+
+impl TryFrom<Bson> for MultiHSetCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct LGetCommandInput {
+    pub list: String,
+    /// Negative indices count from the end of the list, -1 is the last element
+    pub index: isize,
+}
+
+impl TryFrom<Bson> for LGetCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct HDumpCommandInput {
+    pub key: String,
+}
+
+impl TryFrom<Bson> for HDumpCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct HRestoreCommandInput {
+    pub key: String,
+    /// The document returned by HDUMP
+    pub value: std::collections::HashMap<String, String>,
+    /// Overwrites whatever is stored at the key, unless it is protected, a user or strict_types forbids it. Otherwise the fields are merged into an existing hash and any other type fails
+    #[serde(default)]
+    pub replace: bool,
+}
+
+impl TryFrom<Bson> for HRestoreCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct LRotateCommandInput {
+    pub list: String,
+    /// Moves the head to the tail if true, the tail to the head otherwise. Defaults to true
+    pub left_to_right: Option<bool>,
+}
+
+impl TryFrom<Bson> for LRotateCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct MemoryUsageCommandInput {
+    pub key: String,
+}
+
+impl TryFrom<Bson> for MemoryUsageCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct MultiLockCommandInput {
+    /// Acquired together or not at all. A key counts as held if it exists, whatever it contains
+    pub keys: Vec<String>,
+    /// Stored as the value of every acquired key, usually something identifying the owner
+    pub token: String,
+}
+
+impl TryFrom<Bson> for MultiLockCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct WatchKeyCommandInput {
+    pub key: String,
+}
+
+impl TryFrom<Bson> for WatchKeyCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct UnwatchKeyCommandInput {
+    pub key: String,
+}
+
+impl TryFrom<Bson> for UnwatchKeyCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct TagCommandInput {
+    pub key: String,
+    pub tags: Vec<String>,
+}
+
+impl TryFrom<Bson> for TagCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct UntagCommandInput {
+    pub key: String,
+    pub tags: Vec<String>,
+}
+
+impl TryFrom<Bson> for UntagCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct KeysByTagCommandInput {
+    pub tag: String,
+}
+
+impl TryFrom<Bson> for KeysByTagCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct HFilterCommandInput {
+    pub key: String,
+    pub predicate: Predicate,
+}
+
+impl TryFrom<Bson> for HFilterCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct HSwapCommandInput {
+    pub key: String,
+    pub field_a: String,
+    pub field_b: String,
+}
+
+impl TryFrom<Bson> for HSwapCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct LInterCommandInput {
+    pub first: String,
+    pub second: String,
+    /// Return every element only once, otherwise duplicates of the first list are kept
+    #[serde(default)]
+    pub dedup: bool,
+}
+
+impl TryFrom<Bson> for LInterCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct LDiffCommandInput {
+    pub first: String,
+    pub second: String,
+}
+
+impl TryFrom<Bson> for LDiffCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct GetOrSetCommandInput {
+    pub key: String,
+    /// Stored and returned if the key doesn't exist yet
+    pub default: String,
+}
+
+impl TryFrom<Bson> for GetOrSetCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct HTopCommandInput {
+    pub key: String,
+    pub count: usize,
+    /// Fail with a TypeError if a value isn't a number, instead of skipping it
+    #[serde(default)]
+    pub strict: bool,
+}
+
+impl TryFrom<Bson> for HTopCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct ReKeyCommandInput {
+    /// The age public key responses should be encrypted with from now on
+    pub pub_key: String,
+    /// The decrypted challenge of the previous ReKey, only needed if the user has a registered key
+    pub challenge: Option<String>,
+}
+
+impl TryFrom<Bson> for ReKeyCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct LMultiLenCommandInput {
+    /// Missing keys and keys holding another type are answered with 0. With strict_types another type fails the command with TypeError
+    pub lists: Vec<String>,
+}
+
+impl TryFrom<Bson> for LMultiLenCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct PipelineCommandInput {
+    /// Executed in order, each one checked against the ACLs on its own. A PIPELINE can't contain another PIPELINE
+    pub commands: Vec<Command>,
+}
+
+impl TryFrom<Bson> for PipelineCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct DecrDelCommandInput {
+    pub key: String,
+    /// Subtracted from the value, defaults to 1
+    pub delta: Option<i64>,
+}
+
+impl TryFrom<Bson> for DecrDelCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct RecentlyModifiedCommandInput {
+    pub count: usize,
+}
+
+impl TryFrom<Bson> for RecentlyModifiedCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct PauseWritesCommandInput {
+    /// Writes resume on their own after this many seconds. The server caps it, and uses the cap if it isn't set
+    pub timeout_secs: Option<u64>,
+}
+
+impl TryFrom<Bson> for PauseWritesCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct AclImportCommandInput {
+    pub acls: AclExport,
+    /// Replace all ACLs with the imported ones, instead of adding them to the existing ones
+    #[serde(default)]
+    pub replace: bool,
+}
+
+impl TryFrom<Bson> for AclImportCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct AppendIfCommandInput {
+    pub key: String,
+    /// Appended unless the value already ends with it
+    pub suffix: String,
+}
+
+impl TryFrom<Bson> for AppendIfCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct KeysOfTypeCommandInput {
+    /// string, hash or list
+    #[serde(rename = "type")]
+    pub type_name: String,
+    /// Glob the keys have to match, every key of the type if not set
+    pub pattern: Option<String>,
+}
+
+impl TryFrom<Bson> for KeysOfTypeCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct ProtectCommandInput {
+    pub key: String,
+}
+
+impl TryFrom<Bson> for ProtectCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct UnprotectCommandInput {
+    pub key: String,
+}
+
+impl TryFrom<Bson> for UnprotectCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct LReplaceCommandInput {
+    pub list: String,
+    /// The new contents, in order. An empty vec deletes the list
+    pub values: Vec<String>,
+}
+
+impl TryFrom<Bson> for LReplaceCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+/// An end of a list. Left is the head, right the tail
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ListEnd {
+    Left,
+    Right,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct LPopToListCommandInput {
+    /// Popped from the end given by from
+    pub source: String,
+    /// Pushed onto the right end, the tail. Created if it doesn't exist
+    pub destination: String,
+    /// The end of source to pop. LPOP pops the right end, because LPUSH appends there too
+    pub from: ListEnd,
+}
+
+impl TryFrom<Bson> for LPopToListCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+/// How KEYPAGE sorts the keys. Size and modified put the largest and the most recently written keys first
+#[derive(Debug, Copy, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyOrder {
+    #[default]
+    Name,
+    Size,
+    Modified,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct KeyPageCommandInput {
+    /// How many keys of the sorted order to skip
+    #[serde(default)]
+    pub offset: usize,
+    pub count: usize,
+    /// Sorted by name if not set
+    #[serde(default)]
+    pub order: KeyOrder,
+    /// string, hash or list. Keys of every type if not set
+    #[serde(rename = "type")]
+    pub type_name: Option<String>,
+}
+
+impl TryFrom<Bson> for KeyPageCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct SetWithVersionCommandInput {
+    pub key: String,
+    /// The version the key currently needs to have, as returned by the last SETWITHVERSION. 0 if the key must not exist
+    pub version: u64,
+    pub value: String,
+}
+
+impl TryFrom<Bson> for SetWithVersionCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct CloseIdleCommandInput {
+    /// Connections that sent nothing for longer than this are closed
+    pub idle_secs: u64,
+}
+
+impl TryFrom<Bson> for CloseIdleCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct ExpireCommandInput {
+    pub key: String,
+    /// Seconds until the key is removed
+    pub ttl_secs: u64,
+}
+
+impl TryFrom<Bson> for ExpireCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct TtlCommandInput {
+    pub key: String,
+}
+
+impl TryFrom<Bson> for TtlCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct PersistCommandInput {
+    pub key: String,
+}
+
+impl TryFrom<Bson> for PersistCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct RenameTypedCommandInput {
+    pub source: String,
+    pub destination: String,
+}
+
+impl TryFrom<Bson> for RenameTypedCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct ExistsCommandInput {
+    pub key: String,
+}
+
+impl TryFrom<Bson> for ExistsCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct TypeCommandInput {
+    pub key: String,
+}
+
+impl TryFrom<Bson> for TypeCommandInput {
+    type Error = bson::de::Error;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        bson::from_bson(bson)
+    }
+}
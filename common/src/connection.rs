@@ -1,16 +1,227 @@
+use std::collections::HashMap;
 use std::io::prelude::{Read, Write};
+use std::time::Duration;
 
 use age::Decryptor;
+use age::stream::StreamReader;
 use age::x25519::{Identity, Recipient};
 use brotli2::CompressParams;
 use brotli2::read::BrotliDecoder;
 use brotli2::write::BrotliEncoder;
+use serde::{Deserialize, Serialize};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
+use tokio::time::Instant;
 use uuid::Uuid;
 
+use crate::acl::{AclDecisionCache, AclVersion};
+use crate::checksum::crc32;
+use crate::command::CommandID;
 use crate::message::Message;
 
+/// Longest line the plaintext protocol accepts, so a peer can't make us buffer forever
+const MAX_LINE_LENGTH: usize = 64 * 1024;
+
+/// Set in the flag byte when a CRC32 of the payload follows the payload
+const FLAG_CHECKSUM: u8 = 0b0000_0001;
+
+/// How much read_message asks the socket and the decompressor for at once
+const DECODE_CHUNK: usize = 64 * 1024;
+
+/// Encrypts buf so only the owner of key can read it
+pub fn encrypt_to(key: &Recipient, buf: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encrypted = Vec::new();
+    let e = age::Encryptor::with_recipients(vec![Box::new(key.clone())]).unwrap();
+    let mut writer = e.wrap_output(&mut encrypted).unwrap();
+    writer.write_all(buf)?;
+    writer.finish()?;
+    Ok(encrypted)
+}
+
+/// Every frame on the wire is the big endian u32 length, followed by the payload. Once both sides agreed on checksums
+/// in the key exchange, a flag byte precedes the payload and, if the checksum flag is set, the big endian CRC32 of the payload follows it
+pub(crate) fn encode_frame(payload: &[u8], checksum: bool) -> Vec<u8> {
+    if !checksum {
+        return payload.to_vec();
+    }
+    let mut frame = Vec::with_capacity(payload.len() + 5);
+    frame.push(FLAG_CHECKSUM);
+    frame.extend_from_slice(payload);
+    frame.extend_from_slice(&crc32(payload).to_be_bytes());
+    frame
+}
+
+/// Returns the payload of the frame. checksum tells whether the frame has a flag byte, see encode_frame.
+/// A checksum mismatch results in InvalidData
+pub(crate) fn decode_frame(frame: &[u8], checksum: bool) -> std::io::Result<&[u8]> {
+    if !checksum {
+        return Ok(frame);
+    }
+    let (flags, rest) = match frame.split_first() {
+        Some(split) => split,
+        None => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Received empty frame")),
+    };
+    if flags & FLAG_CHECKSUM == 0 {
+        return Ok(rest);
+    }
+    if rest.len() < 4 {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Frame is too short to contain a checksum"));
+    }
+    let (payload, checksum) = rest.split_at(rest.len() - 4);
+    let expected = u32::from_be_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]);
+    let actual = crc32(payload);
+    if expected != actual {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Checksum mismatch, expected {:08x} but got {:08x}", expected, actual)));
+    }
+    Ok(payload)
+}
+
+fn is_encrypted(buf: &[u8]) -> bool {
+    buf.starts_with(b"age-encrypt")
+}
+
+/// Reader over the plaintext of an age encrypted buffer, decrypting one chunk at a time as it's read
+fn decrypting_reader<'a>(buf: &'a [u8], key: &Identity) -> std::io::Result<StreamReader<&'a [u8]>> {
+    let dec = match Decryptor::new(buf) {
+        Ok(Decryptor::Recipients(d)) => d,
+        Ok(Decryptor::Passphrase(_)) => {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Received passphrase encrypted message, expected public key encrypted message"));
+        }
+        Err(err) => {
+            let formatted = format!("Error creating decryptor: {}", err);
+            log::error!("{}", formatted);
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, formatted));
+        }
+    };
+    match dec.decrypt(vec![key as &dyn age::Identity].into_iter()) {
+        Ok(reader) => Ok(reader),
+        Err(err) => {
+            let formatted = format!("Error decrypting message: {}", err);
+            log::error!("{}", formatted);
+            Err(std::io::Error::new(std::io::ErrorKind::InvalidData, formatted))
+        }
+    }
+}
+
+/// Decompresses the bson document the reader yields, pulling DECODE_CHUNK bytes at a time
+/// Chained behind decrypting_reader, the decrypted but still compressed message never exists as a whole.
+/// The buffer only grows with the bytes that were actually decompressed, a few compressed bytes can declare gigabytes.
+/// A declared length above max is rejected right away, and no more than the declared length is ever read
+pub(crate) fn read_decompressed<R: Read>(reader: R, max: Option<usize>) -> std::io::Result<Vec<u8>> {
+    let mut decoder = BrotliDecoder::new(reader);
+    let mut length = [0; 4];
+    decoder.read_exact(&mut length)?;
+    let length = u32::from_le_bytes(length) as usize;
+    if let Some(max) = max.filter(|max| length > *max) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Message declares {} bytes, more than the limit of {}", length, max)));
+    }
+    let mut decompressed = Vec::new();
+    decompressed.extend_from_slice(&(length as u32).to_le_bytes());
+    let mut chunk = vec![0; DECODE_CHUNK];
+    loop {
+        let read = decoder.read(&mut chunk)?;
+        if read == 0 {
+            return Ok(decompressed);
+        }
+        if decompressed.len() + read > length {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Message is longer than it declares"));
+        }
+        decompressed.try_reserve(read).map_err(|err| std::io::Error::new(std::io::ErrorKind::OutOfMemory, err))?;
+        decompressed.extend_from_slice(&chunk[..read]);
+    }
+}
+
+/// Machine readable description of the wire format, sent in the KEYEXCHANGE response
+/// so clients that don't share this code can check that they speak the same protocol
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FrameCapabilities {
+    /// Incremented whenever the frame format changes incompatibly
+    pub version: u32,
+    /// Size of the length prefix in front of every frame
+    pub length_prefix_bytes: u8,
+    /// Byte order of the length prefix and the checksum
+    pub byte_order: String,
+    /// Bit in the flag byte that marks a trailing checksum. Frames only have the flag byte if checksum_enabled is true
+    pub checksum_flag: u8,
+    pub checksum: String,
+    /// Whether frames in both directions carry the flag byte and checksum from now on. Only if the client asked for it
+    /// in the key exchange. The key exchange response itself is still sent without
+    pub checksum_enabled: bool,
+    /// The steps a message goes through before it's framed, in the order they're applied
+    pub pipeline: Vec<String>,
+    /// Whether the messages of this connection are encrypted from now on
+    pub encrypted: bool,
+}
+
+impl FrameCapabilities {
+    pub fn current(encrypted: bool, checksum_enabled: bool) -> Self {
+        Self {
+            version: 1,
+            length_prefix_bytes: 4,
+            byte_order: String::from("big-endian"),
+            checksum_flag: FLAG_CHECKSUM,
+            checksum: String::from("crc32"),
+            checksum_enabled,
+            pipeline: vec![String::from("bson"), String::from("brotli"), String::from("age-x25519")],
+            encrypted,
+        }
+    }
+}
+
+/// Counters of a single connection. Byte counts include the length prefix and frame overhead
+#[derive(Debug, Default, Copy, Clone)]
+pub struct ConnectionStats {
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub messages_sent: u64,
+    pub messages_received: u64,
+    /// Bytes passed to compress, and what they were compressed to
+    pub uncompressed_bytes: u64,
+    pub compressed_bytes: u64,
+}
+
+impl ConnectionStats {
+    /// Compressed size relative to the uncompressed size of everything sent, lower is better. 1.0 if nothing was sent yet
+    pub fn compression_ratio(&self) -> f64 {
+        if self.uncompressed_bytes == 0 {
+            return 1.0;
+        }
+        self.compressed_bytes as f64 / self.uncompressed_bytes as f64
+    }
+}
+
+/// Token bucket for outgoing bytes. Holds at most one second worth of bytes, so a quiet connection can burst that much
+#[derive(Debug)]
+struct EgressLimit {
+    bytes_per_sec: u64,
+    /// Goes negative when a write is larger than what's available, the next writes then wait until it's paid back
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl EgressLimit {
+    fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec,
+            tokens: bytes_per_sec as f64,
+            refilled: Instant::now(),
+        }
+    }
+
+    /// How long to wait before sending bytes, to stay within the limit
+    fn take(&mut self, bytes: usize) -> Duration {
+        let now = Instant::now();
+        let rate = self.bytes_per_sec as f64;
+        self.tokens = (self.tokens + now.duration_since(self.refilled).as_secs_f64() * rate).min(rate);
+        self.refilled = now;
+        self.tokens -= bytes as f64;
+        if self.tokens >= 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(-self.tokens / rate)
+    }
+}
+
 pub struct Connection {
     socket: TcpStream,
     is_closed: bool,
@@ -18,6 +229,22 @@ pub struct Connection {
     user: Option<String>,
     pub_key: Option<Recipient>,
     brotli_effort: u8,
+    /// Whether frames in both directions carry the flag byte and checksum, see encode_frame
+    checksum: bool,
+    /// Set by enable_checksum_after_next_message, checksum is switched on once the next message is written
+    checksum_pending: bool,
+    stats: ConnectionStats,
+    /// Received bytes that don't form a full frame or line yet. Only appended to once a socket read completed,
+    /// so read_message and read_line can be cancelled, e.g. in a select, without losing data
+    read_buf: Vec<u8>,
+    /// Set if outgoing bytes are limited, writes wait instead of failing when it's exceeded
+    egress_limit: Option<EgressLimit>,
+    /// How often each command was issued on this connection, whether it was allowed or not
+    command_counts: HashMap<CommandID, u64>,
+    /// Set if the connection checks ACLs against a store, see set_acl_version
+    acl_cache: Option<AclDecisionCache>,
+    /// Received messages that decompress to more bytes than this are rejected. Unlimited if not set
+    max_decompressed_size: Option<usize>,
 }
 
 impl Connection {
@@ -29,41 +256,21 @@ impl Connection {
             user: None,
             pub_key: None,
             brotli_effort,
+            checksum: false,
+            checksum_pending: false,
+            stats: ConnectionStats::default(),
+            read_buf: Vec::new(),
+            egress_limit: None,
+            command_counts: HashMap::new(),
+            acl_cache: None,
+            max_decompressed_size: None,
         }
     }
 
-    /// Decrypts the buffer with the private key of the server, if the first bytes are age-encrypt
+    /// Decrypts the buffer with the private key of the server, if the first bytes are age-encrypt. None if they aren't
     fn decrypt(&self, buf: &[u8], key: &Identity) -> std::io::Result<Option<Vec<u8>>> {
-        let encrypted = match std::str::from_utf8(&buf[..11]) {
-            Ok(header) => {
-                header == "age-encrypt"
-            }
-            Err(_) => false
-        };
-        if encrypted {
-            let dec = match Decryptor::new(&buf[..]) {
-                Ok(dec) => {
-                    match dec {
-                        Decryptor::Recipients(d) => { d }
-                        Decryptor::Passphrase(_) => {
-                            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Received passphrase encrypted message, expected public key encrypted message"));
-                        }
-                    }
-                }
-                Err(err) => {
-                    let formatted = format!("Error creating decryptor: {}", err);
-                    log::error!("{}", formatted);
-                    return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, formatted));
-                }
-            };
-            let mut reader = match dec.decrypt(vec![key as &dyn age::Identity].into_iter()) {
-                Ok(reader) => reader,
-                Err(err) => {
-                    let formatted = format!("Error decrypting message: {}", err);
-                    log::error!("{}", formatted);
-                    return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, formatted));
-                }
-            };
+        if is_encrypted(buf) {
+            let mut reader = decrypting_reader(buf, key)?;
             let mut decrypted = Vec::new();
             match reader.read_to_end(&mut decrypted) {
                 Ok(_) => {}
@@ -75,19 +282,14 @@ impl Connection {
             }
             return Ok(Some(decrypted));
         }
-        // Return buffer unmodified if it's not encrypted, ensures compatibility with unencrypted messages
-        return Ok(Some(buf.to_vec()));
+        // Not encrypted, the caller keeps using the buffer unmodified. Ensures compatibility with unencrypted messages
+        return Ok(None);
     }
 
     fn encrypt(&self, buf: &[u8]) -> std::io::Result<Vec<u8>> {
         return match self.pub_key.as_ref() {
             Some(key) => {
-                let mut encrypted = Vec::new();
-                let e = age::Encryptor::with_recipients(vec![Box::new(key.clone())]).unwrap();
-                let mut writer = e.wrap_output(&mut encrypted).unwrap();
-                writer.write_all(buf)?;
-                writer.finish()?;
-                Ok(encrypted)
+                encrypt_to(key, buf)
             }
             None => {
                 Ok(buf.to_vec())
@@ -95,87 +297,151 @@ impl Connection {
         };
     }
 
-    fn compress(&self, buf: &[u8]) -> std::io::Result<Vec<u8>> {
+    fn compress(&mut self, buf: &[u8]) -> std::io::Result<Vec<u8>> {
         let mut params = CompressParams::new();
         params.quality(self.brotli_effort as u32);
         let mut e = BrotliEncoder::from_params(Vec::new(), &params);
         e.write_all(buf)?;
         let compressed_buf = e.finish()?;
+        self.stats.uncompressed_bytes += buf.len() as u64;
+        self.stats.compressed_bytes += compressed_buf.len() as u64;
         return Ok(compressed_buf);
     }
 
     fn decompress(&self, buf: &[u8]) -> std::io::Result<Vec<u8>> {
-        let mut d = BrotliDecoder::new(&buf[..]);
+        let d = BrotliDecoder::new(&buf[..]);
         let mut decompressed_buf = Vec::new();
-        d.read_to_end(&mut decompressed_buf)?;
+        // One byte more than allowed is enough to tell that it's too large
+        let limit = self.max_decompressed_size.map_or(u64::MAX, |max| max as u64 + 1);
+        d.take(limit).read_to_end(&mut decompressed_buf)?;
+        if let Some(max) = self.max_decompressed_size.filter(|max| decompressed_buf.len() > *max) {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Message decompresses to more than the limit of {} bytes", max)));
+        }
         return Ok(decompressed_buf);
     }
 
-    /// Read -> decompress -> decrypt
+    /// Read -> decrypt -> decompress, the reverse of write
     pub async fn read(&mut self, key: &Identity) -> std::io::Result<(Vec<u8>, bool)> {
         let size = self.socket.read_u32().await?;
         let mut buf = vec![0; size as usize];
-        return match self.socket.read(&mut buf).await {
-            Ok(read) => {
-                if read == 0 {
-                    return Err(std::io::Error::from(std::io::ErrorKind::ConnectionAborted));
-                }
-                buf.truncate(read);
-                log::trace!("Read {} bytes from socket, decompressing", read);
-                let decompressed_buf = self.decompress(&buf)?;
-                log::trace!("Decompressed {} bytes, decrypting", decompressed_buf.len());
-                match self.decrypt(&decompressed_buf, key)? {
-                    Some(decrypted) => {
-                        log::trace!("Decrypted {} bytes", decrypted.len());
-                        Ok((decrypted, true))
-                    }
-                    None => {
-                        log::trace!("No public key present, returning decompressed buffer");
-                        Ok((decompressed_buf, false))
-                    }
-                }
+        // A single read returns whatever arrived so far, larger frames need several
+        self.socket.read_exact(&mut buf).await?;
+        self.stats.bytes_received += 4 + buf.len() as u64;
+        self.stats.messages_received += 1;
+        log::trace!("Read {} bytes from socket, decrypting", buf.len());
+        let payload = decode_frame(&buf, self.checksum)?;
+        match self.decrypt(payload, key)? {
+            Some(decrypted) => {
+                log::trace!("Decrypted {} bytes, decompressing", decrypted.len());
+                Ok((self.decompress(&decrypted)?, true))
             }
-            Err(err) => {
-                Err(err)
+            None => {
+                log::trace!("Not encrypted, decompressing");
+                Ok((self.decompress(payload)?, false))
             }
-        };
+        }
     }
 
-    /// Encrypt -> compress -> write
+    /// Compress -> encrypt -> write, in the order of FrameCapabilities::pipeline like send_message
     pub async fn write(&mut self, buf: &[u8]) -> std::io::Result<()> {
-        let maybe_encrypted = self.encrypt(&buf)?;
-        let compressed_buf = self.compress(&maybe_encrypted)?;
-        let len_bytes = (compressed_buf.len() as u32).to_be_bytes();
-        self.socket.write_all(&len_bytes).await?;
+        let compressed_buf = self.compress(buf)?;
         // Maybe encrypt because we might not have a public key. And thus need to send unencrypted
-        return self.socket.write_all(&compressed_buf).await;
+        let maybe_encrypted = self.encrypt(&compressed_buf)?;
+        let frame = encode_frame(&maybe_encrypted, self.checksum);
+        let len_bytes = (frame.len() as u32).to_be_bytes();
+        self.throttle(4 + frame.len()).await;
+        self.socket.write_all(&len_bytes).await?;
+        self.socket.write_all(&frame).await?;
+        self.stats.bytes_sent += 4 + frame.len() as u64;
+        self.stats.messages_sent += 1;
+        self.apply_pending_checksum();
+        Ok(())
     }
 
     pub async fn send_message(&mut self, msg: &Message) -> std::io::Result<()> {
         let msg = msg.to_vec().unwrap();
         let msg = self.compress(&msg).unwrap();
         let msg = self.encrypt(&msg).unwrap();
+        let msg = encode_frame(&msg, self.checksum);
         let msg_size_bytes = (msg.len() as u32).to_be_bytes();
         log::trace!("Sending message of size {}bytes", msg.len());
+        self.throttle(4 + msg.len()).await;
         self.socket.write_all(&msg_size_bytes).await?;
-        self.socket.write_all(&*msg).await
+        self.socket.write_all(&*msg).await?;
+        self.stats.bytes_sent += 4 + msg.len() as u64;
+        self.stats.messages_sent += 1;
+        self.apply_pending_checksum();
+        Ok(())
+    }
+
+    /// Reads one line of the plaintext protocol without the line ending. None if the peer closed the connection
+    pub async fn read_line(&mut self) -> std::io::Result<Option<String>> {
+        loop {
+            if let Some(pos) = self.read_buf.iter().position(|b| *b == b'\n') {
+                let mut line: Vec<u8> = self.read_buf.drain(..=pos).collect();
+                self.stats.bytes_received += line.len() as u64;
+                self.stats.messages_received += 1;
+                line.pop();
+                // nc and telnet may send \r\n
+                if line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+                return String::from_utf8(line).map(Some).map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err));
+            }
+            if self.read_buf.len() > MAX_LINE_LENGTH {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Line too long"));
+            }
+            if self.socket.read_buf(&mut self.read_buf).await? == 0 {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Writes one line of the plaintext protocol, the line ending is added
+    pub async fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        self.throttle(line.len() + 1).await;
+        self.socket.write_all(line.as_bytes()).await?;
+        self.socket.write_all(b"\n").await?;
+        self.stats.bytes_sent += line.len() as u64 + 1;
+        self.stats.messages_sent += 1;
+        Ok(())
     }
 
     // Boolean flag indicates that the message was encrypted
     pub async fn read_message(&mut self, key: &Identity) -> std::io::Result<(Message, bool)> {
-        let mut len_bytes = [0u8; 4];
-        self.socket.read_exact(&mut len_bytes).await?;
-        let msg_size = u32::from_be_bytes(len_bytes); // Convert from big endian
-
-        log::trace!("Reading message of size {}bytes", msg_size);
-        let mut buf = vec![0; msg_size as usize];
-        self.socket.read_exact(&mut buf).await?;
-        let before = buf.len();
-        let buf = self.decrypt(&buf, key)?.unwrap();
-        let after = buf.len();
-        let buf = self.decompress(&buf)?;
-        log::trace!("Decompressed message from {} to {} bytes", before, buf.len());
-        return Ok((Message::from_slice(&buf).unwrap(), before != after));
+        let frame = loop {
+            if self.read_buf.len() >= 4 {
+                let msg_size = u32::from_be_bytes([self.read_buf[0], self.read_buf[1], self.read_buf[2], self.read_buf[3]]) as usize; // Convert from big endian
+                let needed = 4 + msg_size;
+                if self.read_buf.len() >= needed {
+                    log::trace!("Reading message of size {}bytes", msg_size);
+                    // Hand the buffer over instead of copying the frame out of it, only what belongs to the next frame is copied
+                    let rest = self.read_buf.split_off(needed);
+                    let mut frame = std::mem::replace(&mut self.read_buf, rest);
+                    frame.drain(..4);
+                    break frame;
+                }
+                // Grow with what actually arrives instead of trusting the declared size, at most doubling at a time
+                let missing = needed - self.read_buf.len();
+                self.read_buf.reserve_exact(missing.min(self.read_buf.len().max(DECODE_CHUNK)));
+            }
+            if self.socket.read_buf(&mut self.read_buf).await? == 0 {
+                return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Connection closed"));
+            }
+        };
+        self.stats.bytes_received += 4 + frame.len() as u64;
+        self.stats.messages_received += 1;
+        let payload = decode_frame(&frame, self.checksum)?;
+        let encrypted = is_encrypted(payload);
+        let buf = if encrypted {
+            read_decompressed(decrypting_reader(payload, key)?, self.max_decompressed_size)?
+        } else {
+            read_decompressed(payload, self.max_decompressed_size)?
+        };
+        log::trace!("Decompressed message from {} to {} bytes", payload.len(), buf.len());
+        // Not needed anymore while the message is parsed
+        drop(frame);
+        return Ok((Message::from_slice(&buf).unwrap(), encrypted));
     }
 
     /// Important. Does not actually close the connection, just sets a flag closed flag
@@ -197,12 +463,89 @@ impl Connection {
 
     pub fn set_user(&mut self, user: String) {
         self.user = Some(user);
+        if let Some(cache) = self.acl_cache.as_mut() {
+            cache.clear();
+        }
+    }
+
+    /// Enables caching ACL decisions, until the version changes or another user logs in
+    pub fn set_acl_version(&mut self, version: AclVersion) {
+        self.acl_cache = Some(AclDecisionCache::new(version));
+    }
+
+    pub fn cached_acl_decision(&self, command: CommandID) -> Option<bool> {
+        self.acl_cache.as_ref().and_then(|cache| cache.get(command))
+    }
+
+    pub fn cache_acl_decision(&mut self, version: u64, command: CommandID, allowed: bool) {
+        if let Some(cache) = self.acl_cache.as_mut() {
+            cache.insert(version, command, allowed);
+        }
     }
 
     pub fn set_pub_key(&mut self, key: Recipient) {
         self.pub_key = Some(key);
     }
 
+    /// Limits how many bytes per second are written to this connection. None removes the limit
+    pub fn set_egress_limit(&mut self, bytes_per_sec: Option<u64>) {
+        self.egress_limit = bytes_per_sec.filter(|rate| *rate > 0).map(EgressLimit::new);
+    }
+
+    /// Limits how large received messages may get once decompressed. None removes the limit
+    pub fn set_max_decompressed_size(&mut self, max: Option<usize>) {
+        self.max_decompressed_size = max;
+    }
+
+    /// Waits until bytes can be sent without exceeding the egress limit
+    async fn throttle(&mut self, bytes: usize) {
+        if let Some(limit) = self.egress_limit.as_mut() {
+            let wait = limit.take(bytes);
+            if !wait.is_zero() {
+                log::trace!("Egress limit reached, waiting {:?} before sending {} bytes", wait, bytes);
+                tokio::time::sleep(wait).await;
+            }
+        }
+    }
+
+    /// Enables or disables the flag byte and checksum on frames in both directions. Both sides have to agree, see FrameCapabilities
+    pub fn set_checksum(&mut self, checksum: bool) {
+        self.checksum = checksum;
+    }
+
+    /// Enables the checksum once the next message was written, so the response that agrees to it is still readable without
+    pub fn enable_checksum_after_next_message(&mut self) {
+        self.checksum_pending = true;
+    }
+
+    fn apply_pending_checksum(&mut self) {
+        if self.checksum_pending {
+            self.checksum_pending = false;
+            self.checksum = true;
+        }
+    }
+
+    pub fn is_checksum_enabled(&self) -> bool {
+        self.checksum
+    }
+
+    pub fn get_stats(&self) -> ConnectionStats {
+        self.stats
+    }
+
+    pub fn record_command(&mut self, command: CommandID) {
+        *self.command_counts.entry(command).or_default() += 1;
+    }
+
+    pub fn get_command_counts(&self) -> HashMap<CommandID, u64> {
+        self.command_counts.clone()
+    }
+
+    /// Whether a key exchange happened on this connection, so responses are encrypted
+    pub fn is_key_exchanged(&self) -> bool {
+        self.pub_key.is_some()
+    }
+
     /// Returns a clone of the public key, if it exists
     pub fn get_pub_key(&self) -> Option<Recipient> {
         self.pub_key.clone()
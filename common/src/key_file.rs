@@ -0,0 +1,34 @@
+use std::fs::{create_dir_all, OpenOptions};
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+
+/// Writes a private key, only readable and writable by the owner on unix. Missing parent directories are created
+pub fn write_key_file(path: &Path, key: &str) -> std::io::Result<()> {
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        create_dir_all(parent)?;
+    }
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(path)?;
+    // The mode only applies to newly created files, so an existing file gets tightened as well
+    #[cfg(unix)]
+    file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    file.write_all(key.as_bytes())
+}
+
+/// Logs a warning if the key file can be accessed by anyone other than its owner
+pub fn warn_if_permissive(path: &Path) {
+    #[cfg(unix)]
+    if let Ok(metadata) = path.metadata() {
+        let mode = metadata.permissions().mode() & 0o777;
+        if mode & 0o077 != 0 {
+            log::warn!("Key file {} has permissions {:o}, it should only be accessible by its owner (600)", path.display(), mode);
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+}
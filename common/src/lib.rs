@@ -5,6 +5,9 @@ pub mod message;
 pub mod acl;
 pub mod connection;
 pub mod command_input;
+pub mod checksum;
+pub mod key_file;
+pub mod plaintext;
 
 pub fn init_env_logger() {
     env_logger::Builder::from_env(env_logger::Env::default())
@@ -24,4 +27,492 @@ pub fn init_env_logger() {
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use std::collections::BTreeMap;
+    use std::io::Write;
+
+    use crate::acl::{AclDecisionCache, ACL};
+    use crate::checksum::crc32;
+    use crate::command::{CommandAliases, CommandID};
+    use crate::command_input::{HFilterCommandInput, KeyOrder, KeyPageCommandInput, PipelineCommandInput, Predicate, SetCommandInput, Transform};
+    use crate::connection::{Connection, FrameCapabilities, decode_frame, encode_frame, read_decompressed};
+    use crate::key_file::write_key_file;
+    use crate::message::{Command, Message, MessageContent, MessageResponse, OperationStatus};
+    use crate::plaintext::{format_response, parse_line};
+
+    /// Both ends of a TCP connection over loopback, the accepted one first
+    async fn socket_pair() -> (tokio::net::TcpStream, tokio::net::TcpStream) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = tokio::net::TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        (server, client)
+    }
+
+    /// A server and a client Connection talking to each other, both compressing with brotli_effort
+    async fn connected_pair(brotli_effort: u8) -> (Connection, Connection) {
+        let (server, client) = socket_pair().await;
+        (Connection::new(server, uuid::Uuid::new_v4(), brotli_effort), Connection::new(client, uuid::Uuid::new_v4(), brotli_effort))
+    }
+
+    #[test]
+    fn crc32_matches_reference_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn checksum_detects_corrupted_frame() {
+        let mut frame = encode_frame(b"some payload", true);
+        assert_eq!(decode_frame(&frame, true).unwrap(), b"some payload");
+        frame[3] ^= 0x01;
+        let err = decode_frame(&frame, true).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn frame_without_checksum_has_no_flag_byte() {
+        let frame = encode_frame(b"some payload", false);
+        assert_eq!(frame, b"some payload");
+        assert_eq!(decode_frame(&frame, false).unwrap(), b"some payload");
+    }
+
+    #[tokio::test]
+    async fn checksum_is_enabled_after_the_agreeing_response() {
+        let (mut server, mut client) = connected_pair(6).await;
+        let key = age::x25519::Identity::generate();
+        let response = |status| Message::new_response(uuid::Uuid::new_v4(), MessageResponse { content: None, status });
+
+        // Like KEYEXCHANGE, the response agreeing to checksums is still sent without
+        server.enable_checksum_after_next_message();
+        assert!(!server.is_checksum_enabled());
+        server.send_message(&response(OperationStatus::Success)).await.unwrap();
+        assert!(server.is_checksum_enabled());
+        client.read_message(&key).await.unwrap();
+        client.set_checksum(true);
+
+        client.send_message(&response(OperationStatus::NotFound)).await.unwrap();
+        let (received, _) = server.read_message(&key).await.unwrap();
+        assert_eq!(received.content, MessageContent::Response(MessageResponse { content: None, status: OperationStatus::NotFound }));
+        server.send_message(&response(OperationStatus::Failure)).await.unwrap();
+        let (received, _) = client.read_message(&key).await.unwrap();
+        assert_eq!(received.content, MessageContent::Response(MessageResponse { content: None, status: OperationStatus::Failure }));
+    }
+
+    #[test]
+    fn anonymous_acls_apply_to_users_without_login() {
+        let mut acl = ACL::default();
+        acl.add_anonymous(CommandID::Get);
+        assert!(acl.is_allowed("", CommandID::Get));
+        assert!(!acl.is_allowed("", CommandID::Set));
+        assert!(!acl.is_allowed("user", CommandID::Get));
+    }
+
+    #[test]
+    fn disabled_command_is_rejected_for_allowed_user() {
+        let mut acl = ACL::default();
+        acl.add("admin", CommandID::Shutdown);
+        acl.add("admin", CommandID::Get);
+        acl.disable(CommandID::Shutdown);
+        assert!(!acl.is_allowed("admin", CommandID::Shutdown));
+        assert!(acl.is_allowed("admin", CommandID::Get));
+    }
+
+    #[test]
+    fn alias_resolves_to_canonical_command() {
+        let aliases = CommandAliases::new(&BTreeMap::from([("stop-7f3a".to_string(), "Shutdown".to_string())])).unwrap();
+        assert_eq!(aliases.resolve("stop-7f3a").unwrap(), CommandID::Shutdown);
+        assert_eq!(aliases.resolve("GET").unwrap(), CommandID::Get);
+        assert!(aliases.resolve("stop").is_err());
+
+        // Shadowing a command and aliases of aliases are rejected
+        assert!(CommandAliases::new(&BTreeMap::from([("GET".to_string(), "SET".to_string())])).is_err());
+        let chained = BTreeMap::from([("a".to_string(), "b".to_string()), ("b".to_string(), "a".to_string())]);
+        assert!(CommandAliases::new(&chained).is_err());
+    }
+
+    #[tokio::test]
+    async fn shutdown_notification_is_received() {
+        let (mut server, mut client) = connected_pair(6).await;
+
+        let notification = Message::new_response(uuid::Uuid::new_v4(), MessageResponse {
+            content: None,
+            status: OperationStatus::ServerShutdown,
+        });
+        server.send_message(&notification).await.unwrap();
+        let (received, _) = client.read_message(&age::x25519::Identity::generate()).await.unwrap();
+        match received.content {
+            MessageContent::Response(rsp) => assert_eq!(rsp.status, OperationStatus::ServerShutdown),
+            MessageContent::Command(_) => panic!("Expected a response"),
+        }
+    }
+
+    #[tokio::test]
+    async fn connection_stats_count_sent_bytes() {
+        let (mut server, mut client) = connected_pair(6).await;
+        assert_eq!(client.get_stats().bytes_sent, 0);
+
+        let command = Message::new_command(uuid::Uuid::new_v4(), crate::message::Command {
+            command_id: CommandID::Get,
+            payload: bson::Bson::String("key".to_string()),
+        });
+        client.send_message(&command).await.unwrap();
+        server.read_message(&age::x25519::Identity::generate()).await.unwrap();
+
+        let sent = client.get_stats();
+        let received = server.get_stats();
+        assert!(sent.bytes_sent > 0);
+        assert_eq!(sent.messages_sent, 1);
+        assert_eq!(received.bytes_received, sent.bytes_sent);
+        assert_eq!(received.messages_received, 1);
+        assert!(sent.uncompressed_bytes > 0);
+    }
+
+    #[test]
+    fn acl_decisions_are_cached_until_the_acls_change() {
+        let mut acl = ACL::default();
+        acl.add("user", CommandID::Get);
+        let mut cache = AclDecisionCache::new(acl.version());
+        assert_eq!(cache.get(CommandID::Get), None);
+
+        cache.insert(acl.version().get(), CommandID::Get, acl.is_allowed("user", CommandID::Get));
+        cache.insert(acl.version().get(), CommandID::Set, acl.is_allowed("user", CommandID::Set));
+        assert_eq!(cache.get(CommandID::Get), Some(true));
+        assert_eq!(cache.get(CommandID::Set), Some(false));
+
+        acl.add("user", CommandID::Set);
+        assert_eq!(cache.get(CommandID::Get), None);
+        assert_eq!(cache.get(CommandID::Set), None);
+        cache.insert(acl.version().get(), CommandID::Set, acl.is_allowed("user", CommandID::Set));
+        assert_eq!(cache.get(CommandID::Set), Some(true));
+
+        cache.clear();
+        assert_eq!(cache.get(CommandID::Set), None);
+    }
+
+    #[test]
+    fn imported_acls_make_the_same_decisions() {
+        let mut source = ACL::default();
+        source.add("alice", CommandID::Get);
+        source.add("alice", CommandID::Set);
+        source.add("bob", CommandID::HGET);
+        source.add_anonymous(CommandID::Get);
+        source.disable(CommandID::Shutdown);
+
+        let exported = bson::to_bson(&source.export()).unwrap();
+        let mut target = ACL::default();
+        target.add("carol", CommandID::Delete);
+        target.import(bson::from_bson(exported).unwrap(), true);
+
+        assert_eq!(target.export(), source.export());
+        for user in ["alice", "bob", "carol", ""] {
+            for command in [CommandID::Get, CommandID::Set, CommandID::HGET, CommandID::Delete, CommandID::Shutdown] {
+                assert_eq!(target.is_allowed(user, command), source.is_allowed(user, command), "{} {}", user, command);
+            }
+        }
+
+        let mut merged = ACL::default();
+        merged.add("carol", CommandID::Delete);
+        merged.import(source.export(), false);
+        assert!(merged.is_allowed("carol", CommandID::Delete));
+        assert!(merged.is_allowed("alice", CommandID::Set));
+    }
+
+    #[test]
+    fn only_keyspace_changes_are_writes() {
+        for command in [CommandID::Set, CommandID::Delete, CommandID::HSET, CommandID::DRAIN, CommandID::DECRDEL, CommandID::LPOPTOLIST, CommandID::SETWITHVERSION, CommandID::Expire, CommandID::Persist] {
+            assert!(command.is_write(), "{} should be a write", command);
+        }
+        for command in [CommandID::Get, CommandID::HGETALL, CommandID::LGET, CommandID::AclSet, CommandID::PauseWrites, CommandID::Ttl] {
+            assert!(!command.is_write(), "{} shouldn't be a write", command);
+        }
+    }
+
+    #[tokio::test]
+    async fn connection_counts_issued_commands() {
+        let (server, _client) = socket_pair().await;
+        let mut connection = Connection::new(server, uuid::Uuid::new_v4(), 6);
+        assert!(connection.get_command_counts().is_empty());
+
+        for command in [CommandID::Get, CommandID::Set, CommandID::Get, CommandID::SessionCommands] {
+            connection.record_command(command);
+        }
+
+        let counts = connection.get_command_counts();
+        assert_eq!(counts.len(), 3);
+        assert_eq!(counts[&CommandID::Get], 2);
+        assert_eq!(counts[&CommandID::Set], 1);
+        assert_eq!(counts[&CommandID::SessionCommands], 1);
+    }
+
+    #[tokio::test]
+    async fn plaintext_lines_are_parsed_into_commands() {
+        let (server, mut client) = socket_pair().await;
+        let mut server = Connection::new(server, uuid::Uuid::new_v4(), 0);
+
+        tokio::io::AsyncWriteExt::write_all(&mut client, b"set greeting hello world\r\nGET greeting\nPING\n").await.unwrap();
+        let set = parse_line(&server.read_line().await.unwrap().unwrap(), &CommandAliases::default()).unwrap();
+        assert_eq!(set.command_id, CommandID::Set);
+        let input: SetCommandInput = set.payload.try_into().unwrap();
+        assert_eq!((input.key.as_str(), input.value.as_str()), ("greeting", "hello world"));
+        let get = parse_line(&server.read_line().await.unwrap().unwrap(), &CommandAliases::default()).unwrap();
+        assert_eq!(get.command_id, CommandID::Get);
+        assert!(parse_line(&server.read_line().await.unwrap().unwrap(), &CommandAliases::default()).is_err());
+        assert!(parse_line("GET", &CommandAliases::default()).is_err());
+
+        server.write_line(&format_response(&MessageResponse { content: Some(bson::Bson::String("a\nb".to_string())), status: OperationStatus::Success })).await.unwrap();
+        server.write_line(&format_response(&MessageResponse { content: None, status: OperationStatus::NotAllowed })).await.unwrap();
+        drop(server);
+        let mut received = String::new();
+        tokio::io::AsyncReadExt::read_to_string(&mut client, &mut received).await.unwrap();
+        assert_eq!(received, "a\\nb\nERR NotAllowed\n");
+    }
+
+    #[tokio::test]
+    async fn cancelled_read_keeps_partial_frame() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // Capture the bytes of a real frame
+        let (mut capture, sender) = socket_pair().await;
+        let mut sender = Connection::new(sender, uuid::Uuid::new_v4(), 6);
+        let message = Message::new_response(uuid::Uuid::new_v4(), MessageResponse { content: None, status: OperationStatus::Success });
+        sender.send_message(&message).await.unwrap();
+        drop(sender);
+        let mut frame = Vec::new();
+        capture.read_to_end(&mut frame).await.unwrap();
+
+        let (server, mut client) = socket_pair().await;
+        let mut server = Connection::new(server, uuid::Uuid::new_v4(), 6);
+        let key = age::x25519::Identity::generate();
+        let (first, second) = frame.split_at(frame.len() / 2);
+        client.write_all(first).await.unwrap();
+        let cancelled = tokio::time::timeout(std::time::Duration::from_millis(50), server.read_message(&key)).await;
+        assert!(cancelled.is_err());
+        client.write_all(second).await.unwrap();
+        let (received, _) = server.read_message(&key).await.unwrap();
+        assert_eq!(received, message);
+    }
+
+    #[tokio::test]
+    async fn write_and_send_message_apply_the_advertised_pipeline() {
+        let (mut server, mut client) = connected_pair(6).await;
+        let key = age::x25519::Identity::generate();
+        client.set_pub_key(key.to_public());
+        server.set_pub_key(key.to_public());
+        let message = Message::new_response(uuid::Uuid::new_v4(), MessageResponse { content: None, status: OperationStatus::Success });
+        assert_eq!(FrameCapabilities::current(true, false).pipeline, vec!["bson", "brotli", "age-x25519"]);
+
+        // Each path reads what the other one wrote, so both compress first and encrypt second
+        client.write(&message.to_vec().unwrap()).await.unwrap();
+        let (received, encrypted) = server.read_message(&key).await.unwrap();
+        assert!(encrypted);
+        assert_eq!(received, message);
+
+        server.send_message(&message).await.unwrap();
+        let (received, encrypted) = client.read(&key).await.unwrap();
+        assert!(encrypted);
+        assert_eq!(received, message.to_vec().unwrap());
+    }
+
+    #[tokio::test]
+    async fn responses_use_the_swapped_key() {
+        let (mut server, mut client) = connected_pair(6).await;
+        let old_key = age::x25519::Identity::generate();
+        let new_key = age::x25519::Identity::generate();
+        let message = Message::new_response(uuid::Uuid::new_v4(), MessageResponse { content: None, status: OperationStatus::Success });
+
+        server.set_pub_key(old_key.to_public());
+        server.send_message(&message).await.unwrap();
+        let (_, encrypted) = client.read_message(&old_key).await.unwrap();
+        assert!(encrypted);
+
+        server.set_pub_key(new_key.to_public());
+        server.send_message(&message).await.unwrap();
+        server.send_message(&message).await.unwrap();
+        assert_eq!(client.read_message(&new_key).await.unwrap().0, message);
+        assert!(client.read_message(&old_key).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn large_message_is_read_in_chunks() {
+        let (mut server, mut client) = connected_pair(1).await;
+        let key = age::x25519::Identity::generate();
+        server.set_pub_key(key.to_public());
+        // Incompressible enough to span many socket reads and several age chunks
+        let mut x: u64 = 1;
+        let value: String = (0..4 * 1024 * 1024).map(|_| {
+            x = x.wrapping_mul(6364136223846793005).wrapping_add(1);
+            (b'a' + (x >> 59) as u8 % 26) as char
+        }).collect();
+        let message = Message::new_response(uuid::Uuid::new_v4(), MessageResponse { content: Some(bson::Bson::String(value)), status: OperationStatus::Success });
+
+        let sent = message.clone();
+        let sender = tokio::spawn(async move { server.send_message(&sent).await.unwrap(); server });
+        let (received, encrypted) = client.read_message(&key).await.unwrap();
+        sender.await.unwrap();
+        assert!(encrypted);
+        assert_eq!(received, message);
+    }
+
+    #[tokio::test]
+    async fn large_write_is_read_completely() {
+        let (mut server, mut client) = connected_pair(1).await;
+        // Incompressible, so the frame stays larger than what a single socket read returns
+        let mut x: u64 = 7;
+        let value: Vec<u8> = (0..200 * 1024).map(|_| {
+            x = x.wrapping_mul(6364136223846793005).wrapping_add(1);
+            (x >> 56) as u8
+        }).collect();
+
+        let sent = value.clone();
+        let sender = tokio::spawn(async move { client.write(&sent).await.unwrap(); client });
+        let key = age::x25519::Identity::generate();
+        let (received, encrypted) = server.read(&key).await.unwrap();
+        sender.await.unwrap();
+        assert!(!encrypted);
+        assert_eq!(received, value);
+    }
+
+    #[tokio::test]
+    async fn decompression_bomb_is_rejected() {
+        let (mut server, mut client) = connected_pair(6).await;
+        server.set_max_decompressed_size(Some(1024 * 1024));
+        // Compresses to a few kilobytes
+        let value = "a".repeat(16 * 1024 * 1024);
+        let message = Message::new_response(uuid::Uuid::new_v4(), MessageResponse { content: Some(bson::Bson::String(value)), status: OperationStatus::Success });
+
+        let sender = tokio::spawn(async move { client.send_message(&message).await.unwrap(); client });
+        let key = age::x25519::Identity::generate();
+        let err = server.read_message(&key).await.unwrap_err();
+        sender.await.unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn declared_length_is_not_reserved_up_front() {
+        // A header declaring 1GiB, followed by a few bytes
+        let mut plain = (1u32 << 30).to_le_bytes().to_vec();
+        plain.extend_from_slice(b"short");
+        let mut encoder = brotli2::write::BrotliEncoder::new(Vec::new(), 6);
+        encoder.write_all(&plain).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let decompressed = read_decompressed(&compressed[..], None).unwrap();
+        assert_eq!(decompressed, plain);
+        assert!(decompressed.capacity() < 1024 * 1024, "reserved {} bytes", decompressed.capacity());
+        assert_eq!(read_decompressed(&compressed[..], Some(1024)).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn egress_limit_paces_large_responses() {
+        let (mut server, mut client) = connected_pair(1).await;
+        server.set_egress_limit(Some(1_000_000));
+        // Random bytes, so compression doesn't shrink them below the limit
+        let mut x: u64 = 1;
+        let bytes: Vec<u8> = (0..1_000_000).map(|_| {
+            x = x.wrapping_mul(6364136223846793005).wrapping_add(1);
+            (x >> 56) as u8
+        }).collect();
+        let message = Message::new_response(uuid::Uuid::new_v4(), MessageResponse {
+            content: Some(bson::Bson::Binary(bson::Binary { subtype: bson::spec::BinarySubtype::Generic, bytes })),
+            status: OperationStatus::Success,
+        });
+
+        let start = std::time::Instant::now();
+        let sent = message.clone();
+        let sender = tokio::spawn(async move {
+            // The first one is covered by the burst, the second has to wait for it to be paid back
+            server.send_message(&sent).await.unwrap();
+            server.send_message(&sent).await.unwrap();
+            server
+        });
+        let key = age::x25519::Identity::generate();
+        assert_eq!(client.read_message(&key).await.unwrap().0, message);
+        assert_eq!(client.read_message(&key).await.unwrap().0, message);
+        sender.await.unwrap();
+        let elapsed = start.elapsed();
+        assert!(elapsed >= std::time::Duration::from_millis(900), "took {:?}", elapsed);
+        assert!(elapsed < std::time::Duration::from_secs(5), "took {:?}", elapsed);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn key_file_is_only_accessible_by_owner() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("in-mem-key-{}.age", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "old").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        write_key_file(&path, "AGE-SECRET-KEY-TEST").unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode() & 0o777;
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(mode, 0o600);
+    }
+
+    #[test]
+    fn capabilities_document_parses() {
+        let document = bson::to_bson(&FrameCapabilities::current(true, false)).unwrap();
+        let parsed: FrameCapabilities = bson::from_bson(document.clone()).unwrap();
+        assert_eq!(parsed, FrameCapabilities::current(true, false));
+        let document = document.as_document().unwrap();
+        assert_eq!(document.get_str("byte_order").unwrap(), "big-endian");
+        assert_eq!(document.get_i32("length_prefix_bytes").unwrap(), 4);
+    }
+
+    #[test]
+    fn set_transforms() {
+        assert_eq!(Transform::Trim.apply(String::from("  Value \n")), "Value");
+        assert_eq!(Transform::Lowercase.apply(String::from("VaLuE")), "value");
+        assert_eq!(Transform::Uppercase.apply(String::from("VaLuE")), "VALUE");
+
+        let input = SetCommandInput::try_from(bson::bson!({ "key": "k", "value": "v", "transform": "uppercase" })).unwrap();
+        assert_eq!(input.transform, Some(Transform::Uppercase));
+        let input = SetCommandInput::try_from(bson::bson!({ "key": "k", "value": "v" })).unwrap();
+        assert_eq!(input.transform, None);
+    }
+
+    #[test]
+    fn key_page_defaults_to_the_first_page_by_name() {
+        let input = KeyPageCommandInput::try_from(bson::bson!({ "count": 20 })).unwrap();
+        assert_eq!((input.offset, input.count, input.order, input.type_name), (0, 20, KeyOrder::Name, None));
+        let input = KeyPageCommandInput::try_from(bson::bson!({ "offset": 40, "count": 20, "order": "modified", "type": "list" })).unwrap();
+        assert_eq!((input.offset, input.order, input.type_name.as_deref()), (40, KeyOrder::Modified, Some("list")));
+        assert!(KeyPageCommandInput::try_from(bson::bson!({ "count": 20, "order": "age" })).is_err());
+    }
+
+    #[test]
+    fn filter_predicates() {
+        assert!(Predicate::Eq(String::from("a")).matches("a"));
+        assert!(!Predicate::Eq(String::from("a")).matches("ab"));
+        assert!(Predicate::Ne(String::from("a")).matches("ab"));
+        assert!(!Predicate::Ne(String::from("a")).matches("a"));
+        assert!(Predicate::Prefix(String::from("user:")).matches("user:1"));
+        assert!(!Predicate::Prefix(String::from("user:")).matches("admin:1"));
+        assert!(Predicate::Suffix(String::from(".png")).matches("cat.png"));
+        assert!(!Predicate::Suffix(String::from(".png")).matches("cat.jpg"));
+        assert!(Predicate::Contains(String::from("ell")).matches("hello"));
+        assert!(!Predicate::Contains(String::from("ell")).matches("help"));
+        assert!(Predicate::Gt(10.0).matches("10.5"));
+        assert!(!Predicate::Gt(10.0).matches("10"));
+        assert!(!Predicate::Gt(10.0).matches("eleven"));
+        assert!(Predicate::Lt(0.0).matches("-3"));
+        assert!(!Predicate::Lt(0.0).matches("3"));
+
+        let input = HFilterCommandInput::try_from(bson::bson!({ "key": "k", "predicate": { "op": "gt", "value": 5 } })).unwrap();
+        assert_eq!(input.predicate, Predicate::Gt(5.0));
+    }
+
+    #[test]
+    fn pipeline_keeps_sub_commands_in_order() {
+        let pipeline = bson::bson!({ "commands": [
+            { "command_id": "LGET", "payload": { "list": "feed", "index": 0 } },
+            { "command_id": "HGETALL", "payload": { "key": "meta" } },
+        ] });
+        let message = Message::new_command(uuid::Uuid::new_v4(), Command { command_id: CommandID::PIPELINE, payload: pipeline });
+        let message = Message::from_slice(&message.to_vec().unwrap()).unwrap();
+        let MessageContent::Command(cmd) = message.content else { panic!("expected a command") };
+
+        let input = PipelineCommandInput::try_from(cmd.payload).unwrap();
+        let ids = input.commands.iter().map(|cmd| cmd.command_id).collect::<Vec<CommandID>>();
+        assert_eq!(ids, vec![CommandID::LGET, CommandID::HGETALL]);
+        assert_eq!(input.commands[1].payload, bson::bson!({ "key": "meta" }));
+    }
+}
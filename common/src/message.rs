@@ -20,6 +20,13 @@ pub enum OperationStatus {
     OutOfMemory,
     /// Happens when you try to access a string as a number
     TypeError,
+    /// Sent unsolicited right before the server closes the connection because it's shutting down
+    ServerShutdown,
+    /// Sent unsolicited to connections watching a key whenever it's written. The content is a document with the key and its value
+    KeyChanged,
+    /// Sent unsolicited as the first message of a connection if the server has a banner configured
+    /// The content is a document with the banner message, the server and protocol version and the node id
+    Banner,
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -0,0 +1,50 @@
+//! A line based protocol for poking at the server with nc or telnet. One command per line, one response per line.
+//! There is no encryption and no login, so everything runs as an anonymous connection
+use bson::Bson;
+
+use crate::command::{CommandAliases, CommandID};
+use crate::command_input::{DeleteCommandInput, GetCommandInput, SetCommandInput};
+use crate::message::{Command, MessageResponse, OperationStatus};
+
+fn invalid(msg: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, msg)
+}
+
+/// Parses `GET key`, `SET key value` and `DELETE key`. The command name is case insensitive, aliases are matched exactly
+/// and everything after the key of a SET is the value, spaces included
+pub fn parse_line(line: &str, aliases: &CommandAliases) -> std::io::Result<Command> {
+    let line = line.trim_start();
+    let (name, rest) = line.split_once(' ').unwrap_or((line, ""));
+    let rest = rest.trim_start();
+    let (key, value) = rest.split_once(' ').unwrap_or((rest, ""));
+    let command_id = aliases.resolve(name).or_else(|_| aliases.resolve(&name.to_ascii_uppercase()));
+    if !matches!(command_id, Ok(CommandID::Get | CommandID::Set | CommandID::Delete)) {
+        return Err(invalid(format!("Unsupported command: {}", name)));
+    }
+    if key.is_empty() {
+        return Err(invalid(format!("{} needs a key", name)));
+    }
+    let (command_id, payload) = match command_id? {
+        CommandID::Set => {
+            if value.is_empty() {
+                return Err(invalid(format!("{} needs a value", name)));
+            }
+            (CommandID::Set, bson::to_bson(&SetCommandInput { key: key.to_string(), value: value.to_string(), transform: None }))
+        }
+        CommandID::Delete => (CommandID::Delete, bson::to_bson(&DeleteCommandInput { key: key.to_string() })),
+        _ => (CommandID::Get, bson::to_bson(&GetCommandInput { key: key.to_string(), default: None })),
+    };
+    let payload = payload.map_err(|err| invalid(err.to_string()))?;
+    Ok(Command { command_id, payload })
+}
+
+/// Renders a response as a single line. Successful responses are their content or `OK`, everything else `ERR <status>`
+pub fn format_response(response: &MessageResponse) -> String {
+    match (&response.status, &response.content) {
+        (OperationStatus::Success, None) => "OK".to_string(),
+        // Escaped so a stored newline can't be mistaken for the end of the response
+        (OperationStatus::Success, Some(Bson::String(value))) => value.replace('\\', "\\\\").replace('\n', "\\n"),
+        (OperationStatus::Success, Some(content)) => content.to_string(),
+        (status, _) => format!("ERR {:?}", status),
+    }
+}
@@ -1,18 +1,22 @@
+use std::collections::HashSet;
 use std::io::Read;
 use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::time::Duration;
 
 use age::secrecy::ExposeSecret;
-use age::x25519::Identity;
+use age::x25519::{Identity, Recipient};
 use clap::Parser;
 use tokio::net::TcpStream;
 use uuid::Uuid;
-use bson;
+use bson::Bson;
 
 use common::init_env_logger;
-use common::command::CommandID;
+use common::key_file::{warn_if_permissive, write_key_file};
+use common::command::{CommandID, str_to_command_id};
 use common::message::Command;
-use common::connection::Connection;
-use common::message::Message;
+use common::connection::{Connection, FrameCapabilities};
+use common::message::{Message, MessageContent, MessageResponse, OperationStatus};
 use common::command_input;
 
 #[derive(Parser, Debug)]
@@ -24,26 +28,133 @@ struct CLI {
     /// The port to bind to
     #[arg(default_value = "3000", env = "PORT", help = "The port to connect to")]
     port: u16,
+    /// Whether to append a checksum to every message
+    #[arg(long, env = "CHECKSUM", help = "Ask the server in the key exchange to append a CRC32 checksum to every message in both directions")]
+    checksum: bool,
+    /// Whether to reconnect when the connection drops
+    #[arg(long, env = "RECONNECT", help = "Reconnect with exponential backoff when the connection drops, repeating the key exchange and login")]
+    reconnect: bool,
+    /// The user to log in as
+    #[arg(long, env = "IN_MEM_USER", help = "Log in as this user after the key exchange")]
+    user: Option<String>,
+    /// The password of the user
+    #[arg(long, env = "IN_MEM_PASSWORD", requires = "user", help = "The password of --user")]
+    password: Option<String>,
 }
 
+/// Initial wait before reconnecting, doubled for every failed attempt
+const RECONNECT_BACKOFF_BASE: Duration = Duration::from_millis(100);
+/// Upper bound for the wait before reconnecting
+const RECONNECT_BACKOFF_MAX: Duration = Duration::from_secs(30);
 
-#[tokio::main]
-async fn main() {
-    init_env_logger();
 
-    let args = CLI::parse();
-    log::trace!("Connecting to {}:{}", args.host, args.port);
-    let socket = match TcpStream::connect(SocketAddr::new(args.host, args.port)).await {
-        Ok(socket) => socket,
-        Err(err) => {
-            log::error!("Error connecting to {}:{}: {}", args.host, args.port, err);
-            return;
+/// Asks the server which commands are registered. Sending anything else would make the server drop the connection
+async fn fetch_supported_commands(connection: &mut Connection, private_key: &Identity) -> std::io::Result<HashSet<CommandID>> {
+    let cmd = Command { command_id: CommandID::CommandList, payload: Bson::Null };
+    connection.send_message(&Message::new_command(Uuid::new_v4(), cmd)).await?;
+    let content = match connection.read_message(private_key).await? {
+        (Message { content: MessageContent::Response(rsp), .. }, _) if rsp.status == OperationStatus::Success => rsp.content,
+        _ => None,
+    };
+    let supported_commands = match content {
+        Some(Bson::Array(commands)) => {
+            commands.into_iter().filter_map(|command| match command {
+                Bson::String(name) => str_to_command_id(name).ok(),
+                _ => None
+            }).collect()
+        }
+        _ => {
+            log::warn!("Server didn't list its supported commands");
+            HashSet::new()
         }
     };
+    Ok(supported_commands)
+}
+
+fn heartbeat_message() -> Message {
+    let cmd = Command {
+        command_id: CommandID::Heartbeat,
+        payload: bson::Bson::Null,
+    };
+    Message::new_command(Uuid::new_v4(), cmd)
+}
+
+/// Connects and runs the key exchange, the login if a user is given, a first heartbeat and fetches the supported commands,
+/// so the connection is ready for commands. Returns the connection with the commands the server supports
+async fn connect(args: &CLI, private_key: &Identity, server_public_key: &Recipient) -> std::io::Result<(Connection, HashSet<CommandID>)> {
+    log::trace!("Connecting to {}:{}", args.host, args.port);
+    let socket = TcpStream::connect(SocketAddr::new(args.host, args.port)).await?;
     let mut connection = Connection::new(socket, Uuid::new_v4(), 6);
+    connection.set_pub_key(server_public_key.clone());
     log::info!("Connected to {}:{}", args.host, args.port);
+    let kex_msg = {
+        let payload = bson::to_bson(&command_input::KeyExchangeCommandInput { pub_key: private_key.to_public().to_string(), checksum: args.checksum }).unwrap();
+        let cmd = Command { command_id: CommandID::KEYEXCHANGE, payload };
+        Message::new_command(Uuid::new_v4(), cmd)
+    };
+    log::debug!("Sending key exchange message");
+    connection.send_message(&kex_msg).await?;
+    // A banner is the very first message, so it can only come before the key exchange response
+    let mut kex_rsp = connection.read_message(private_key).await?.0;
+    if let MessageContent::Response(rsp) = &kex_rsp.content {
+        if rsp.status == OperationStatus::Banner {
+            log::info!("Server banner: {}", rsp.content.clone().unwrap_or(Bson::Null));
+            kex_rsp = connection.read_message(private_key).await?.0;
+        }
+    }
+    // The server only switches to checksummed frames after its response, and only if it agreed
+    if let MessageContent::Response(MessageResponse { content: Some(content), .. }) = kex_rsp.content {
+        match bson::from_bson::<FrameCapabilities>(content) {
+            Ok(capabilities) => connection.set_checksum(capabilities.checksum_enabled),
+            Err(err) => log::warn!("Server didn't describe its frame format: {}", err),
+        }
+    }
+    if args.checksum && !connection.is_checksum_enabled() {
+        log::warn!("Server didn't agree to checksums, frames are sent without");
+    }
+    if let Some(user) = &args.user {
+        log::debug!("Logging in as {}", user);
+        let payload = bson::to_bson(&command_input::LoginCommandInput { user: user.clone(), password: args.password.clone().unwrap_or_default() }).unwrap();
+        let cmd = Command { command_id: CommandID::Login, payload };
+        connection.send_message(&Message::new_command(Uuid::new_v4(), cmd)).await?;
+        match connection.read_message(private_key).await?.0.content {
+            MessageContent::Response(rsp) if rsp.status == OperationStatus::Success => log::info!("Logged in as {}", user),
+            _ => return Err(std::io::Error::new(std::io::ErrorKind::PermissionDenied, format!("Login as {} failed", user))),
+        }
+    }
+    log::debug!("Sending first heartbeat message");
+    connection.send_message(&heartbeat_message()).await?;
+    connection.read_message(private_key).await?;
+    let supported_commands = fetch_supported_commands(&mut connection, private_key).await?;
+    Ok((connection, supported_commands))
+}
+
+/// Connects again, waiting twice as long after every failed attempt. Only returns once it succeeded
+///
+/// Only idempotent requests are retried. connect repeats the key exchange, the login and the command list on the new
+/// connection, since the server keeps no state of the old one, and the main loop sends the heartbeat that failed again.
+/// Commands typed by the user are never resent, a write may have been applied before the connection dropped
+async fn reconnect(args: &CLI, private_key: &Identity, server_public_key: &Recipient) -> (Connection, HashSet<CommandID>) {
+    let mut backoff = RECONNECT_BACKOFF_BASE;
+    loop {
+        log::info!("Reconnecting in {:?}", backoff);
+        tokio::time::sleep(backoff).await;
+        match connect(args, private_key, server_public_key).await {
+            Ok(connected) => return connected,
+            Err(err) => log::warn!("Error reconnecting to {}:{}: {}", args.host, args.port, err),
+        }
+        backoff = (backoff * 2).min(RECONNECT_BACKOFF_MAX);
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    init_env_logger();
+
+    let args = CLI::parse();
     let private_key = match std::fs::File::open("identity-client.age") {
         Ok(mut file) => {
+            warn_if_permissive(Path::new("identity-client.age"));
             let mut buf = Vec::new();
             match file.read_to_end(&mut buf) {
                 Ok(_) => {}
@@ -57,7 +168,7 @@ async fn main() {
         Err(err) => {
             log::warn!("No identity file found or not readable. Generating new identity file: {}", err);
             let key = Identity::generate();
-            match std::fs::write("identity-client.age", key.to_string().expose_secret()) {
+            match write_key_file(Path::new("identity-client.age"), key.to_string().expose_secret()) {
                 Ok(_) => {}
                 Err(err) => {
                     log::error!("Error writing identity file: {}", err);
@@ -84,52 +195,34 @@ async fn main() {
             std::process::exit(-1);
         }
     };
-    let public_key = private_key.to_public();
-    log::info!("Public key: \"{}\"", public_key);
-    connection.set_pub_key(server_public_key);
-    let heartbeat_message = {
-        let cmd = Command {
-            command_id: CommandID::Heartbeat,
-            payload: bson::Bson::Null,
-        };
-        Message::new_command(Uuid::new_v4(), cmd)
-    };
-    let kex_msg = {
-        let payload = bson::to_bson(&command_input::KeyExchangeCommandInput { pub_key: public_key.clone().to_string() }).unwrap();
-        let cmd = Command { command_id: CommandID::KEYEXCHANGE, payload };
-        Message::new_command(Uuid::new_v4(), cmd)
-    };
-    log::debug!("Sending key exchange message");
-    match connection.send_message(&kex_msg).await {
-        Ok(_) => {}
+    log::info!("Public key: \"{}\"", private_key.to_public());
+    let (mut connection, mut supported_commands) = match connect(&args, &private_key, &server_public_key).await {
+        Ok(connected) => connected,
         Err(err) => {
-            log::error!("Error sending heartbeat message size: {}", err);
+            log::error!("Error connecting to {}:{}: {}", args.host, args.port, err);
             std::process::exit(-1);
         }
-    }
-    log::debug!("Sending first heartbeat message");
-    match connection.send_message(&heartbeat_message).await {
-        Ok(_) => {
-            if connection.read_message(&private_key).await.is_err() {
-                log::error!("Error reading heartbeat response");
-                std::process::exit(-1);
-            }
-        }
-        Err(_) => {
-            log::error!("Connection shut down");
-        }
-    }
+    };
     loop {
         log::debug!("Sending heartbeat message");
-        match connection.send_message(&heartbeat_message).await {
-            Ok(_) => {
-                if connection.read_message(&private_key).await.is_err() {
-                    log::error!("Error reading heartbeat response");
-                    std::process::exit(-1);
-                }
+        let heartbeat = match connection.send_message(&heartbeat_message()).await {
+            Ok(_) => connection.read_message(&private_key).await,
+            Err(err) => Err(err),
+        };
+        match heartbeat {
+            Ok((Message { content: MessageContent::Response(rsp), .. }, _)) if rsp.status == OperationStatus::ServerShutdown => {
+                log::info!("Server is shutting down");
+                std::process::exit(0);
             }
-            Err(_) => {
-                log::error!("Connection shut down");
+            Ok(_) => {}
+            Err(err) if args.reconnect => {
+                log::warn!("Connection lost: {}", err);
+                (connection, supported_commands) = reconnect(&args, &private_key, &server_public_key).await;
+                continue;
+            }
+            Err(err) => {
+                log::error!("Connection shut down: {}", err);
+                std::process::exit(-1);
             }
         }
 
@@ -140,6 +233,19 @@ async fn main() {
         if input.is_empty() {
             continue;
         }
+        let name = input.split_whitespace().next().unwrap_or_default();
+        match str_to_command_id(name.to_string()) {
+            Ok(command) => {
+                if !supported_commands.contains(&command) {
+                    log::error!("The server doesn't support {}", command);
+                    continue;
+                }
+            }
+            Err(_) => {
+                log::error!("Unknown command: {}", name);
+                continue;
+            }
+        }
         /*let cmd = match command::Command::try_from(input) {
             Ok(cmd) => cmd,
             Err(err) => {
@@ -165,3 +271,54 @@ async fn main() {
         log::info!("Response: {}", message);*/
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    /// Answers every command with Success and drops the connection after the command list, for the given number of connections.
+    /// Returns the commands each connection sent
+    async fn serve(listener: TcpListener, server_key: Identity, connections: usize) -> Vec<Vec<CommandID>> {
+        let mut received = Vec::new();
+        for _ in 0..connections {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut connection = Connection::new(socket, Uuid::new_v4(), 6);
+            let mut commands = Vec::new();
+            loop {
+                let (msg, _) = connection.read_message(&server_key).await.unwrap();
+                let MessageContent::Command(cmd) = msg.content else { panic!("expected a command") };
+                commands.push(cmd.command_id);
+                let content = match cmd.command_id {
+                    CommandID::KEYEXCHANGE => Some(bson::to_bson(&FrameCapabilities::current(false, false)).unwrap()),
+                    CommandID::CommandList => Some(Bson::Array(vec![Bson::String(CommandID::Heartbeat.to_string())])),
+                    _ => None,
+                };
+                connection.send_message(&Message::new_response(msg.id, MessageResponse { content, status: OperationStatus::Success })).await.unwrap();
+                if cmd.command_id == CommandID::CommandList {
+                    break;
+                }
+            }
+            received.push(commands);
+        }
+        received
+    }
+
+    #[tokio::test]
+    async fn reconnect_replays_login_and_refetches_commands() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port().to_string();
+        let server_key = Identity::generate();
+        let server = tokio::spawn(serve(listener, server_key.clone(), 2));
+        let args = CLI::parse_from(["in-mem-client", "127.0.0.1", &port, "--reconnect", "--user", "alice", "--password", "secret"]);
+        let client_key = Identity::generate();
+
+        let (_connection, supported_commands) = connect(&args, &client_key, &server_key.to_public()).await.unwrap();
+        assert!(supported_commands.contains(&CommandID::Heartbeat));
+        let (_connection, supported_commands) = reconnect(&args, &client_key, &server_key.to_public()).await;
+        assert!(supported_commands.contains(&CommandID::Heartbeat));
+
+        let setup = vec![CommandID::KEYEXCHANGE, CommandID::Login, CommandID::Heartbeat, CommandID::CommandList];
+        assert_eq!(server.await.unwrap(), vec![setup.clone(), setup]);
+    }
+}
@@ -0,0 +1,68 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::time::Instant;
+use uuid::Uuid;
+
+/// Signals the server sends to a worker loop from outside of the connection
+#[derive(Debug, Clone)]
+pub enum ClientSignal {
+    /// The server is shutting down. The worker notifies the client and closes the connection
+    Shutdown,
+    /// A key the connection watches was written. The worker pushes its current value to the client
+    KeyChanged(String),
+    /// The worker closes the connection without notifying the client
+    Close,
+}
+
+#[derive(Debug)]
+struct Client {
+    sender: UnboundedSender<ClientSignal>,
+    /// When the connection last sent a message
+    last_active: Instant,
+}
+
+/// Keeps track of all active connections, so they can be signaled from other tasks
+#[derive(Debug, Default)]
+pub struct ClientRegistry {
+    clients: HashMap<Uuid, Client>,
+}
+
+impl ClientRegistry {
+    /// Registers the connection and returns the receiving end of its signal channel
+    pub fn register(&mut self, id: Uuid) -> UnboundedReceiver<ClientSignal> {
+        let (sender, receiver) = unbounded_channel();
+        self.clients.insert(id, Client { sender, last_active: Instant::now() });
+        receiver
+    }
+
+    /// The sending end of the connection's signal channel, None if it isn't registered
+    pub fn sender(&self, id: &Uuid) -> Option<UnboundedSender<ClientSignal>> {
+        self.clients.get(id).map(|client| client.sender.clone())
+    }
+
+    pub fn unregister(&mut self, id: &Uuid) {
+        self.clients.remove(id);
+    }
+
+    /// Marks the connection as active now
+    pub fn touch(&mut self, id: &Uuid) {
+        if let Some(client) = self.clients.get_mut(id) {
+            client.last_active = Instant::now();
+        }
+    }
+
+    /// Sends the signal to every registered connection, returns how many received it
+    pub fn broadcast(&self, signal: ClientSignal) -> usize {
+        self.clients.values().filter(|client| client.sender.send(signal.clone()).is_ok()).count()
+    }
+
+    /// Closes the connections that sent nothing for longer than idle, except the one with the id except. Returns how many were closed
+    pub fn close_idle(&self, idle: Duration, except: &Uuid) -> usize {
+        self.clients.iter()
+            .filter(|(id, client)| *id != except && client.last_active.elapsed() > idle)
+            .filter(|(_, client)| client.sender.send(ClientSignal::Close).is_ok())
+            .count()
+    }
+}
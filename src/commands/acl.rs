@@ -2,13 +2,17 @@ use std::sync::Arc;
 use async_trait::async_trait;
 use bson::Bson;
 use tokio::sync::RwLock;
-use common::command_input::{AclListCommandInput, AclRemoveCommandInput, AclSetCommandInput};
+use common::command_input::{AclImportCommandInput, AclListCommandInput, AclRemoveCommandInput, AclSetCommandInput};
 use common::connection::Connection;
 use common::message::{Message, MessageResponse, OperationStatus};
 use crate::commands::Command;
+use crate::config::AuthSnapshot;
 use crate::store::{ACLAble, Store};
 
-pub struct AclSetCommand {}
+#[derive(Default)]
+pub struct AclSetCommand {
+    snapshot: Option<AuthSnapshot>,
+}
 
 
 #[async_trait]
@@ -23,6 +27,7 @@ impl Command for AclSetCommand {
         };
 
         store.acl_add(&args.user, args.command);
+        self.snapshot = store.auth_snapshot();
         let rsp = MessageResponse {
             content: None,
             status: OperationStatus::Success,
@@ -30,10 +35,17 @@ impl Command for AclSetCommand {
         Some(rsp)
     }
 
-    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {
+        if let Some(snapshot) = self.snapshot.take() {
+            snapshot.persist().await;
+        }
+    }
 }
 
-pub struct AclRemoveCommand {}
+#[derive(Default)]
+pub struct AclRemoveCommand {
+    snapshot: Option<AuthSnapshot>,
+}
 
 #[async_trait]
 impl Command for AclRemoveCommand {
@@ -47,6 +59,7 @@ impl Command for AclRemoveCommand {
         };
 
         store.acl_remove(&args.user, args.command);
+        self.snapshot = store.auth_snapshot();
         let rsp = MessageResponse {
             content: None,
             status: OperationStatus::Success,
@@ -54,7 +67,11 @@ impl Command for AclRemoveCommand {
         Some(rsp)
     }
 
-    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {
+        if let Some(snapshot) = self.snapshot.take() {
+            snapshot.persist().await;
+        }
+    }
 }
 
 pub struct AclListCommand {}
@@ -80,4 +97,66 @@ impl Command for AclListCommand {
     }
 
     async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
-}
\ No newline at end of file
+}
+
+/// Every user's ACLs, the anonymous ACLs and the disabled commands, to be applied on another server with ACLImport.
+/// Passwords and keys aren't part of it
+pub struct AclExportCommand {}
+
+#[async_trait]
+impl Command for AclExportCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, _args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let acls = store.read().await.acl_export();
+        let rsp = match bson::to_bson(&acls) {
+            Ok(acls) => MessageResponse {
+                content: Some(acls),
+                status: OperationStatus::Success,
+            },
+            Err(err) => {
+                log::error!("Error serializing ACLs: {}", err);
+                MessageResponse {
+                    content: None,
+                    status: OperationStatus::Failure,
+                }
+            }
+        };
+        Some(rsp)
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+/// Applies the result of ACLExport, merged into the existing ACLs or replacing them
+#[derive(Default)]
+pub struct AclImportCommand {
+    snapshot: Option<AuthSnapshot>,
+}
+
+#[async_trait]
+impl Command for AclImportCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let mut store = store.write().await;
+        let args: AclImportCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        store.acl_import(args.acls, args.replace);
+        self.snapshot = store.auth_snapshot();
+        let rsp = MessageResponse {
+            content: None,
+            status: OperationStatus::Success,
+        };
+        Some(rsp)
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {
+        if let Some(snapshot) = self.snapshot.take() {
+            snapshot.persist().await;
+        }
+    }
+}
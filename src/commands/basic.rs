@@ -3,13 +3,13 @@ use async_trait::async_trait;
 
 use bson::Bson;
 use tokio::sync::RwLock;
-use common::command_input::{DeleteCommandInput, GetCommandInput, SetCommandInput};
+use common::command_input::{AppendIfCommandInput, CompareSetCommandInput, DecrDelCommandInput, DeleteCommandInput, GetCommandInput, GetOrSetCommandInput, MultiLockCommandInput, SetCommandInput, SetWithVersionCommandInput};
 use common::connection::Connection;
 
 use common::message::{Message, MessageResponse, OperationStatus};
 
-use crate::commands::Command;
-use crate::store::{Store, StoreAble};
+use crate::commands::{Command, error_response, key_too_long, protected, wrong_type};
+use crate::store::{ErrorType, Kind, Store, StoreAble};
 
 
 pub struct GetCommand {}
@@ -25,6 +25,9 @@ impl Command for GetCommand {
             Ok(doc) => doc
         };
 
+        if let Some(rsp) = wrong_type(&store, &args.key, Kind::String) {
+            return Some(rsp);
+        }
         let rsp = match store.get(&args.key) {
             None => {
                 MessageResponse {
@@ -58,7 +61,17 @@ impl Command for SetCommand {
             Ok(doc) => doc
         };
 
-        let rsp = match store.set(args.key, args.value) {
+        if let Some(rsp) = key_too_long(&store, &args.key) {
+            return Some(rsp);
+        }
+        if let Some(rsp) = protected(&store, &args.key) {
+            return Some(rsp);
+        }
+        let value = match args.transform {
+            Some(transform) => transform.apply(args.value),
+            None => args.value,
+        };
+        let rsp = match store.set(args.key, value) {
             Ok(_) => {
                 MessageResponse {
                     content: None,
@@ -91,6 +104,12 @@ impl Command for DeleteCommand {
             Ok(doc) => doc
         };
 
+        if let Some(rsp) = wrong_type(&store, &args.key, Kind::String) {
+            return Some(rsp);
+        }
+        if let Some(rsp) = protected(&store, &args.key) {
+            return Some(rsp);
+        }
         let rsp = match store.remove(&args.key) {
             Some(val) => {
                 MessageResponse {
@@ -109,4 +128,266 @@ impl Command for DeleteCommand {
     }
 
     async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
-}
\ No newline at end of file
+}
+pub struct CompareSetCommand {}
+
+#[async_trait]
+impl Command for CompareSetCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let mut store = store.write().await;
+        let args: CompareSetCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        if let Some(rsp) = key_too_long(&store, &args.key) {
+            return Some(rsp);
+        }
+        if let Some(rsp) = protected(&store, &args.key) {
+            return Some(rsp);
+        }
+        let rsp = match store.compare_set(args.key, args.expected, args.value) {
+            Ok(set) => {
+                MessageResponse {
+                    content: Some(Bson::Boolean(set)),
+                    status: OperationStatus::Success,
+                }
+            }
+            Err(err) => error_response(err),
+        };
+        Some(rsp)
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+/// Like COMPARESET, but compares the version of the key instead of its value. Cheaper for large values.
+/// Answers with the new version, or with Failure and the current version if the given one is stale
+pub struct SetWithVersionCommand {}
+
+#[async_trait]
+impl Command for SetWithVersionCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let mut store = store.write().await;
+        let args: SetWithVersionCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        if let Some(rsp) = key_too_long(&store, &args.key) {
+            return Some(rsp);
+        }
+        if let Some(rsp) = protected(&store, &args.key) {
+            return Some(rsp);
+        }
+        let rsp = match store.set_with_version(args.key, args.version, args.value) {
+            Ok((set, version)) => {
+                MessageResponse {
+                    content: Some(Bson::Int64(version as i64)),
+                    status: if set { OperationStatus::Success } else { OperationStatus::Failure },
+                }
+            }
+            Err(err) => error_response(err),
+        };
+        Some(rsp)
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+/// Unlike GET with a default, the default is stored. Concurrent callers all get the value of whoever was first
+pub struct GetOrSetCommand {}
+
+#[async_trait]
+impl Command for GetOrSetCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let mut store = store.write().await;
+        let args: GetOrSetCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        if let Some(rsp) = key_too_long(&store, &args.key) {
+            return Some(rsp);
+        }
+        let rsp = match store.get_or_set(args.key, args.default) {
+            Ok((value, created)) => {
+                MessageResponse {
+                    content: Some(Bson::Document(bson::doc! { "value": value, "created": created })),
+                    status: OperationStatus::Success,
+                }
+            }
+            Err(err) => error_response(err),
+        };
+        Some(rsp)
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+/// Releases a reference count, removing the key once nothing references it anymore
+pub struct DecrDelCommand {}
+
+#[async_trait]
+impl Command for DecrDelCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let mut store = store.write().await;
+        let args: DecrDelCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        if let Some(rsp) = key_too_long(&store, &args.key) {
+            return Some(rsp);
+        }
+        if let Some(rsp) = protected(&store, &args.key) {
+            return Some(rsp);
+        }
+        let rsp = match store.decr_del(args.key, args.delta.unwrap_or(1)) {
+            Ok((value, deleted)) => {
+                MessageResponse {
+                    content: Some(Bson::Document(bson::doc! { "value": value, "deleted": deleted })),
+                    status: OperationStatus::Success,
+                }
+            }
+            Err(ErrorType::ParseIntError(_)) => {
+                MessageResponse {
+                    content: Some(Bson::String(String::from("Key does not contain a number"))),
+                    status: OperationStatus::TypeError,
+                }
+            }
+            Err(ErrorType::Overflow) => {
+                MessageResponse {
+                    content: Some(Bson::String(String::from("Decrement would overflow"))),
+                    status: OperationStatus::Failure,
+                }
+            }
+            Err(err) => error_response(err),
+        };
+        Some(rsp)
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+/// Acquires several locks at once, so clients don't deadlock by taking them one by one
+pub struct MultiLockCommand {}
+
+#[async_trait]
+impl Command for MultiLockCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let mut store = store.write().await;
+        let args: MultiLockCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        if let Some(rsp) = args.keys.iter().find_map(|key| key_too_long(&store, key)) {
+            return Some(rsp);
+        }
+        let keys = args.keys.clone();
+        let rsp = match store.multi_lock(args.keys, args.token) {
+            Ok(blocked) => {
+                let acquired = if blocked.is_empty() { keys } else { Vec::new() };
+                MessageResponse {
+                    content: Some(Bson::Document(bson::doc! {
+                        "acquired": acquired,
+                        "blocked": blocked,
+                    })),
+                    status: OperationStatus::Success,
+                }
+            }
+            Err(err) => error_response(err),
+        };
+        Some(rsp)
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+/// Appends a suffix unless the string already ends with it, for idempotent appends. Returns whether it was appended and the new length
+pub struct AppendIfCommand {}
+
+#[async_trait]
+impl Command for AppendIfCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let mut store = store.write().await;
+        let args: AppendIfCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        if let Some(rsp) = key_too_long(&store, &args.key) {
+            return Some(rsp);
+        }
+        if let Some(rsp) = protected(&store, &args.key) {
+            return Some(rsp);
+        }
+        let rsp = match store.append_if(args.key, args.suffix) {
+            Ok((appended, len)) => {
+                MessageResponse {
+                    content: Some(Bson::Document(bson::doc! { "appended": appended, "length": len as i64 })),
+                    status: OperationStatus::Success,
+                }
+            }
+            Err(err) => error_response(err),
+        };
+        Some(rsp)
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{run, test_store};
+    use crate::store::{KeyAble, ListAble};
+
+    fn get_or_set(key: &str, default: &str) -> GetOrSetCommandInput {
+        GetOrSetCommandInput { key: key.to_string(), default: default.to_string() }
+    }
+
+    #[tokio::test]
+    async fn getorset_only_stores_the_first_default() {
+        let store = test_store();
+        let rsp = run(&mut GetOrSetCommand {}, &store, get_or_set("key", "first")).await.unwrap();
+        assert_eq!(rsp.content, Some(Bson::Document(bson::doc! { "value": "first", "created": true })));
+        let rsp = run(&mut GetOrSetCommand {}, &store, get_or_set("key", "second")).await.unwrap();
+        assert_eq!(rsp.content, Some(Bson::Document(bson::doc! { "value": "first", "created": false })));
+        assert_eq!(store.read().await.get("key"), Some(&"first".to_string()));
+
+        store.write().await.rpush("list".to_string(), vec!["a".to_string()]).unwrap();
+        let rsp = run(&mut GetOrSetCommand {}, &store, get_or_set("list", "default")).await.unwrap();
+        assert_eq!(rsp.status, OperationStatus::TypeError);
+    }
+
+    #[tokio::test]
+    async fn keys_over_max_key_length_are_never_created() {
+        use crate::commands::{HashMapSetCommand, LpushCommand};
+        let store = test_store();
+        store.write().await.set_max_key_length(Some(4));
+        let set = |key: &str| SetCommandInput { key: key.to_string(), value: "1".to_string(), transform: None };
+        let rsp = run(&mut SetCommand {}, &store, set("abcd")).await.unwrap();
+        assert_eq!(rsp.status, OperationStatus::Success);
+        let rsp = run(&mut SetCommand {}, &store, set("abcde")).await.unwrap();
+        assert_eq!(rsp.status, OperationStatus::Failure);
+        let rsp = run(&mut HashMapSetCommand {}, &store, bson::doc! { "key": "abcde", "value": { "field": "1" } }).await.unwrap();
+        assert_eq!(rsp.status, OperationStatus::Failure);
+        let rsp = run(&mut LpushCommand {}, &store, bson::doc! { "list": "abcde", "values": ["1"] }).await.unwrap();
+        assert_eq!(rsp.status, OperationStatus::Failure);
+        assert!(!store.read().await.exists("abcde"));
+    }
+}
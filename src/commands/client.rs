@@ -1,12 +1,19 @@
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
+use age::x25519::Recipient;
 use async_trait::async_trait;
-use bson::Bson;
-use tokio::sync::RwLock;
+use bson::{Bson, Document};
+use sha2::{Digest, Sha256};
+use tokio::sync::{Mutex, RwLock};
 use uuid::Uuid;
-use common::connection::Connection;
+use common::command::CommandID;
+use common::command_input::CloseIdleCommandInput;
+use common::connection::{Connection, ConnectionStats};
 use common::message::{Message, MessageResponse, OperationStatus};
+use crate::clients::ClientRegistry;
 use crate::commands::Command;
-use crate::store::Store;
+use crate::store::{Store, UserAble};
 
 #[derive(Default)]
 pub struct ClientIDCommand {
@@ -28,4 +35,210 @@ impl Command for ClientIDCommand {
     }
 
     async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
-}
\ No newline at end of file
+}
+
+/// Counters of the connection that sends it. Only ever exposes the callers own connection
+#[derive(Default)]
+pub struct ConnStatsCommand {
+    stats: ConnectionStats,
+    encrypted: bool,
+}
+
+#[async_trait]
+impl Command for ConnStatsCommand {
+    async fn pre_exec(&mut self, connection: &Connection, _encrypted: bool) -> bool {
+        self.stats = connection.get_stats();
+        self.encrypted = connection.is_key_exchanged();
+        true
+    }
+
+    async fn execute(&mut self, _store: Arc<RwLock<Store>>, _args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let stats = &self.stats;
+        let doc = bson::doc! {
+            "bytes_sent": stats.bytes_sent as i64,
+            "bytes_received": stats.bytes_received as i64,
+            "commands": stats.messages_received as i64,
+            "responses": stats.messages_sent as i64,
+            "compression_ratio": stats.compression_ratio(),
+            "encrypted": self.encrypted,
+        };
+        Some(MessageResponse {
+            content: Some(Bson::Document(doc)),
+            status: OperationStatus::Success,
+        })
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+/// How often the calling connection issued each command, including this one. Like ConnStats it only exposes the callers own connection
+#[derive(Default)]
+pub struct SessionCommandsCommand {
+    counts: HashMap<CommandID, u64>,
+}
+
+#[async_trait]
+impl Command for SessionCommandsCommand {
+    async fn pre_exec(&mut self, connection: &Connection, _encrypted: bool) -> bool {
+        self.counts = connection.get_command_counts();
+        true
+    }
+
+    async fn execute(&mut self, _store: Arc<RwLock<Store>>, _args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let mut counts: Vec<(String, u64)> = self.counts.drain().map(|(command, count)| (command.to_string(), count)).collect();
+        counts.sort_unstable();
+        let doc = counts.into_iter().map(|(command, count)| (command, Bson::Int64(count as i64))).collect::<Document>();
+        Some(MessageResponse {
+            content: Some(Bson::Document(doc)),
+            status: OperationStatus::Success,
+        })
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+/// Whether the calling connection is encrypted and with which key, to check that a KEYEXCHANGE took effect.
+/// key_verified is only true for a logged in user whose configured public key is the one in use
+#[derive(Default)]
+pub struct SecurityInfoCommand {
+    key: Option<Recipient>,
+    user: Option<String>,
+    message_encrypted: bool,
+}
+
+#[async_trait]
+impl Command for SecurityInfoCommand {
+    async fn pre_exec(&mut self, connection: &Connection, encrypted: bool) -> bool {
+        self.key = connection.get_pub_key();
+        self.user = connection.get_user();
+        self.message_encrypted = encrypted;
+        true
+    }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, _args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let key_verified = match (&self.user, &self.key) {
+            (Some(user), Some(key)) => store.read().await.verify_key(user, key),
+            _ => false,
+        };
+        let fingerprint = self.key.as_ref().map(|key| format!("{:x}", Sha256::digest(key.to_string().as_bytes())));
+        let doc = bson::doc! {
+            "encrypted": self.key.is_some(),
+            "message_encrypted": self.message_encrypted,
+            "key_fingerprint": fingerprint,
+            "user": self.user.clone(),
+            "key_verified": key_verified,
+        };
+        Some(MessageResponse {
+            content: Some(Bson::Document(doc)),
+            status: OperationStatus::Success,
+        })
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+/// Closes the connections that sent nothing for longer than idle_secs and answers how many. The calling one is left open.
+/// Plaintext connections aren't registered with the clients, so they are never closed
+pub struct CloseIdleCommand {
+    clients: Arc<Mutex<ClientRegistry>>,
+    conn_id: Option<Uuid>,
+}
+
+impl CloseIdleCommand {
+    pub fn new(clients: Arc<Mutex<ClientRegistry>>) -> Self {
+        Self { clients, conn_id: None }
+    }
+}
+
+#[async_trait]
+impl Command for CloseIdleCommand {
+    async fn pre_exec(&mut self, connection: &Connection, _encrypted: bool) -> bool {
+        self.conn_id = Some(connection.get_id());
+        true
+    }
+
+    async fn execute(&mut self, _store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let args: CloseIdleCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+        let conn_id = self.conn_id?;
+
+        let closed = self.clients.lock().await.close_idle(Duration::from_secs(args.idle_secs), &conn_id);
+        log::info!("Closing {} connections idle for more than {}s", closed, args.idle_secs);
+        Some(MessageResponse {
+            content: Some(Bson::Int64(closed as i64)),
+            status: OperationStatus::Success,
+        })
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {
+        self.conn_id = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use age::x25519::Identity;
+    use super::*;
+    use crate::clients::ClientSignal;
+    use crate::commands::{run, test_connection, test_store};
+
+    async fn security_info(connection: &Connection, store: &Arc<RwLock<Store>>) -> Document {
+        let mut command = SecurityInfoCommand::default();
+        assert!(command.pre_exec(connection, connection.is_key_exchanged()).await);
+        let rsp = run(&mut command, store, Bson::Null).await.unwrap();
+        let Some(Bson::Document(doc)) = rsp.content else { panic!("expected a document, got {:?}", rsp.content) };
+        doc
+    }
+
+    #[tokio::test]
+    async fn security_info_only_verifies_the_users_configured_key() {
+        let store = test_store();
+        let key = Identity::generate().to_public();
+        store.write().await.user_add("user", "password", Some(key.clone()));
+        let (mut connection, _peer) = test_connection().await;
+        let doc = security_info(&connection, &store).await;
+        assert_eq!(doc.get_bool("encrypted"), Ok(false));
+        assert_eq!(doc.get("key_fingerprint"), Some(&Bson::Null));
+        assert_eq!(doc.get_bool("key_verified"), Ok(false));
+
+        connection.set_pub_key(Identity::generate().to_public());
+        connection.set_user("user".to_string());
+        let doc = security_info(&connection, &store).await;
+        assert_eq!(doc.get_bool("encrypted"), Ok(true));
+        assert_eq!(doc.get_str("user"), Ok("user"));
+        assert_eq!(doc.get_bool("key_verified"), Ok(false));
+
+        connection.set_pub_key(key.clone());
+        let doc = security_info(&connection, &store).await;
+        assert_eq!(doc.get_str("key_fingerprint").unwrap(), format!("{:x}", Sha256::digest(key.to_string().as_bytes())));
+        assert_eq!(doc.get_bool("key_verified"), Ok(true));
+    }
+
+
+    #[tokio::test]
+    async fn close_idle_closes_other_idle_connections() {
+        let clients = Arc::new(Mutex::new(ClientRegistry::default()));
+        let (connection, _peer) = test_connection().await;
+        let (idle, gone) = (Uuid::new_v4(), Uuid::new_v4());
+        let (mut own_signals, mut idle_signals) = {
+            let mut clients = clients.lock().await;
+            (clients.register(connection.get_id()), clients.register(idle))
+        };
+        drop(clients.lock().await.register(gone));
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        let mut command = CloseIdleCommand::new(clients.clone());
+        assert!(command.pre_exec(&connection, false).await);
+        let rsp = run(&mut command, &test_store(), CloseIdleCommandInput { idle_secs: 3600 }).await.unwrap();
+        assert_eq!(rsp.content, Some(Bson::Int64(0)));
+        assert!(idle_signals.try_recv().is_err());
+
+        let rsp = run(&mut command, &test_store(), CloseIdleCommandInput { idle_secs: 0 }).await.unwrap();
+        // The disconnected one can't be signaled anymore and isn't counted
+        assert_eq!(rsp.content, Some(Bson::Int64(1)));
+        assert!(matches!(idle_signals.try_recv(), Ok(ClientSignal::Close)));
+        assert!(own_signals.try_recv().is_err());
+    }
+}
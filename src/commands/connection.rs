@@ -1,12 +1,14 @@
 use std::str::FromStr;
 use std::sync::Arc;
 use age::x25519::Recipient;
+use bson::spec::BinarySubtype;
 use async_trait::async_trait;
 use bson::Bson;
 use sha2::{Digest, Sha512};
+use uuid::Uuid;
 use tokio::sync::RwLock;
-use common::command_input::{KeyExchangeCommandInput, LoginCommandInput};
-use common::connection::Connection;
+use common::command_input::{KeyExchangeCommandInput, LoginCommandInput, ReKeyCommandInput};
+use common::connection::{encrypt_to, Connection, FrameCapabilities};
 use common::message::{Message, MessageResponse, OperationStatus};
 use crate::commands::Command;
 use crate::store::{Store, UserAble};
@@ -95,6 +97,8 @@ impl Command for LoginCommand {
 pub struct KeyExchangeCommand {
     encrypted: bool,
     recipient: Option<Recipient>,
+    /// Whether the client asked for checksums, enabled in post_exec after the response
+    checksum: bool,
 }
 
 #[async_trait]
@@ -117,6 +121,7 @@ impl Command for KeyExchangeCommand {
         match age::x25519::Recipient::from_str(&*args.pub_key) {
             Ok(key) => {
                 self.recipient = Some(key);
+                self.checksum = args.checksum;
             }
             Err(err) => {
                 log::error!("Error parsing public key: {}", err);
@@ -127,8 +132,9 @@ impl Command for KeyExchangeCommand {
                 return Some(rsp);
             }
         };
+        // The response is already encrypted with the new key, see post_exec
         let rsp = MessageResponse {
-            content: None,
+            content: bson::to_bson(&FrameCapabilities::current(true, self.checksum)).ok(),
             status: OperationStatus::Success,
         };
         Some(rsp)
@@ -140,5 +146,154 @@ impl Command for KeyExchangeCommand {
             connection.set_pub_key(pub_key.clone());
         });
         self.recipient = None;
+        if self.checksum {
+            connection.enable_checksum_after_next_message();
+        }
+        self.checksum = false;
+    }
+}
+
+/// Replaces the key responses are encrypted with, for long-lived connections. Only accepted over encrypted connections
+///
+/// If the user has a registered key, ownership of the new key has to be proven first. The first ReKey returns a challenge
+/// encrypted with the new key, the second ReKey with the decrypted challenge swaps the key
+#[derive(Default)]
+pub struct ReKeyCommand {
+    encrypted: bool,
+    user: Option<String>,
+    /// The new key and the challenge sent for it, waiting for the answer
+    pending: Option<(Recipient, String)>,
+    /// Swapped in by post_exec, so the response is already encrypted with the new key
+    new_key: Option<Recipient>,
+}
+
+#[async_trait]
+impl Command for ReKeyCommand {
+    async fn pre_exec(&mut self, connection: &Connection, encrypted: bool) -> bool {
+        self.encrypted = encrypted;
+        self.user = connection.get_user();
+        true
+    }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let args: ReKeyCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        if !self.encrypted {
+            log::error!("Received unencrypted re-key message");
+            return None;
+        }
+        let key = match Recipient::from_str(&args.pub_key) {
+            Ok(key) => key,
+            Err(err) => {
+                return Some(MessageResponse {
+                    content: Some(Bson::String(err.to_string())),
+                    status: OperationStatus::Failure,
+                });
+            }
+        };
+
+        let needs_challenge = match &self.user {
+            Some(user) => store.read().await.user_has_key(user),
+            None => false,
+        };
+        if !needs_challenge {
+            self.new_key = Some(key);
+            return Some(MessageResponse {
+                content: None,
+                status: OperationStatus::Success,
+            });
+        }
+
+        match (args.challenge, self.pending.take()) {
+            (None, _) => {
+                let challenge = Uuid::new_v4().to_string();
+                let encrypted = match encrypt_to(&key, challenge.as_bytes()) {
+                    Ok(encrypted) => encrypted,
+                    Err(err) => {
+                        log::error!("Error encrypting re-key challenge: {}", err);
+                        return None;
+                    }
+                };
+                self.pending = Some((key, challenge));
+                Some(MessageResponse {
+                    content: Some(Bson::Document(bson::doc! {
+                        "challenge": bson::Binary { subtype: BinarySubtype::Generic, bytes: encrypted },
+                    })),
+                    status: OperationStatus::Success,
+                })
+            }
+            (Some(answer), Some((pending_key, challenge))) if pending_key == key && answer == challenge => {
+                self.new_key = Some(key);
+                Some(MessageResponse {
+                    content: None,
+                    status: OperationStatus::Success,
+                })
+            }
+            (Some(_), _) => {
+                log::error!("Re-key challenge failed for user {:?}", self.user);
+                Some(MessageResponse {
+                    content: Some(Bson::String(String::from("Challenge failed, request a new one"))),
+                    status: OperationStatus::NotAllowed,
+                })
+            }
+        }
     }
-}
\ No newline at end of file
+
+    async fn post_exec(&mut self, connection: &mut Connection, _: Option<&MessageResponse>) {
+        self.encrypted = false;
+        self.user = None;
+        if let Some(key) = self.new_key.take() {
+            connection.set_pub_key(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::message::MessageContent;
+    use super::*;
+    use crate::commands::{run, test_connection, test_store};
+
+    /// Runs KEYEXCHANGE like a connection would. The peer's socket is returned so the connection stays open
+    async fn key_exchange(checksum: bool) -> (Option<MessageResponse>, Connection, tokio::net::TcpStream) {
+        let (mut connection, peer) = test_connection().await;
+        let mut command = KeyExchangeCommand::default();
+        let input = KeyExchangeCommandInput {
+            pub_key: age::x25519::Identity::generate().to_public().to_string(),
+            checksum,
+        };
+        assert!(command.pre_exec(&connection, true).await);
+        let rsp = run(&mut command, &test_store(), input).await;
+        command.post_exec(&mut connection, rsp.as_ref()).await;
+        (rsp, connection, peer)
+    }
+
+    fn capabilities(rsp: Option<MessageResponse>) -> FrameCapabilities {
+        bson::from_bson(rsp.unwrap().content.unwrap()).unwrap()
+    }
+
+    fn response() -> Message {
+        Message::new(Uuid::new_v4(), MessageContent::Response(MessageResponse { content: None, status: OperationStatus::Success }))
+    }
+
+    #[tokio::test]
+    async fn key_exchange_enables_requested_checksum_after_the_response() {
+        let (rsp, mut connection, _peer) = key_exchange(true).await;
+        assert!(capabilities(rsp).checksum_enabled);
+        assert!(connection.is_key_exchanged());
+        assert!(!connection.is_checksum_enabled());
+        connection.send_message(&response()).await.unwrap();
+        assert!(connection.is_checksum_enabled());
+    }
+
+    #[tokio::test]
+    async fn key_exchange_keeps_frames_unchanged_without_checksum() {
+        let (rsp, mut connection, _peer) = key_exchange(false).await;
+        assert!(!capabilities(rsp).checksum_enabled);
+        connection.send_message(&response()).await.unwrap();
+        assert!(!connection.is_checksum_enabled());
+    }
+}
@@ -1,12 +1,25 @@
 use std::sync::Arc;
 use async_trait::async_trait;
-use bson::{Bson, Document};
+use bson::{Bson, doc, Document};
 use tokio::sync::RwLock;
-use common::command_input::{HashMapDeleteCommandInput, HashMapExistsCommandInput, HashMapGetAllCommandInput, HashMapGetCommandInput, HashMapIncrByCommandInput, HashMapKeysCommandInput, HashMapLenCommandInput, HashMapSetCommandInput, HashMapStringLenCommandInput, HashMapUpsertCommandInput, HashMapValuesCommandInput};
+use common::command_input::{HashMapDeleteCommandInput, HDumpCommandInput, HRestoreCommandInput, HashMapExistsCommandInput, HFilterCommandInput, HashMapGetAllCommandInput, HashMapGetCommandInput, HGetSortedCommandInput, HashMapIncrByCommandInput, HashMapKeysCommandInput, HKeysMatchCommandInput, HashMapLenCommandInput, MultiHSetCommandInput, HashMapSetCommandInput, HashMapStringLenCommandInput, HSwapCommandInput, HTopCommandInput, HashMapUpsertCommandInput, HashMapValuesCommandInput};
 use common::connection::Connection;
 use common::message::{Message, MessageResponse, OperationStatus};
-use crate::commands::Command;
-use crate::store::{ErrorType, HashMapAble, Store};
+use crate::commands::{Command, key_too_long, protected, wrong_type};
+use crate::store::{ErrorType, HashMapAble, Kind, Store};
+
+/// Returns a failure response if the hash has more fields than max_response allows, so the response is never built
+fn exceeds_max_response(store: &Store, map_key: &str, max_response: Option<usize>) -> Option<MessageResponse> {
+    let max_response = max_response?;
+    let len = store.hlen(map_key.to_string());
+    if len <= max_response {
+        return None;
+    }
+    Some(MessageResponse {
+        content: Some(Bson::String(format!("Hash has {} fields, more than the limit of {}. Too large, use HSCAN", len, max_response))),
+        status: OperationStatus::Failure,
+    })
+}
 
 pub struct HashMapDeleteCommand {}
 
@@ -21,6 +34,9 @@ impl Command for HashMapDeleteCommand {
         };
 
         let mut store = store.write().await;
+        if let Some(rsp) = wrong_type(&store, &args.key, Kind::Hash) {
+            return Some(rsp);
+        }
         let rsp = match store.hremove(args.key, args.field) {
             true => {
                 MessageResponse {
@@ -54,6 +70,9 @@ impl Command for HashMapGetCommand {
         };
 
         let store = store.read().await;
+        if let Some(rsp) = wrong_type(&store, &args.key, Kind::Hash) {
+            return Some(rsp);
+        }
         let rsp = match store.hget(args.key, args.field) {
             None => {
                 MessageResponse {
@@ -88,6 +107,19 @@ impl Command for HashMapSetCommand {
         };
 
         let mut store = store.write().await;
+        if let Some(rsp) = wrong_type(&store, &args.key, Kind::Hash) {
+            return Some(rsp);
+        }
+        if let Some(rsp) = key_too_long(&store, &args.key) {
+            return Some(rsp);
+        }
+        // Checked up front, so a hash that can't take all fields doesn't get some of them
+        if let Err(err) = store.check_hash_fields(&args.key, args.value.keys()) {
+            return Some(MessageResponse {
+                content: Some(Bson::String(err.to_string())),
+                status: OperationStatus::Failure,
+            });
+        }
         let mut okay = Vec::new();
         match okay.try_reserve_exact(args.value.len()) {
             Ok(_) => {}
@@ -122,7 +154,15 @@ impl Command for HashMapSetCommand {
     async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
 }
 
-pub struct HashMapGetAllCommand {}
+pub struct HashMapGetAllCommand {
+    max_response: Option<usize>,
+}
+
+impl HashMapGetAllCommand {
+    pub fn new(max_response: Option<usize>) -> Self {
+        Self { max_response }
+    }
+}
 
 #[async_trait]
 impl Command for HashMapGetAllCommand {
@@ -135,6 +175,12 @@ impl Command for HashMapGetAllCommand {
         };
 
         let store = store.read().await;
+        if let Some(rsp) = exceeds_max_response(&store, &args.key, self.max_response) {
+            return Some(rsp);
+        }
+        if let Some(rsp) = wrong_type(&store, &args.key, Kind::Hash) {
+            return Some(rsp);
+        }
         let rsp = match store.hget_all(args.key) {
             Ok(map) => {
                 let map = map.into_iter().map(|(k, v)| (k, Bson::String(v))).collect::<Document>();
@@ -156,7 +202,15 @@ impl Command for HashMapGetAllCommand {
     async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
 }
 
-pub struct HashMapKeysCommand {}
+pub struct HashMapKeysCommand {
+    max_response: Option<usize>,
+}
+
+impl HashMapKeysCommand {
+    pub fn new(max_response: Option<usize>) -> Self {
+        Self { max_response }
+    }
+}
 
 #[async_trait]
 impl Command for HashMapKeysCommand {
@@ -169,6 +223,12 @@ impl Command for HashMapKeysCommand {
         };
 
         let store = store.read().await;
+        if let Some(rsp) = exceeds_max_response(&store, &args.key, self.max_response) {
+            return Some(rsp);
+        }
+        if let Some(rsp) = wrong_type(&store, &args.key, Kind::Hash) {
+            return Some(rsp);
+        }
         let rsp = match store.hkeys(args.key) {
             Ok(keys) => {
                 let keys = keys.into_iter().map(|k| Bson::String(k)).collect::<Vec<Bson>>();
@@ -190,6 +250,43 @@ impl Command for HashMapKeysCommand {
     async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
 }
 
+pub struct HKeysMatchCommand {}
+
+#[async_trait]
+impl Command for HKeysMatchCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let args: HKeysMatchCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        let store = store.read().await;
+        if let Some(rsp) = wrong_type(&store, &args.key, Kind::Hash) {
+            return Some(rsp);
+        }
+        let rsp = match store.hkeys_match(args.key, &args.pattern) {
+            Ok(keys) => {
+                let keys = keys.into_iter().map(Bson::String).collect::<Vec<Bson>>();
+                MessageResponse {
+                    content: Some(Bson::Array(keys)),
+                    status: OperationStatus::Success,
+                }
+            }
+            Err(err) => {
+                MessageResponse {
+                    content: Some(Bson::String(err.to_string())),
+                    status: OperationStatus::Failure,
+                }
+            }
+        };
+        Some(rsp)
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
 pub struct HashMapLenCommand {}
 
 #[async_trait]
@@ -203,6 +300,9 @@ impl Command for HashMapLenCommand {
         };
 
         let store = store.read().await;
+        if let Some(rsp) = wrong_type(&store, &args.key, Kind::Hash) {
+            return Some(rsp);
+        }
         let rsp = MessageResponse {
             content: Some(Bson::Int64(store.hlen(args.key) as i64)),
             status: OperationStatus::Success,
@@ -213,7 +313,15 @@ impl Command for HashMapLenCommand {
     async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
 }
 
-pub struct HashMapValuesCommand {}
+pub struct HashMapValuesCommand {
+    max_response: Option<usize>,
+}
+
+impl HashMapValuesCommand {
+    pub fn new(max_response: Option<usize>) -> Self {
+        Self { max_response }
+    }
+}
 
 #[async_trait]
 impl Command for HashMapValuesCommand {
@@ -226,6 +334,12 @@ impl Command for HashMapValuesCommand {
         };
 
         let store = store.read().await;
+        if let Some(rsp) = exceeds_max_response(&store, &args.key, self.max_response) {
+            return Some(rsp);
+        }
+        if let Some(rsp) = wrong_type(&store, &args.key, Kind::Hash) {
+            return Some(rsp);
+        }
         let rsp = match store.hget_all_values(args.key) {
             Ok(values) => {
                 let values = values.into_iter().map(|v| Bson::String(v)).collect::<Vec<Bson>>();
@@ -260,6 +374,9 @@ impl Command for HashMapExistsCommand {
         };
 
         let store = store.read().await;
+        if let Some(rsp) = wrong_type(&store, &args.key, Kind::Hash) {
+            return Some(rsp);
+        }
         let rsp = MessageResponse {
             content: Some(Bson::Boolean(store.hcontains(args.key, args.field))),
             status: OperationStatus::Success,
@@ -283,6 +400,12 @@ impl Command for HashMapIncrByCommand {
         };
 
         let mut store = store.write().await;
+        if let Some(rsp) = key_too_long(&store, &args.key) {
+            return Some(rsp);
+        }
+        if let Some(rsp) = wrong_type(&store, &args.key, Kind::Hash) {
+            return Some(rsp);
+        }
         let rsp = match store.hincrby(args.key, args.field, args.value) {
             Ok(val) => {
                 MessageResponse {
@@ -304,6 +427,24 @@ impl Command for HashMapIncrByCommand {
                             status: OperationStatus::TypeError,
                         }
                     }
+                    ErrorType::WrongType => {
+                        MessageResponse {
+                            content: Some(Bson::String(String::from("Key does not contain a hashmap"))),
+                            status: OperationStatus::TypeError,
+                        }
+                    }
+                    ErrorType::Overflow => {
+                        MessageResponse {
+                            content: Some(Bson::String(String::from("Increment would overflow"))),
+                            status: OperationStatus::Failure,
+                        }
+                    }
+                    ErrorType::TypeLimit(_) | ErrorType::SizeLimit(..) => {
+                        MessageResponse {
+                            content: Some(Bson::String(err.to_string())),
+                            status: OperationStatus::Failure,
+                        }
+                    }
                 }
             }
         };
@@ -326,6 +467,9 @@ impl Command for HashMapStringLenCommand {
         };
 
         let store = store.read().await;
+        if let Some(rsp) = wrong_type(&store, &args.key, Kind::Hash) {
+            return Some(rsp);
+        }
         let rsp = match store.hstr_len(args.key, args.field) {
             Some(len) => {
                 MessageResponse {
@@ -359,6 +503,12 @@ impl Command for HashMapUpsertCommand {
         };
 
         let mut store = store.write().await;
+        if let Some(rsp) = wrong_type(&store, &args.key, Kind::Hash) {
+            return Some(rsp);
+        }
+        if let Some(rsp) = key_too_long(&store, &args.key) {
+            return Some(rsp);
+        }
         let rsp = match store.hupsert(args.key, args.field, args.value) {
             Ok(_) => {
                 MessageResponse {
@@ -366,6 +516,12 @@ impl Command for HashMapUpsertCommand {
                     status: OperationStatus::Success,
                 }
             }
+            Err(err @ ErrorType::SizeLimit(..)) => {
+                MessageResponse {
+                    content: Some(Bson::String(err.to_string())),
+                    status: OperationStatus::Failure,
+                }
+            }
             Err(err) => {
                 log::error!("Error upserting: {}", err);
                 MessageResponse {
@@ -379,3 +535,457 @@ impl Command for HashMapUpsertCommand {
 
     async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
 }
+
+pub struct HGetSortedCommand {}
+
+#[async_trait]
+impl Command for HGetSortedCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let args: HGetSortedCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        let store = store.read().await;
+        if let Some(rsp) = wrong_type(&store, &args.key, Kind::Hash) {
+            return Some(rsp);
+        }
+        let rsp = match store.hget_sorted(args.key, args.descending, args.offset.unwrap_or(0), args.count) {
+            Ok(pairs) => {
+                let pairs = pairs.into_iter().map(|(field, value)| Bson::Document(doc! { "field": field, "value": value })).collect::<Vec<Bson>>();
+                MessageResponse {
+                    content: Some(Bson::Array(pairs)),
+                    status: OperationStatus::Success,
+                }
+            }
+            Err(err) => {
+                MessageResponse {
+                    content: Some(Bson::String(err.to_string())),
+                    status: OperationStatus::Failure,
+                }
+            }
+        };
+        Some(rsp)
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+pub struct HFilterCommand {}
+
+#[async_trait]
+impl Command for HFilterCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let args: HFilterCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        let store = store.read().await;
+        if let Some(rsp) = wrong_type(&store, &args.key, Kind::Hash) {
+            return Some(rsp);
+        }
+        let rsp = match store.hfilter(args.key, &args.predicate) {
+            Ok(pairs) => {
+                MessageResponse {
+                    content: Some(Bson::Document(pairs.into_iter().map(|(field, value)| (field, Bson::String(value))).collect())),
+                    status: OperationStatus::Success,
+                }
+            }
+            Err(err) => {
+                MessageResponse {
+                    content: Some(Bson::String(err.to_string())),
+                    status: OperationStatus::Failure,
+                }
+            }
+        };
+        Some(rsp)
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+pub struct HSwapCommand {}
+
+#[async_trait]
+impl Command for HSwapCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let args: HSwapCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        let mut store = store.write().await;
+        if let Some(rsp) = wrong_type(&store, &args.key, Kind::Hash) {
+            return Some(rsp);
+        }
+        let status = match store.hswap(args.key, args.field_a, args.field_b) {
+            true => OperationStatus::Success,
+            false => OperationStatus::NotFound,
+        };
+        Some(MessageResponse {
+            content: None,
+            status,
+        })
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+/// A small leaderboard on top of a hash, with the fields as members and their values as scores
+pub struct HTopCommand {}
+
+#[async_trait]
+impl Command for HTopCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let args: HTopCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        let store = store.read().await;
+        if let Some(rsp) = wrong_type(&store, &args.key, Kind::Hash) {
+            return Some(rsp);
+        }
+        let rsp = match store.htop(args.key, args.count, args.strict) {
+            Ok(pairs) => {
+                let pairs = pairs.into_iter().map(|(field, value)| Bson::Document(doc! { "field": field, "value": value })).collect::<Vec<Bson>>();
+                MessageResponse {
+                    content: Some(Bson::Array(pairs)),
+                    status: OperationStatus::Success,
+                }
+            }
+            Err(ErrorType::WrongType) => {
+                MessageResponse {
+                    content: Some(Bson::String(String::from("Hash contains a value that is not a number"))),
+                    status: OperationStatus::TypeError,
+                }
+            }
+            Err(err) => {
+                MessageResponse {
+                    content: Some(Bson::String(err.to_string())),
+                    status: OperationStatus::Failure,
+                }
+            }
+        };
+        Some(rsp)
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+pub struct MultiHSetCommand {}
+
+#[async_trait]
+impl Command for MultiHSetCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let args: MultiHSetCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        let mut store = store.write().await;
+        if let Some(rsp) = args.hashes.keys().find_map(|key| key_too_long(&store, key)) {
+            return Some(rsp);
+        }
+        if let Some(rsp) = args.hashes.keys().find_map(|key| wrong_type(&store, key, Kind::Hash)) {
+            return Some(rsp);
+        }
+        let rsp = match store.hset_multi(args.hashes) {
+            Ok(counts) => {
+                let counts = counts.into_iter().map(|(k, count)| (k, Bson::Int64(count as i64))).collect::<Document>();
+                MessageResponse {
+                    content: Some(Bson::Document(counts)),
+                    status: OperationStatus::Success,
+                }
+            }
+            Err(ErrorType::WrongType) => {
+                MessageResponse {
+                    content: Some(Bson::String(String::from("A key does not contain a hashmap"))),
+                    status: OperationStatus::TypeError,
+                }
+            }
+            Err(ErrorType::TryReserveError(err)) => {
+                MessageResponse {
+                    content: Some(Bson::String(err.to_string())),
+                    status: OperationStatus::OutOfMemory,
+                }
+            }
+            Err(err @ ErrorType::TypeLimit(_)) | Err(err @ ErrorType::SizeLimit(..)) => {
+                MessageResponse {
+                    content: Some(Bson::String(err.to_string())),
+                    status: OperationStatus::Failure,
+                }
+            }
+            Err(ErrorType::ParseIntError(_)) | Err(ErrorType::Overflow) => {
+                // hset_multi doesn't parse or calculate anything
+                MessageResponse {
+                    content: None,
+                    status: OperationStatus::Failure,
+                }
+            }
+        };
+        Some(rsp)
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+pub struct HDumpCommand {}
+
+#[async_trait]
+impl Command for HDumpCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let args: HDumpCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        let store = store.read().await;
+        if let Some(rsp) = wrong_type(&store, &args.key, Kind::Hash) {
+            return Some(rsp);
+        }
+        let rsp = match store.hdump(args.key) {
+            Ok(Some(map)) => {
+                let map = map.into_iter().map(|(k, v)| (k, Bson::String(v))).collect::<Document>();
+                MessageResponse {
+                    content: Some(Bson::Document(map)),
+                    status: OperationStatus::Success,
+                }
+            }
+            Ok(None) => {
+                MessageResponse {
+                    content: None,
+                    status: OperationStatus::NotFound,
+                }
+            }
+            Err(err) => {
+                MessageResponse {
+                    content: Some(Bson::String(err.to_string())),
+                    status: OperationStatus::Failure,
+                }
+            }
+        };
+        Some(rsp)
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+pub struct HRestoreCommand {}
+
+#[async_trait]
+impl Command for HRestoreCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let args: HRestoreCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        let mut store = store.write().await;
+        if let Some(rsp) = key_too_long(&store, &args.key) {
+            return Some(rsp);
+        }
+        if let Some(rsp) = wrong_type(&store, &args.key, Kind::Hash) {
+            return Some(rsp);
+        }
+        if args.replace {
+            if let Some(rsp) = protected(&store, &args.key) {
+                return Some(rsp);
+            }
+        }
+        let replace = args.replace;
+        let rsp = match store.hrestore(args.key, args.value, args.replace) {
+            Ok(_) => {
+                MessageResponse {
+                    content: None,
+                    status: OperationStatus::Success,
+                }
+            }
+            Err(ErrorType::WrongType) if replace => {
+                MessageResponse {
+                    content: Some(Bson::String(String::from("Key can't be replaced by a hashmap"))),
+                    status: OperationStatus::TypeError,
+                }
+            }
+            Err(ErrorType::WrongType) => {
+                MessageResponse {
+                    content: Some(Bson::String(String::from("Key does not contain a hashmap, set replace to overwrite it"))),
+                    status: OperationStatus::TypeError,
+                }
+            }
+            Err(err) => {
+                MessageResponse {
+                    content: Some(Bson::String(err.to_string())),
+                    status: OperationStatus::Failure,
+                }
+            }
+        };
+        Some(rsp)
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use super::*;
+    use crate::commands::{run, test_store};
+    use crate::store::{ListAble, StoreAble, UserAble};
+
+    fn incr_by(key: &str, value: i64) -> HashMapIncrByCommandInput {
+        HashMapIncrByCommandInput {
+            key: key.to_string(),
+            field: "field".to_string(),
+            value,
+        }
+    }
+
+    #[tokio::test]
+    async fn hincrby_on_other_types_is_type_error() {
+        let store = test_store();
+        store.write().await.set("string".to_string(), "1".to_string()).unwrap();
+        store.write().await.rpush("list".to_string(), vec!["1".to_string()]).unwrap();
+        for key in ["string", "list"] {
+            let rsp = run(&mut HashMapIncrByCommand {}, &store, incr_by(key, 1)).await.unwrap();
+            assert_eq!(rsp.status, OperationStatus::TypeError);
+        }
+    }
+
+    #[tokio::test]
+    async fn hincrby_overflow_fails() {
+        let store = test_store();
+        let rsp = run(&mut HashMapIncrByCommand {}, &store, incr_by("hash", i64::MAX)).await.unwrap();
+        assert_eq!(rsp.content, Some(Bson::Int64(i64::MAX)));
+        let rsp = run(&mut HashMapIncrByCommand {}, &store, incr_by("hash", 1)).await.unwrap();
+        assert_eq!(rsp.status, OperationStatus::Failure);
+    }
+
+    /// A store with a string at "string", strict types switched on or off
+    async fn store_with_string(strict: bool) -> Arc<RwLock<Store>> {
+        let store = test_store();
+        let mut locked = store.write().await;
+        locked.set_strict_types(strict);
+        locked.set("string".to_string(), "1".to_string()).unwrap();
+        drop(locked);
+        store
+    }
+
+    #[tokio::test]
+    async fn hincrby_on_string_fails_in_both_modes() {
+        for strict in [false, true] {
+            let store = store_with_string(strict).await;
+            let rsp = run(&mut HashMapIncrByCommand {}, &store, incr_by("string", 1)).await.unwrap();
+            assert_eq!(rsp.status, OperationStatus::TypeError);
+        }
+    }
+
+    #[tokio::test]
+    async fn multi_hset_on_string_fails_in_both_modes() {
+        for strict in [false, true] {
+            let store = store_with_string(strict).await;
+            let hashes = HashMap::from([
+                ("hash".to_string(), HashMap::from([("a".to_string(), "1".to_string())])),
+                ("string".to_string(), HashMap::from([("a".to_string(), "1".to_string())])),
+            ]);
+            let rsp = run(&mut MultiHSetCommand {}, &store, MultiHSetCommandInput { hashes }).await.unwrap();
+            assert_eq!(rsp.status, OperationStatus::TypeError);
+            assert_eq!(store.read().await.hlen("hash".to_string()), 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn hrestore_replace_over_string_only_without_strict_types() {
+        for strict in [false, true] {
+            let store = store_with_string(strict).await;
+            let input = HRestoreCommandInput {
+                key: "string".to_string(),
+                value: HashMap::from([("a".to_string(), "1".to_string())]),
+                replace: true,
+            };
+            let rsp = run(&mut HRestoreCommand {}, &store, input).await.unwrap();
+            if strict {
+                assert_eq!(rsp.status, OperationStatus::TypeError);
+                assert_eq!(store.read().await.get("string"), Some(&"1".to_string()));
+            } else {
+                assert_eq!(rsp.status, OperationStatus::Success);
+                assert_eq!(store.read().await.hlen("string".to_string()), 1);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn hrestore_never_replaces_users() {
+        let store = test_store();
+        store.write().await.user_add("alice", "password", None);
+        let input = HRestoreCommandInput {
+            key: "alice".to_string(),
+            value: HashMap::from([("a".to_string(), "1".to_string())]),
+            replace: true,
+        };
+        let rsp = run(&mut HRestoreCommand {}, &store, input).await.unwrap();
+        assert_eq!(rsp.status, OperationStatus::TypeError);
+        assert!(store.read().await.user_is_valid("alice", "password"));
+    }
+
+    #[tokio::test]
+    async fn collection_responses_over_the_limit_fail() {
+        let store = test_store();
+        for field in ["a", "b", "c"] {
+            store.write().await.hadd("hash".to_string(), field.to_string(), "1".to_string()).unwrap();
+        }
+        let get_all = bson::doc! { "key": "hash", "field": "" };
+        let rsp = run(&mut HashMapGetAllCommand::new(Some(2)), &store, &get_all).await.unwrap();
+        assert_eq!(rsp.status, OperationStatus::Failure);
+        let rsp = run(&mut HashMapKeysCommand::new(Some(2)), &store, &get_all).await.unwrap();
+        assert_eq!(rsp.status, OperationStatus::Failure);
+        let rsp = run(&mut HashMapValuesCommand::new(Some(2)), &store, bson::doc! { "key": "hash" }).await.unwrap();
+        assert_eq!(rsp.status, OperationStatus::Failure);
+
+        let rsp = run(&mut HashMapGetAllCommand::new(Some(3)), &store, &get_all).await.unwrap();
+        assert_eq!(rsp.status, OperationStatus::Success);
+        let rsp = run(&mut HashMapGetAllCommand::new(None), &store, &get_all).await.unwrap();
+        assert_eq!(rsp.status, OperationStatus::Success);
+    }
+
+    #[tokio::test]
+    async fn hdump_output_restores_into_another_key() {
+        let store = test_store();
+        for (field, value) in [("a", "1"), ("b", "2")] {
+            store.write().await.hadd("source".to_string(), field.to_string(), value.to_string()).unwrap();
+        }
+        let rsp = run(&mut HDumpCommand {}, &store, HDumpCommandInput { key: "source".to_string() }).await.unwrap();
+        let Some(Bson::Document(dump)) = rsp.content else { panic!("expected a document, got {:?}", rsp.content) };
+        let value: HashMap<String, String> = bson::from_document(dump).unwrap();
+        let restore = |value: HashMap<String, String>, replace: bool| HRestoreCommandInput { key: "destination".to_string(), value, replace };
+
+        let rsp = run(&mut HRestoreCommand {}, &store, restore(value.clone(), false)).await.unwrap();
+        assert_eq!(rsp.status, OperationStatus::Success);
+        assert_eq!(store.read().await.hget_all("destination".to_string()).unwrap(), value);
+        // Without replace the fields are merged, with it they replace the hash
+        let rsp = run(&mut HRestoreCommand {}, &store, restore(HashMap::from([("c".to_string(), "3".to_string())]), false)).await.unwrap();
+        assert_eq!(rsp.status, OperationStatus::Success);
+        assert_eq!(store.read().await.hlen("destination".to_string()), 3);
+        let rsp = run(&mut HRestoreCommand {}, &store, restore(HashMap::from([("d".to_string(), "4".to_string())]), true)).await.unwrap();
+        assert_eq!(rsp.status, OperationStatus::Success);
+        assert_eq!(store.read().await.hget_all("destination".to_string()).unwrap(), HashMap::from([("d".to_string(), "4".to_string())]));
+
+        let rsp = run(&mut HDumpCommand {}, &store, HDumpCommandInput { key: "missing".to_string() }).await.unwrap();
+        assert_eq!(rsp.status, OperationStatus::NotFound);
+    }
+}
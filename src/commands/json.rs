@@ -0,0 +1,235 @@
+use std::sync::Arc;
+use async_trait::async_trait;
+use bson::Bson;
+use serde_json::{Map, Value};
+use tokio::sync::RwLock;
+use common::command_input::{JsonGetCommandInput, JsonSetCommandInput};
+use common::connection::Connection;
+use common::message::{Message, MessageResponse, OperationStatus};
+use crate::commands::{Command, protected, wrong_type};
+use crate::store::{Kind, Store, StoreAble};
+
+/// Walks the dotted path through objects and arrays. Array elements are addressed by their index
+fn lookup<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    if path.is_empty() {
+        return Some(value);
+    }
+    let mut current = value;
+    for segment in path.split('.') {
+        current = match current {
+            Value::Object(map) => map.get(segment)?,
+            Value::Array(list) => list.get(segment.parse::<usize>().ok()?)?,
+            _ => return None
+        };
+    }
+    Some(current)
+}
+
+/// Sets the value at the dotted path. Missing objects along the way are created, missing array indices are not
+/// Returns false if the path runs into a scalar or an array index that doesn't exist
+fn assign(value: &mut Value, path: &str, new_value: Value) -> bool {
+    if path.is_empty() {
+        *value = new_value;
+        return true;
+    }
+    let segments: Vec<&str> = path.split('.').collect();
+    let (last, parents) = segments.split_last().unwrap();
+    let mut current = value;
+    for segment in parents {
+        current = match current {
+            Value::Object(map) => map.entry(segment.to_string()).or_insert(Value::Object(Map::new())),
+            Value::Array(list) => {
+                match segment.parse::<usize>().ok().and_then(|i| list.get_mut(i)) {
+                    Some(v) => v,
+                    None => return false
+                }
+            }
+            _ => return false
+        };
+    }
+    match current {
+        Value::Object(map) => {
+            map.insert(last.to_string(), new_value);
+            true
+        }
+        Value::Array(list) => {
+            match last.parse::<usize>().ok().and_then(|i| list.get_mut(i)) {
+                Some(v) => {
+                    *v = new_value;
+                    true
+                }
+                None => false
+            }
+        }
+        _ => false
+    }
+}
+
+pub struct JsonGetCommand {}
+
+#[async_trait]
+impl Command for JsonGetCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let store = store.read().await;
+        let args: JsonGetCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        if let Some(rsp) = wrong_type(&store, &args.key, Kind::String) {
+            return Some(rsp);
+        }
+        let document = match store.get(&args.key) {
+            Some(val) => val,
+            None => {
+                return Some(MessageResponse {
+                    content: None,
+                    status: OperationStatus::NotFound,
+                });
+            }
+        };
+        let document: Value = match serde_json::from_str(document) {
+            Ok(doc) => doc,
+            Err(err) => {
+                return Some(MessageResponse {
+                    content: Some(Bson::String(err.to_string())),
+                    status: OperationStatus::TypeError,
+                });
+            }
+        };
+
+        let rsp = match lookup(&document, &args.path) {
+            Some(val) => {
+                MessageResponse {
+                    content: Some(Bson::String(val.to_string())),
+                    status: OperationStatus::Success,
+                }
+            }
+            None => {
+                MessageResponse {
+                    content: None,
+                    status: OperationStatus::NotFound,
+                }
+            }
+        };
+        Some(rsp)
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+pub struct JsonSetCommand {}
+
+#[async_trait]
+impl Command for JsonSetCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let mut store = store.write().await;
+        let args: JsonSetCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        if let Some(rsp) = wrong_type(&store, &args.key, Kind::String) {
+            return Some(rsp);
+        }
+        if let Some(rsp) = protected(&store, &args.key) {
+            return Some(rsp);
+        }
+        let new_value: Value = match serde_json::from_str(&args.value) {
+            Ok(val) => val,
+            Err(err) => {
+                return Some(MessageResponse {
+                    content: Some(Bson::String(err.to_string())),
+                    status: OperationStatus::TypeError,
+                });
+            }
+        };
+        // The document has to exist already, so that a value of another type is never overwritten
+        let mut document: Value = match store.get(&args.key).map(|x| serde_json::from_str(x)) {
+            Some(Ok(doc)) => doc,
+            Some(Err(err)) => {
+                return Some(MessageResponse {
+                    content: Some(Bson::String(err.to_string())),
+                    status: OperationStatus::TypeError,
+                });
+            }
+            None => {
+                return Some(MessageResponse {
+                    content: None,
+                    status: OperationStatus::NotFound,
+                });
+            }
+        };
+
+        if !assign(&mut document, &args.path, new_value) {
+            return Some(MessageResponse {
+                content: None,
+                status: OperationStatus::NotFound,
+            });
+        }
+
+        let rsp = match store.set(args.key, document.to_string()) {
+            Ok(_) => {
+                MessageResponse {
+                    content: None,
+                    status: OperationStatus::Success,
+                }
+            }
+            Err(err) => {
+                MessageResponse {
+                    content: Some(Bson::String(err.to_string())),
+                    status: OperationStatus::Failure,
+                }
+            }
+        };
+        Some(rsp)
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{run, test_store};
+
+    #[tokio::test]
+    async fn jsonset_then_jsonget_follows_the_dotted_path() {
+        let store = test_store();
+        store.write().await.set("doc".to_string(), r#"{"user":{"addresses":[{"city":"Berlin"}]}}"#.to_string()).unwrap();
+
+        let rsp = run(&mut JsonSetCommand {}, &store, bson::doc! { "key": "doc", "path": "user.addresses.0.city", "value": r#""Hamburg""# }).await.unwrap();
+        assert_eq!(rsp.status, OperationStatus::Success);
+        let rsp = run(&mut JsonSetCommand {}, &store, bson::doc! { "key": "doc", "path": "user.name", "value": r#""Ada""# }).await.unwrap();
+        assert_eq!(rsp.status, OperationStatus::Success);
+        let rsp = run(&mut JsonGetCommand {}, &store, bson::doc! { "key": "doc", "path": "user.addresses.0.city" }).await.unwrap();
+        assert_eq!(rsp, MessageResponse { content: Some(Bson::String(r#""Hamburg""#.to_string())), status: OperationStatus::Success });
+        let rsp = run(&mut JsonGetCommand {}, &store, bson::doc! { "key": "doc", "path": "user.name" }).await.unwrap();
+        assert_eq!(rsp.content, Some(Bson::String(r#""Ada""#.to_string())));
+    }
+
+    #[tokio::test]
+    async fn json_paths_that_dont_resolve_are_not_found() {
+        let store = test_store();
+        store.write().await.set("doc".to_string(), r#"{"list":[1],"scalar":1}"#.to_string()).unwrap();
+        store.write().await.set("text".to_string(), "not json".to_string()).unwrap();
+
+        for path in ["missing", "list.1", "scalar.field"] {
+            let rsp = run(&mut JsonGetCommand {}, &store, bson::doc! { "key": "doc", "path": path }).await.unwrap();
+            assert_eq!(rsp.status, OperationStatus::NotFound, "{}", path);
+        }
+        for path in ["list.1", "scalar.field"] {
+            let rsp = run(&mut JsonSetCommand {}, &store, bson::doc! { "key": "doc", "path": path, "value": "2" }).await.unwrap();
+            assert_eq!(rsp.status, OperationStatus::NotFound, "{}", path);
+        }
+        let rsp = run(&mut JsonSetCommand {}, &store, bson::doc! { "key": "missing", "path": "", "value": "2" }).await.unwrap();
+        assert_eq!(rsp.status, OperationStatus::NotFound);
+        let rsp = run(&mut JsonGetCommand {}, &store, bson::doc! { "key": "text", "path": "" }).await.unwrap();
+        assert_eq!(rsp.status, OperationStatus::TypeError);
+        assert_eq!(store.read().await.get("doc"), Some(&r#"{"list":[1],"scalar":1}"#.to_string()));
+    }
+}
@@ -0,0 +1,675 @@
+use std::sync::Arc;
+use std::time::Duration;
+use async_trait::async_trait;
+use bson::{Bson, Document};
+use tokio::sync::RwLock;
+use common::command_input::{ExistsCommandInput, ExpireCommandInput, KeyInfoCommandInput, KeyPageCommandInput, KeysByTagCommandInput, KeysOfTypeCommandInput, MemoryUsageCommandInput, PersistCommandInput, ProtectCommandInput, RecentlyModifiedCommandInput, RenameTypedCommandInput, TagCommandInput, TtlCommandInput, TypeCommandInput, UnprotectCommandInput, UntagCommandInput};
+use common::connection::Connection;
+use common::message::{Message, MessageResponse, OperationStatus};
+use crate::commands::{Command, error_response, key_too_long, protected};
+use crate::store::{ErrorType, Expirable, KeyAble, Kind, Store};
+
+pub struct KeyInfoCommand {}
+
+#[async_trait]
+impl Command for KeyInfoCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let args: KeyInfoCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        let store = store.read().await;
+        let info = store.key_types(args.keys).into_iter()
+            .map(|(key, name)| (key, name.map_or(Bson::Null, |x| Bson::String(x.to_string()))))
+            .collect::<Document>();
+        Some(MessageResponse {
+            content: Some(Bson::Document(info)),
+            status: OperationStatus::Success,
+        })
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+pub struct MemoryUsageCommand {}
+
+#[async_trait]
+impl Command for MemoryUsageCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let args: MemoryUsageCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        let store = store.read().await;
+        let rsp = match store.memory_usage(&args.key) {
+            Some(bytes) => {
+                MessageResponse {
+                    content: Some(Bson::Int64(bytes as i64)),
+                    status: OperationStatus::Success,
+                }
+            }
+            None => {
+                MessageResponse {
+                    content: None,
+                    status: OperationStatus::NotFound,
+                }
+            }
+        };
+        Some(rsp)
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+/// Counts the keys per size bucket, to see whether the store holds many small values or a few large ones
+pub struct SizeHistogramCommand {}
+
+#[async_trait]
+impl Command for SizeHistogramCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, _args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let histogram = store.read().await.size_histogram().into_iter()
+            .map(|(bucket, count)| (bucket.to_string(), Bson::Int64(count as i64)))
+            .collect::<Document>();
+        Some(MessageResponse {
+            content: Some(Bson::Document(histogram)),
+            status: OperationStatus::Success,
+        })
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+/// Tags are removed together with the key, overwriting the key keeps them
+pub struct TagCommand {}
+
+#[async_trait]
+impl Command for TagCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let args: TagCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        let mut store = store.write().await;
+        let rsp = match store.tag(&args.key, args.tags) {
+            Some(added) => {
+                MessageResponse {
+                    content: Some(Bson::Int64(added as i64)),
+                    status: OperationStatus::Success,
+                }
+            }
+            None => {
+                MessageResponse {
+                    content: None,
+                    status: OperationStatus::NotFound,
+                }
+            }
+        };
+        Some(rsp)
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+pub struct UntagCommand {}
+
+#[async_trait]
+impl Command for UntagCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let args: UntagCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        let mut store = store.write().await;
+        let removed = store.untag(&args.key, args.tags);
+        Some(MessageResponse {
+            content: Some(Bson::Int64(removed as i64)),
+            status: OperationStatus::Success,
+        })
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+pub struct KeysByTagCommand {}
+
+#[async_trait]
+impl Command for KeysByTagCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let args: KeysByTagCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        let store = store.read().await;
+        let keys = store.keys_by_tag(&args.tag);
+        Some(MessageResponse {
+            content: Some(Bson::Array(keys.into_iter().map(Bson::String).collect())),
+            status: OperationStatus::Success,
+        })
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+/// The keys of one type, optionally filtered by a glob, sorted. Unknown types fail
+pub struct KeysOfTypeCommand {}
+
+#[async_trait]
+impl Command for KeysOfTypeCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let args: KeysOfTypeCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        let Some(kind) = Kind::from_name(&args.type_name) else {
+            return Some(MessageResponse {
+                content: Some(Bson::String(format!("Unknown type {}, expected string, hash or list", args.type_name))),
+                status: OperationStatus::Failure,
+            });
+        };
+        let store = store.read().await;
+        let keys = store.keys_of_type(kind, args.pattern.as_deref());
+        Some(MessageResponse {
+            content: Some(Bson::Array(keys.into_iter().map(Bson::String).collect())),
+            status: OperationStatus::Success,
+        })
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+/// The most recently written keys, newest first, with the time of their last write
+pub struct RecentlyModifiedCommand {}
+
+#[async_trait]
+impl Command for RecentlyModifiedCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let args: RecentlyModifiedCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        let store = store.read().await;
+        let keys = store.recently_modified(args.count).into_iter()
+            .map(|(key, modified)| Bson::Document(bson::doc! {
+                "key": key,
+                "last_modified": bson::DateTime::from_millis(modified.timestamp_millis()),
+            }))
+            .collect();
+        Some(MessageResponse {
+            content: Some(Bson::Array(keys)),
+            status: OperationStatus::Success,
+        })
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+/// One page of the keys, sorted by name, size or last write, for browsing the keyspace. Also returns the total number of keys,
+/// so a UI knows how many pages there are. The sort is done on every call, pages are only stable while the keys don't change
+pub struct KeyPageCommand {}
+
+#[async_trait]
+impl Command for KeyPageCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let args: KeyPageCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        let kind = match args.type_name {
+            None => None,
+            Some(type_name) => match Kind::from_name(&type_name) {
+                Some(kind) => Some(kind),
+                None => {
+                    return Some(MessageResponse {
+                        content: Some(Bson::String(format!("Unknown type {}, expected string, hash or list", type_name))),
+                        status: OperationStatus::Failure,
+                    });
+                }
+            },
+        };
+        let store = store.read().await;
+        let (page, total) = store.key_page(kind, args.order, args.offset, args.count);
+        let keys: Vec<Bson> = page.into_iter()
+            .map(|entry| Bson::Document(bson::doc! {
+                "key": entry.key,
+                "type": entry.kind.name(),
+                "size": entry.size as i64,
+                "last_modified": entry.last_modified.map(|modified| bson::DateTime::from_millis(modified.timestamp_millis())),
+            }))
+            .collect();
+        Some(MessageResponse {
+            content: Some(Bson::Document(bson::doc! {
+                "keys": keys,
+                "total": total as i64,
+            })),
+            status: OperationStatus::Success,
+        })
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+/// Makes SET, DELETE and the other commands replacing or deleting the string fail with NotAllowed, until UNPROTECT.
+/// Guards configuration like keys against accidental changes. NotFound if the key doesn't exist, TypeError if it isn't a string
+pub struct ProtectCommand {}
+
+#[async_trait]
+impl Command for ProtectCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let args: ProtectCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        let status = match store.write().await.set_protected(&args.key, true) {
+            Ok(true) => OperationStatus::Success,
+            Ok(false) => OperationStatus::NotFound,
+            Err(err) => return Some(error_response(err)),
+        };
+        Some(MessageResponse {
+            content: None,
+            status,
+        })
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+/// Removes the protection of PROTECT. NotFound if the key doesn't exist
+pub struct UnprotectCommand {}
+
+#[async_trait]
+impl Command for UnprotectCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let args: UnprotectCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        let status = match store.write().await.set_protected(&args.key, false) {
+            Ok(true) => OperationStatus::Success,
+            Ok(false) => OperationStatus::NotFound,
+            Err(err) => return Some(error_response(err)),
+        };
+        Some(MessageResponse {
+            content: None,
+            status,
+        })
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+/// Removes the key once ttl_secs passed. Until then it behaves like any other key, writes keep the expiry,
+/// only replacing it like SET does removes it. NotFound if the key doesn't exist
+pub struct ExpireCommand {}
+
+#[async_trait]
+impl Command for ExpireCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let args: ExpireCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        let mut store = store.write().await;
+        if let Some(rsp) = protected(&store, &args.key) {
+            return Some(rsp);
+        }
+        let status = if store.expire(&args.key, Duration::from_secs(args.ttl_secs)) {
+            OperationStatus::Success
+        } else {
+            OperationStatus::NotFound
+        };
+        Some(MessageResponse {
+            content: None,
+            status,
+        })
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+/// The seconds left until the key expires, rounded up. NotFound if the key doesn't exist or doesn't expire
+pub struct TtlCommand {}
+
+#[async_trait]
+impl Command for TtlCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let args: TtlCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        let rsp = match store.read().await.ttl(&args.key) {
+            Some(ttl) => {
+                MessageResponse {
+                    content: Some(Bson::Int64(ttl.as_millis().div_ceil(1000) as i64)),
+                    status: OperationStatus::Success,
+                }
+            }
+            None => {
+                MessageResponse {
+                    content: None,
+                    status: OperationStatus::NotFound,
+                }
+            }
+        };
+        Some(rsp)
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+/// Removes the expiry of the key. NotFound if the key doesn't exist or doesn't expire
+pub struct PersistCommand {}
+
+#[async_trait]
+impl Command for PersistCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let args: PersistCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        let status = if store.write().await.persist(&args.key) {
+            OperationStatus::Success
+        } else {
+            OperationStatus::NotFound
+        };
+        Some(MessageResponse {
+            content: None,
+            status,
+        })
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+/// Renames source to destination, but only replaces destination if it holds the same type, so a hash can't be overwritten
+/// by a string by accident. TypeError otherwise, NotFound if source doesn't exist
+pub struct RenameTypedCommand {}
+
+#[async_trait]
+impl Command for RenameTypedCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let args: RenameTypedCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        let mut store = store.write().await;
+        if let Some(rsp) = key_too_long(&store, &args.destination) {
+            return Some(rsp);
+        }
+        for key in [&args.source, &args.destination] {
+            if let Some(rsp) = protected(&store, key) {
+                return Some(rsp);
+            }
+        }
+        let rsp = match store.rename_typed(&args.source, args.destination) {
+            Ok(true) => {
+                MessageResponse {
+                    content: None,
+                    status: OperationStatus::Success,
+                }
+            }
+            Ok(false) => {
+                MessageResponse {
+                    content: None,
+                    status: OperationStatus::NotFound,
+                }
+            }
+            Err(ErrorType::WrongType) => {
+                MessageResponse {
+                    content: Some(Bson::String(String::from("Destination holds another type than source"))),
+                    status: OperationStatus::TypeError,
+                }
+            }
+            Err(err) => {
+                MessageResponse {
+                    content: Some(Bson::String(err.to_string())),
+                    status: OperationStatus::Failure,
+                }
+            }
+        };
+        Some(rsp)
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+/// Whether the key exists, whatever type it holds. Users count too, they share the keyspace
+pub struct ExistsCommand {}
+
+#[async_trait]
+impl Command for ExistsCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let args: ExistsCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        Some(MessageResponse {
+            content: Some(Bson::Boolean(store.read().await.exists(&args.key))),
+            status: OperationStatus::Success,
+        })
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+/// The type the key holds as string, hash, list or user. NotFound if the key doesn't exist
+pub struct TypeCommand {}
+
+#[async_trait]
+impl Command for TypeCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let args: TypeCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        let rsp = match store.read().await.type_of(&args.key) {
+            Some(name) => {
+                MessageResponse {
+                    content: Some(Bson::String(name.to_string())),
+                    status: OperationStatus::Success,
+                }
+            }
+            None => {
+                MessageResponse {
+                    content: None,
+                    status: OperationStatus::NotFound,
+                }
+            }
+        };
+        Some(rsp)
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use common::command_input::{DeleteCommandInput, HRestoreCommandInput, SetCommandInput};
+    use super::*;
+    use crate::commands::{run, test_store, DeleteCommand, HRestoreCommand, SetCommand};
+    use crate::store::{HashMapAble, ListAble, StoreAble, UserAble};
+
+    fn set(key: &str, value: &str) -> SetCommandInput {
+        SetCommandInput { key: key.to_string(), value: value.to_string(), transform: None }
+    }
+
+    #[tokio::test]
+    async fn protected_key_resists_set_delete_and_hrestore() {
+        let store = test_store();
+        store.write().await.set("key".to_string(), "1".to_string()).unwrap();
+        let rsp = run(&mut ProtectCommand {}, &store, ProtectCommandInput { key: "key".to_string() }).await.unwrap();
+        assert_eq!(rsp.status, OperationStatus::Success);
+
+        let rsp = run(&mut SetCommand {}, &store, set("key", "2")).await.unwrap();
+        assert_eq!(rsp.status, OperationStatus::NotAllowed);
+        let rsp = run(&mut DeleteCommand {}, &store, DeleteCommandInput { key: "key".to_string() }).await.unwrap();
+        assert_eq!(rsp.status, OperationStatus::NotAllowed);
+        let restore = HRestoreCommandInput {
+            key: "key".to_string(),
+            value: HashMap::from([("a".to_string(), "1".to_string())]),
+            replace: true,
+        };
+        let rsp = run(&mut HRestoreCommand {}, &store, restore).await.unwrap();
+        assert_eq!(rsp.status, OperationStatus::NotAllowed);
+        assert_eq!(store.read().await.get("key"), Some(&"1".to_string()));
+
+        let rsp = run(&mut UnprotectCommand {}, &store, UnprotectCommandInput { key: "key".to_string() }).await.unwrap();
+        assert_eq!(rsp.status, OperationStatus::Success);
+        let rsp = run(&mut SetCommand {}, &store, set("key", "2")).await.unwrap();
+        assert_eq!(rsp.status, OperationStatus::Success);
+        assert_eq!(store.read().await.get("key"), Some(&"2".to_string()));
+
+        // The list and hash commands don't check the mark, so it can't be set on them
+        store.write().await.rpush("list".to_string(), vec!["a".to_string()]).unwrap();
+        store.write().await.hadd("hash".to_string(), "field".to_string(), "1".to_string()).unwrap();
+        for key in ["list", "hash"] {
+            let rsp = run(&mut ProtectCommand {}, &store, ProtectCommandInput { key: key.to_string() }).await.unwrap();
+            assert_eq!(rsp.status, OperationStatus::TypeError);
+            assert!(!store.read().await.is_protected(key));
+        }
+    }
+
+    #[tokio::test]
+    async fn protect_unknown_key_is_not_found() {
+        let store = test_store();
+        let rsp = run(&mut ProtectCommand {}, &store, ProtectCommandInput { key: "missing".to_string() }).await.unwrap();
+        assert_eq!(rsp.status, OperationStatus::NotFound);
+    }
+
+    /// A store with a key of every type: string, hash, list and the user alice
+    async fn store_with_every_type() -> Arc<RwLock<Store>> {
+        let store = test_store();
+        let mut locked = store.write().await;
+        locked.set("string".to_string(), "1".to_string()).unwrap();
+        locked.hadd("hash".to_string(), "field".to_string(), "1".to_string()).unwrap();
+        locked.rpush("list".to_string(), vec!["a".to_string()]).unwrap();
+        locked.user_add("alice", "password", None);
+        drop(locked);
+        store
+    }
+
+    #[tokio::test]
+    async fn exists_matches_every_type() {
+        let store = store_with_every_type().await;
+        for (key, expected) in [("string", true), ("hash", true), ("list", true), ("alice", true), ("missing", false)] {
+            let rsp = run(&mut ExistsCommand {}, &store, ExistsCommandInput { key: key.to_string() }).await.unwrap();
+            assert_eq!(rsp.status, OperationStatus::Success);
+            assert_eq!(rsp.content, Some(Bson::Boolean(expected)), "{}", key);
+        }
+    }
+
+    #[tokio::test]
+    async fn type_reports_every_type() {
+        let store = store_with_every_type().await;
+        for (key, expected) in [("string", "string"), ("hash", "hash"), ("list", "list"), ("alice", "user")] {
+            let rsp = run(&mut TypeCommand {}, &store, TypeCommandInput { key: key.to_string() }).await.unwrap();
+            assert_eq!(rsp.status, OperationStatus::Success);
+            assert_eq!(rsp.content, Some(Bson::String(expected.to_string())));
+        }
+        let rsp = run(&mut TypeCommand {}, &store, TypeCommandInput { key: "missing".to_string() }).await.unwrap();
+        assert_eq!(rsp.status, OperationStatus::NotFound);
+    }
+
+    #[tokio::test]
+    async fn keyinfo_still_hides_users() {
+        let store = store_with_every_type().await;
+        let input = KeyInfoCommandInput { keys: vec!["string".to_string(), "alice".to_string()] };
+        let rsp = run(&mut KeyInfoCommand {}, &store, input).await.unwrap();
+        assert_eq!(rsp.content, Some(Bson::Document(bson::doc! { "string": "string", "alice": Bson::Null })));
+    }
+
+    #[tokio::test]
+    async fn renametyped_answers_type_error_and_respects_protect() {
+        let store = store_with_every_type().await;
+        let rename = |source: &str, destination: &str| RenameTypedCommandInput {
+            source: source.to_string(),
+            destination: destination.to_string(),
+        };
+        let rsp = run(&mut RenameTypedCommand {}, &store, rename("list", "string")).await.unwrap();
+        assert_eq!(rsp.status, OperationStatus::TypeError);
+        store.write().await.set_protected("string", true).unwrap();
+        let rsp = run(&mut RenameTypedCommand {}, &store, rename("string", "other")).await.unwrap();
+        assert_eq!(rsp.status, OperationStatus::NotAllowed);
+        let rsp = run(&mut RenameTypedCommand {}, &store, rename("hash", "other")).await.unwrap();
+        assert_eq!(rsp.status, OperationStatus::Success);
+    }
+
+    #[tokio::test]
+    async fn keyinfo_reports_the_type_of_every_key() {
+        let store = store_with_every_type().await;
+        let keys = ["string", "hash", "list", "missing"].map(String::from).to_vec();
+        let rsp = run(&mut KeyInfoCommand {}, &store, KeyInfoCommandInput { keys }).await.unwrap();
+        assert_eq!(rsp.status, OperationStatus::Success);
+        assert_eq!(rsp.content, Some(Bson::Document(bson::doc! { "string": "string", "hash": "hash", "list": "list", "missing": Bson::Null })));
+    }
+
+
+    #[tokio::test]
+    async fn expired_keys_lose_protection_version_and_recency() {
+        let store = test_store();
+        {
+            let mut store = store.write().await;
+            store.set("key".to_string(), "1".to_string()).unwrap();
+            store.set_protected("key", true).unwrap();
+            store.expire("key", std::time::Duration::ZERO);
+            assert!(!store.is_protected("key"));
+            assert_eq!(store.version("key"), 0);
+            assert!(store.recently_modified(10).is_empty());
+        }
+        let rsp = run(&mut UnprotectCommand {}, &store, UnprotectCommandInput { key: "key".to_string() }).await.unwrap();
+        assert_eq!(rsp.status, OperationStatus::NotFound);
+        let rsp = run(&mut SetCommand {}, &store, set("key", "2")).await.unwrap();
+        assert_eq!(rsp.status, OperationStatus::Success);
+        assert!(!store.read().await.is_protected("key"));
+    }
+}
@@ -1,12 +1,12 @@
 use std::sync::Arc;
 use async_trait::async_trait;
-use bson::Bson;
+use bson::{Bson, Document};
 use tokio::sync::RwLock;
-use common::command_input::{LIndexCommandInput, LLenCommandInput, LMoveCommandInput, LPopCommandInput, LPosCommandInput, LPushCommandInput, LPushxCommandInput, LRangeCommandInput, LRemCommandInput, LSetCommandInput, LTrimCommandInput, RPopCommandInput, RPushCommandInput, RPushxCommandInput};
+use common::command_input::{DrainCommandInput, LDiffCommandInput, LGetCommandInput, LIndexCommandInput, LInterCommandInput, LLenCommandInput, LMoveCommandInput, LMultiLenCommandInput, LPopCommandInput, LPopToListCommandInput, LPosCommandInput, LPushCommandInput, LPushUniqueCommandInput, LPushxCommandInput, LRangeCommandInput, LReplaceCommandInput, LRemCommandInput, LRotateCommandInput, LSetCommandInput, LTrimCommandInput, RPopCommandInput, RPushCommandInput, RPushxCommandInput};
 use common::connection::Connection;
 use common::message::{Message, MessageResponse, OperationStatus};
-use crate::commands::Command;
-use crate::store::{Store, ListAble};
+use crate::commands::{Command, key_too_long, wrong_type};
+use crate::store::{ErrorType, Kind, ListAble, Store};
 
 pub struct LlenCommand {}
 
@@ -21,6 +21,9 @@ impl Command for LlenCommand {
             Ok(doc) => doc
         };
 
+        if let Some(rsp) = wrong_type(&store, &args.list, Kind::List) {
+            return Some(rsp);
+        }
         let rsp = MessageResponse {
             content: Some(Bson::String(store.llen(args.list).to_string())),
             status: OperationStatus::Success,
@@ -31,6 +34,37 @@ impl Command for LlenCommand {
     async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
 }
 
+/// LLEN for several lists at once, answered with a document of key to length
+pub struct LMultiLenCommand {}
+
+#[async_trait]
+impl Command for LMultiLenCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let store = store.read().await;
+        let args: LMultiLenCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        if let Some(rsp) = args.lists.iter().find_map(|list| wrong_type(&store, list, Kind::List)) {
+            return Some(rsp);
+        }
+        let lengths = args.lists.into_iter().map(|list| {
+            let len = store.llen(list.clone());
+            (list, Bson::Int64(len as i64))
+        }).collect::<Document>();
+        let rsp = MessageResponse {
+            content: Some(Bson::Document(lengths)),
+            status: OperationStatus::Success,
+        };
+        Some(rsp)
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
 pub struct LindexCommand {}
 
 #[async_trait]
@@ -44,6 +78,9 @@ impl Command for LindexCommand {
             Ok(doc) => doc
         };
 
+        if let Some(rsp) = wrong_type(&store, &args.list, Kind::List) {
+            return Some(rsp);
+        }
         let rsp = match store.lindex(args.list, args.key) {
             None => {
                 MessageResponse {
@@ -64,6 +101,176 @@ impl Command for LindexCommand {
     async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
 }
 
+pub struct LGetCommand {}
+
+#[async_trait]
+impl Command for LGetCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let store = store.read().await;
+        let args: LGetCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        if let Some(rsp) = wrong_type(&store, &args.list, Kind::List) {
+            return Some(rsp);
+        }
+        let rsp = match store.lget(args.list, args.index) {
+            None => {
+                MessageResponse {
+                    content: None,
+                    status: OperationStatus::NotFound,
+                }
+            }
+            Some(val) => {
+                MessageResponse {
+                    content: Some(Bson::String(val.clone())),
+                    status: OperationStatus::Success,
+                }
+            }
+        };
+        Some(rsp)
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+pub struct LInterCommand {}
+
+#[async_trait]
+impl Command for LInterCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let store = store.read().await;
+        let args: LInterCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        if let Some(rsp) = wrong_type(&store, &args.first, Kind::List).or_else(|| wrong_type(&store, &args.second, Kind::List)) {
+            return Some(rsp);
+        }
+        let rsp = match store.linter(args.first, args.second, args.dedup) {
+            Ok(common) => {
+                MessageResponse {
+                    content: Some(Bson::Array(common.into_iter().map(Bson::String).collect())),
+                    status: OperationStatus::Success,
+                }
+            }
+            Err(err) => {
+                MessageResponse {
+                    content: Some(Bson::String(err.to_string())),
+                    status: OperationStatus::Failure,
+                }
+            }
+        };
+        Some(rsp)
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+pub struct LDiffCommand {}
+
+#[async_trait]
+impl Command for LDiffCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let store = store.read().await;
+        let args: LDiffCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        if let Some(rsp) = wrong_type(&store, &args.first, Kind::List).or_else(|| wrong_type(&store, &args.second, Kind::List)) {
+            return Some(rsp);
+        }
+        let rsp = match store.ldiff(args.first, args.second) {
+            Ok(diff) => {
+                MessageResponse {
+                    content: Some(Bson::Array(diff.into_iter().map(Bson::String).collect())),
+                    status: OperationStatus::Success,
+                }
+            }
+            Err(err) => {
+                MessageResponse {
+                    content: Some(Bson::String(err.to_string())),
+                    status: OperationStatus::Failure,
+                }
+            }
+        };
+        Some(rsp)
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+pub struct LRotateCommand {}
+
+#[async_trait]
+impl Command for LRotateCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let mut store = store.write().await;
+        let args: LRotateCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        if let Some(rsp) = wrong_type(&store, &args.list, Kind::List) {
+            return Some(rsp);
+        }
+        let rsp = match store.lrotate(args.list, args.left_to_right.unwrap_or(true)) {
+            None => {
+                MessageResponse {
+                    content: None,
+                    status: OperationStatus::NotFound,
+                }
+            }
+            Some(val) => {
+                MessageResponse {
+                    content: Some(Bson::String(val)),
+                    status: OperationStatus::Success,
+                }
+            }
+        };
+        Some(rsp)
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+pub struct DrainCommand {}
+
+#[async_trait]
+impl Command for DrainCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let mut store = store.write().await;
+        let args: DrainCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        if let Some(rsp) = wrong_type(&store, &args.list, Kind::List) {
+            return Some(rsp);
+        }
+        let rsp = MessageResponse {
+            content: Some(Bson::Array(store.drain(args.list).into_iter().map(Bson::String).collect())),
+            status: OperationStatus::Success,
+        };
+        Some(rsp)
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
 pub struct LmoveCommand {}
 
 #[async_trait]
@@ -77,6 +284,12 @@ impl Command for LmoveCommand {
             Ok(doc) => doc
         };
 
+        if let Some(rsp) = key_too_long(&store, &args.dest) {
+            return Some(rsp);
+        }
+        if let Some(rsp) = wrong_type(&store, &args.src, Kind::List).or_else(|| wrong_type(&store, &args.dest, Kind::List)) {
+            return Some(rsp);
+        }
         let rsp = match store.lmove(args.src, args.dest, args.left_right, args.right_left) {
             None => {
                 MessageResponse {
@@ -110,6 +323,9 @@ impl Command for LpopCommand {
             Ok(doc) => doc
         };
 
+        if let Some(rsp) = wrong_type(&store, &args.list, Kind::List) {
+            return Some(rsp);
+        }
         let rsp = match store.lpop(args.list, args.count) {
             Ok(result) => {
                 match result {
@@ -153,6 +369,9 @@ impl Command for LposCommand {
             Ok(doc) => doc
         };
 
+        if let Some(rsp) = wrong_type(&store, &args.list, Kind::List) {
+            return Some(rsp);
+        }
         let rsp = match store.lpos(args.list, args.value, args.rank, args.count, args.max_len) {
             Ok(result) => {
                 match result {
@@ -196,6 +415,12 @@ impl Command for LpushCommand {
             Ok(doc) => doc
         };
 
+        if let Some(rsp) = wrong_type(&store, &args.list, Kind::List) {
+            return Some(rsp);
+        }
+        if let Some(rsp) = key_too_long(&store, &args.list) {
+            return Some(rsp);
+        }
         let rsp = match store.lpush(args.list.to_string(), args.values) {
             Ok(_) => {
                 MessageResponse {
@@ -203,6 +428,57 @@ impl Command for LpushCommand {
                     status: OperationStatus::Success,
                 }
             }
+            Err(err @ ErrorType::SizeLimit(..)) => {
+                MessageResponse {
+                    content: Some(Bson::String(err.to_string())),
+                    status: OperationStatus::Failure,
+                }
+            }
+            Err(_err) => {
+                MessageResponse {
+                    content: None,
+                    status: OperationStatus::OutOfMemory,
+                }
+            }
+        };
+        Some(rsp)
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+pub struct LPushUniqueCommand {}
+
+#[async_trait]
+impl Command for LPushUniqueCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let mut store = store.write().await;
+        let args: LPushUniqueCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        if let Some(rsp) = wrong_type(&store, &args.list, Kind::List) {
+            return Some(rsp);
+        }
+        if let Some(rsp) = key_too_long(&store, &args.list) {
+            return Some(rsp);
+        }
+        let rsp = match store.lpush_unique(args.list, args.values, args.max_len) {
+            Ok(len) => {
+                MessageResponse {
+                    content: Some(Bson::Int64(len as i64)),
+                    status: OperationStatus::Success,
+                }
+            }
+            Err(err @ ErrorType::SizeLimit(..)) => {
+                MessageResponse {
+                    content: Some(Bson::String(err.to_string())),
+                    status: OperationStatus::Failure,
+                }
+            }
             Err(_err) => {
                 MessageResponse {
                     content: None,
@@ -229,6 +505,9 @@ impl Command for LpushxCommand {
             Ok(doc) => doc
         };
 
+        if let Some(rsp) = wrong_type(&store, &args.list, Kind::List) {
+            return Some(rsp);
+        }
         let rsp = match store.lpushx(args.list.to_string(), args.values) {
             Ok(_) => {
                 MessageResponse {
@@ -236,6 +515,12 @@ impl Command for LpushxCommand {
                     status: OperationStatus::Success,
                 }
             }
+            Err(err @ ErrorType::SizeLimit(..)) => {
+                MessageResponse {
+                    content: Some(Bson::String(err.to_string())),
+                    status: OperationStatus::Failure,
+                }
+            }
             Err(_err) => {
                 MessageResponse {
                     content: None,
@@ -262,17 +547,20 @@ impl Command for LrangeCommand {
             Ok(doc) => doc
         };
 
+        if let Some(rsp) = wrong_type(&store, &args.list, Kind::List) {
+            return Some(rsp);
+        }
         let rsp = match store.lrange(args.list, args.start, args.stop) {
             Ok(result) => {
                 MessageResponse {
                     content: Some(Bson::Array(result.iter().map(|x| Bson::String(x.to_string())).collect())),
-                    status: OperationStatus::Failure,
+                    status: OperationStatus::Success,
                 }
             }
-            Err(_err) => {
+            Err(err) => {
                 MessageResponse {
-                    content: None,
-                    status: OperationStatus::Success,
+                    content: Some(Bson::String(err.to_string())),
+                    status: OperationStatus::OutOfMemory,
                 }
             }
         };
@@ -295,6 +583,9 @@ impl Command for LremCommand {
             Ok(doc) => doc
         };
 
+        if let Some(rsp) = wrong_type(&store, &args.list, Kind::List) {
+            return Some(rsp);
+        }
         let rsp = MessageResponse {
             content: Some(Bson::Int64(store.lrem(args.list, args.count, args.value) as i64)),
             status: OperationStatus::Success,
@@ -318,6 +609,9 @@ impl Command for LsetCommand {
             Ok(doc) => doc
         };
 
+        if let Some(rsp) = wrong_type(&store, &args.list, Kind::List) {
+            return Some(rsp);
+        }
         let rsp = MessageResponse {
             content: Some(Bson::Boolean(store.lset(args.list, args.index, args.value))),
             status: OperationStatus::Success,
@@ -341,6 +635,9 @@ impl Command for LtrimCommand {
             Ok(doc) => doc
         };
 
+        if let Some(rsp) = wrong_type(&store, &args.list, Kind::List) {
+            return Some(rsp);
+        }
         let rsp = if store.ltrim(args.list, args.start, args.stop) {
             MessageResponse {
                 content: None,
@@ -371,6 +668,9 @@ impl Command for RpopCommand {
             Ok(doc) => doc
         };
 
+        if let Some(rsp) = wrong_type(&store, &args.list, Kind::List) {
+            return Some(rsp);
+        }
         let rsp = match store.rpop(args.list, args.count) {
             None => {
                 MessageResponse {
@@ -404,7 +704,19 @@ impl Command for RpushCommand {
             Ok(doc) => doc
         };
 
+        if let Some(rsp) = wrong_type(&store, &args.list, Kind::List) {
+            return Some(rsp);
+        }
+        if let Some(rsp) = key_too_long(&store, &args.list) {
+            return Some(rsp);
+        }
         let rsp = match store.rpush(args.list, args.values) {
+            Err(err @ ErrorType::SizeLimit(..)) => {
+                MessageResponse {
+                    content: Some(Bson::String(err.to_string())),
+                    status: OperationStatus::Failure,
+                }
+            }
             Err(_err) => {
                 MessageResponse {
                     content: None,
@@ -437,7 +749,16 @@ impl Command for RpushxCommand {
             Ok(doc) => doc
         };
 
+        if let Some(rsp) = wrong_type(&store, &args.list, Kind::List) {
+            return Some(rsp);
+        }
         let rsp = match store.rpushx(args.list, args.values) {
+            Err(err @ ErrorType::SizeLimit(..)) => {
+                MessageResponse {
+                    content: Some(Bson::String(err.to_string())),
+                    status: OperationStatus::Failure,
+                }
+            }
             Err(_err) => {
                 MessageResponse {
                     content: None,
@@ -456,3 +777,217 @@ impl Command for RpushxCommand {
 
     async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
 }
+
+/// Unlike LMOVE, the destination is created if it doesn't exist and always pushed onto its tail. Both happen under one write lock
+pub struct LPopToListCommand {}
+
+#[async_trait]
+impl Command for LPopToListCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let mut store = store.write().await;
+        let args: LPopToListCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        if let Some(rsp) = key_too_long(&store, &args.destination) {
+            return Some(rsp);
+        }
+        if let Some(rsp) = wrong_type(&store, &args.source, Kind::List).or_else(|| wrong_type(&store, &args.destination, Kind::List)) {
+            return Some(rsp);
+        }
+        let rsp = match store.lpop_to_list(args.source, args.destination, args.from) {
+            Ok(None) => {
+                MessageResponse {
+                    content: None,
+                    status: OperationStatus::NotFound,
+                }
+            }
+            Ok(Some(val)) => {
+                MessageResponse {
+                    content: Some(Bson::String(val)),
+                    status: OperationStatus::Success,
+                }
+            }
+            Err(ErrorType::WrongType) => {
+                MessageResponse {
+                    content: Some(Bson::String(String::from("A key does not contain a list"))),
+                    status: OperationStatus::TypeError,
+                }
+            }
+            Err(ErrorType::TryReserveError(err)) => {
+                MessageResponse {
+                    content: Some(Bson::String(err.to_string())),
+                    status: OperationStatus::OutOfMemory,
+                }
+            }
+            Err(err) => {
+                MessageResponse {
+                    content: Some(Bson::String(err.to_string())),
+                    status: OperationStatus::Failure,
+                }
+            }
+        };
+        Some(rsp)
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+/// Replaces a list with new contents under one write lock, so readers see either the old or the new list, never an empty one
+pub struct LReplaceCommand {}
+
+#[async_trait]
+impl Command for LReplaceCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let mut store = store.write().await;
+        let args: LReplaceCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        if let Some(rsp) = key_too_long(&store, &args.list) {
+            return Some(rsp);
+        }
+        if let Some(rsp) = wrong_type(&store, &args.list, Kind::List) {
+            return Some(rsp);
+        }
+        let rsp = match store.lreplace(args.list, args.values) {
+            Ok(len) => {
+                MessageResponse {
+                    content: Some(Bson::Int64(len as i64)),
+                    status: OperationStatus::Success,
+                }
+            }
+            Err(ErrorType::WrongType) => {
+                MessageResponse {
+                    content: Some(Bson::String(String::from("Key does not contain a list"))),
+                    status: OperationStatus::TypeError,
+                }
+            }
+            Err(ErrorType::TryReserveError(err)) => {
+                MessageResponse {
+                    content: Some(Bson::String(err.to_string())),
+                    status: OperationStatus::OutOfMemory,
+                }
+            }
+            Err(err) => {
+                MessageResponse {
+                    content: Some(Bson::String(err.to_string())),
+                    status: OperationStatus::Failure,
+                }
+            }
+        };
+        Some(rsp)
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::command_input::ListEnd;
+    use crate::commands::{run, test_store};
+    use crate::store::StoreAble;
+
+    /// A store with a string at "string" and the list ["a", "b"] at "list", strict types switched on or off
+    async fn store_with_string(strict: bool) -> Arc<RwLock<Store>> {
+        let store = test_store();
+        let mut locked = store.write().await;
+        locked.set_strict_types(strict);
+        locked.set("string".to_string(), "1".to_string()).unwrap();
+        locked.rpush("list".to_string(), vec!["a".to_string(), "b".to_string()]).unwrap();
+        drop(locked);
+        store
+    }
+
+    #[tokio::test]
+    async fn lmultilen_on_string() {
+        for strict in [false, true] {
+            let store = store_with_string(strict).await;
+            let input = LMultiLenCommandInput { lists: vec!["list".to_string(), "string".to_string()] };
+            let rsp = run(&mut LMultiLenCommand {}, &store, input).await.unwrap();
+            if strict {
+                assert_eq!(rsp.status, OperationStatus::TypeError);
+            } else {
+                let lengths = bson::doc! { "list": 2_i64, "string": 0_i64 };
+                assert_eq!(rsp.content, Some(Bson::Document(lengths)));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn lmove_from_string() {
+        for strict in [false, true] {
+            let store = store_with_string(strict).await;
+            let input = LMoveCommandInput {
+                src: "string".to_string(),
+                dest: "list".to_string(),
+                left_right: "LEFT".to_string(),
+                right_left: "RIGHT".to_string(),
+            };
+            let rsp = run(&mut LmoveCommand {}, &store, input).await.unwrap();
+            let expected = if strict { OperationStatus::TypeError } else { OperationStatus::Failure };
+            assert_eq!(rsp.status, expected);
+            assert_eq!(store.read().await.llen("list".to_string()), 2);
+        }
+    }
+
+    #[tokio::test]
+    async fn lpop_to_list_onto_string_fails_in_both_modes() {
+        for strict in [false, true] {
+            let store = store_with_string(strict).await;
+            let input = LPopToListCommandInput { source: "list".to_string(), destination: "string".to_string(), from: ListEnd::Right };
+            let rsp = run(&mut LPopToListCommand {}, &store, input).await.unwrap();
+            assert_eq!(rsp.status, OperationStatus::TypeError);
+            assert_eq!(store.read().await.llen("list".to_string()), 2);
+        }
+    }
+
+    #[tokio::test]
+    async fn lreplace_on_string_fails_in_both_modes() {
+        for strict in [false, true] {
+            let store = store_with_string(strict).await;
+            let input = LReplaceCommandInput { list: "string".to_string(), values: vec!["a".to_string()] };
+            let rsp = run(&mut LReplaceCommand {}, &store, input).await.unwrap();
+            assert_eq!(rsp.status, OperationStatus::TypeError);
+            assert_eq!(store.read().await.get("string"), Some(&"1".to_string()));
+        }
+    }
+
+    #[tokio::test]
+    async fn lrange_populated_list() {
+        let store = store_with_string(false).await;
+        let input = LRangeCommandInput { list: "list".to_string(), start: 0, stop: 2 };
+        let rsp = run(&mut LrangeCommand {}, &store, input).await.unwrap();
+        assert_eq!(rsp.status, OperationStatus::Success);
+        assert_eq!(rsp.content, Some(Bson::Array(vec![Bson::String("a".to_string()), Bson::String("b".to_string())])));
+    }
+
+    #[tokio::test]
+    async fn lrange_missing_key_is_empty() {
+        let store = test_store();
+        let input = LRangeCommandInput { list: "missing".to_string(), start: -100, stop: 10 };
+        let rsp = run(&mut LrangeCommand {}, &store, input).await.unwrap();
+        assert_eq!(rsp.status, OperationStatus::Success);
+        assert_eq!(rsp.content, Some(Bson::Array(vec![])));
+    }
+
+    #[tokio::test]
+    async fn lmultilen_answers_in_request_order() {
+        let store = store_with_string(true).await;
+        store.write().await.rpush("other".to_string(), vec!["a".to_string(), "b".to_string(), "c".to_string()]).unwrap();
+        let lists = ["other", "missing", "list"].map(String::from).to_vec();
+        let rsp = run(&mut LMultiLenCommand {}, &store, LMultiLenCommandInput { lists }).await.unwrap();
+        assert_eq!(rsp.status, OperationStatus::Success);
+        let Some(Bson::Document(lengths)) = rsp.content else { panic!("expected a document, got {:?}", rsp.content) };
+        assert_eq!(lengths.into_iter().collect::<Vec<_>>(), vec![
+            ("other".to_string(), Bson::Int64(3)), ("missing".to_string(), Bson::Int64(0)), ("list".to_string(), Bson::Int64(2)),
+        ]);
+    }
+}
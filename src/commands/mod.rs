@@ -3,20 +3,29 @@ use async_trait::async_trait;
 
 use tokio::sync::RwLock;
 
-use common::message::{Message, MessageResponse};
+use common::message::{Message, MessageResponse, OperationStatus};
 use common::connection::Connection;
 
-use crate::store::Store;
+use crate::store::{ErrorType, Kind, Store};
 
 pub use basic::{GetCommand};
 pub use basic::{SetCommand};
 pub use basic::{DeleteCommand};
+pub use basic::CompareSetCommand;
+pub use basic::MultiLockCommand;
+pub use basic::GetOrSetCommand;
+pub use basic::DecrDelCommand;
+pub use basic::AppendIfCommand;
+pub use basic::SetWithVersionCommand;
 pub use heartbeat::HeartbeatCommand;
 pub use acl::{AclListCommand};
 pub use acl::{AclSetCommand};
 pub use acl::{AclRemoveCommand};
+pub use acl::AclExportCommand;
+pub use acl::AclImportCommand;
 pub use connection::{LoginCommand};
 pub use connection::{KeyExchangeCommand};
+pub use connection::ReKeyCommand;
 
 pub use hashmap::HashMapGetCommand;
 pub use hashmap::HashMapGetAllCommand;
@@ -29,12 +38,84 @@ pub use hashmap::HashMapExistsCommand;
 pub use hashmap::HashMapIncrByCommand;
 pub use hashmap::HashMapStringLenCommand;
 pub use hashmap::HashMapUpsertCommand;
+pub use hashmap::HKeysMatchCommand;
+pub use hashmap::HGetSortedCommand;
+pub use hashmap::MultiHSetCommand;
+pub use hashmap::HDumpCommand;
+pub use hashmap::HRestoreCommand;
+pub use hashmap::HFilterCommand;
+pub use hashmap::HSwapCommand;
+pub use hashmap::HTopCommand;
+
+pub use list::LPushUniqueCommand;
+pub use list::DrainCommand;
+pub use list::LGetCommand;
+pub use list::LRotateCommand;
+pub use list::LInterCommand;
+pub use list::LDiffCommand;
+pub use list::LMultiLenCommand;
+pub use list::LReplaceCommand;
+pub use list::LPopToListCommand;
+pub use list::LlenCommand;
+pub use list::LindexCommand;
+pub use list::LmoveCommand;
+pub use list::LpopCommand;
+pub use list::LposCommand;
+pub use list::LpushCommand;
+pub use list::LpushxCommand;
+pub use list::LrangeCommand;
+pub use list::LremCommand;
+pub use list::LsetCommand;
+pub use list::LtrimCommand;
+pub use list::RpopCommand;
+pub use list::RpushCommand;
+pub use list::RpushxCommand;
 
 pub use user::UserRemoveCommand;
 
 pub use client::ClientIDCommand;
+pub use client::ConnStatsCommand;
+pub use client::SessionCommandsCommand;
+pub use client::SecurityInfoCommand;
+pub use client::CloseIdleCommand;
 
 pub use system::ShutdownCommand;
+pub use system::CommandListCommand;
+pub use system::TimeCommand;
+pub use system::CompactCommand;
+pub use system::DebugCheckCommand;
+pub use system::FeaturesCommand;
+pub use system::RoleCommand;
+pub use system::PauseWritesCommand;
+pub use system::ResumeWritesCommand;
+pub use system::DigestCommand;
+
+pub use json::JsonGetCommand;
+pub use json::JsonSetCommand;
+
+pub use slowlog::SlowLogGetCommand;
+pub use slowlog::SlowLogResetCommand;
+
+pub use keys::KeyInfoCommand;
+pub use keys::MemoryUsageCommand;
+pub use keys::TagCommand;
+pub use keys::UntagCommand;
+pub use keys::KeysByTagCommand;
+pub use keys::RecentlyModifiedCommand;
+pub use keys::KeysOfTypeCommand;
+pub use keys::ProtectCommand;
+pub use keys::UnprotectCommand;
+pub use keys::KeyPageCommand;
+pub use keys::SizeHistogramCommand;
+pub use keys::ExpireCommand;
+pub use keys::TtlCommand;
+pub use keys::PersistCommand;
+pub use keys::RenameTypedCommand;
+pub use keys::ExistsCommand;
+pub use keys::TypeCommand;
+
+pub use watch::WatchKeyCommand;
+pub use watch::UnwatchKeyCommand;
 
 mod basic;
 mod hashmap;
@@ -45,6 +126,10 @@ mod user;
 mod client;
 mod system;
 mod list;
+mod json;
+mod slowlog;
+mod keys;
+mod watch;
 
 #[async_trait]
 pub trait Command: Send {
@@ -56,4 +141,77 @@ pub trait Command: Send {
     /// Post hook for the command, like logging the command, or cleaning up state
     /// Or setting connection parameters based on the state
     async fn post_exec(&mut self, connection: &mut Connection, response: Option<&MessageResponse>);
-}
\ No newline at end of file
+}
+
+/// Returns a failure response if the key is longer than max_key_length allows. Checked by every command that can create a key
+pub(crate) fn key_too_long(store: &Store, key: &str) -> Option<MessageResponse> {
+    let max = store.max_key_length()?;
+    if key.len() <= max {
+        return None;
+    }
+    Some(MessageResponse {
+        content: Some(bson::Bson::String(format!("Key is {} bytes long, more than the limit of {}", key.len(), max))),
+        status: OperationStatus::Failure,
+    })
+}
+
+/// Returns the NotAllowed response if the key was protected with PROTECT. Checked by every command that replaces or deletes a string
+pub(crate) fn protected(store: &Store, key: &str) -> Option<MessageResponse> {
+    if !store.is_protected(key) {
+        return None;
+    }
+    Some(MessageResponse {
+        content: Some(bson::Bson::String(format!("Key {} is protected, UNPROTECT it first", key))),
+        status: OperationStatus::NotAllowed,
+    })
+}
+
+/// Returns the TypeError response if strict types are enabled and the key holds another type than the command works on
+pub(crate) fn wrong_type(store: &Store, key: &str, expected: Kind) -> Option<MessageResponse> {
+    if !store.is_wrong_type(key, expected) {
+        return None;
+    }
+    Some(MessageResponse {
+        content: Some(bson::Bson::String(format!("Key does not contain a {}", expected.name()))),
+        status: OperationStatus::TypeError,
+    })
+}
+
+/// The response for an error of the store. TypeError for values of another type, OutOfMemory if an allocation failed, otherwise Failure
+pub(crate) fn error_response(err: ErrorType) -> MessageResponse {
+    let status = match err {
+        ErrorType::WrongType => OperationStatus::TypeError,
+        ErrorType::TryReserveError(_) => OperationStatus::OutOfMemory,
+        _ => OperationStatus::Failure,
+    };
+    MessageResponse {
+        content: Some(bson::Bson::String(err.to_string())),
+        status,
+    }
+}
+
+/// Runs a command against the store like a connection would, without pre_exec and post_exec
+#[cfg(test)]
+pub(crate) async fn run(command: &mut dyn Command, store: &Arc<RwLock<Store>>, args: impl serde::Serialize) -> Option<MessageResponse> {
+    use common::message::MessageContent;
+    let message = Message::new(uuid::Uuid::nil(), MessageContent::Response(MessageResponse {
+        content: None,
+        status: OperationStatus::Success,
+    }));
+    command.execute(store.clone(), bson::to_bson(&args).unwrap(), &message).await
+}
+
+/// An empty store with default limits, shared like the server does
+#[cfg(test)]
+pub(crate) fn test_store() -> Arc<RwLock<Store>> {
+    Arc::new(RwLock::new(Store::new(false, crate::store::TypeLimits::default())))
+}
+
+/// A connection over loopback for commands that change connection state in post_exec, with the peer's end of the socket
+#[cfg(test)]
+pub(crate) async fn test_connection() -> (Connection, tokio::net::TcpStream) {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let peer = tokio::net::TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+    let (socket, _) = listener.accept().await.unwrap();
+    (Connection::new(socket, uuid::Uuid::new_v4(), 6), peer)
+}
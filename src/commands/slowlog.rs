@@ -0,0 +1,66 @@
+use std::sync::Arc;
+use async_trait::async_trait;
+use bson::Bson;
+use tokio::sync::{Mutex, RwLock};
+use common::command_input::SlowLogGetCommandInput;
+use common::connection::Connection;
+use common::message::{Message, MessageResponse, OperationStatus};
+use crate::commands::Command;
+use crate::slowlog::SlowLog;
+use crate::store::Store;
+
+pub struct SlowLogGetCommand {
+    slowlog: Arc<Mutex<SlowLog>>,
+}
+
+impl SlowLogGetCommand {
+    pub fn new(slowlog: Arc<Mutex<SlowLog>>) -> Self {
+        Self { slowlog }
+    }
+}
+
+#[async_trait]
+impl Command for SlowLogGetCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, _store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let args: SlowLogGetCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        let slowlog = self.slowlog.lock().await;
+        let entries = slowlog.entries(args.count).iter().map(|entry| Bson::Document(entry.to_document())).collect();
+        Some(MessageResponse {
+            content: Some(Bson::Array(entries)),
+            status: OperationStatus::Success,
+        })
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+pub struct SlowLogResetCommand {
+    slowlog: Arc<Mutex<SlowLog>>,
+}
+
+impl SlowLogResetCommand {
+    pub fn new(slowlog: Arc<Mutex<SlowLog>>) -> Self {
+        Self { slowlog }
+    }
+}
+
+#[async_trait]
+impl Command for SlowLogResetCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, _store: Arc<RwLock<Store>>, _args: Bson, _message: &Message) -> Option<MessageResponse> {
+        self.slowlog.lock().await.reset();
+        Some(MessageResponse {
+            content: None,
+            status: OperationStatus::Success,
+        })
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
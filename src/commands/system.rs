@@ -1,21 +1,315 @@
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use async_trait::async_trait;
-use bson::Bson;
-use tokio::sync::RwLock;
-use common::connection::Connection;
-use common::message::{Message, MessageResponse};
+use bson::{Bson, Document};
+use tokio::sync::{Notify, RwLock};
+use common::command::CommandID;
+use common::command_input::PauseWritesCommandInput;
+use common::connection::{Connection, FrameCapabilities};
+use common::message::{Message, MessageResponse, OperationStatus};
 use crate::commands::Command;
+use crate::config::Config;
+use crate::pause::MAX_WRITE_PAUSE;
 use crate::store::Store;
 
-pub struct ShutdownCommand {}
+/// Triggers the graceful shutdown. The listener stops accepting and every client gets notified before the server exits
+pub struct ShutdownCommand {
+    shutdown: Arc<Notify>,
+}
+
+impl ShutdownCommand {
+    pub fn new(shutdown: Arc<Notify>) -> Self {
+        Self { shutdown }
+    }
+}
 
 #[async_trait]
 impl Command for ShutdownCommand {
     async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
 
     async fn execute(&mut self, _store: Arc<RwLock<Store>>, _args: Bson, _message: &Message) -> Option<MessageResponse> {
-        std::process::exit(0);
+        log::info!("Shutdown requested");
+        // notify_one keeps the permit, even if the listener isn't waiting at the moment
+        self.shutdown.notify_one();
+        Some(MessageResponse {
+            content: None,
+            status: OperationStatus::Success,
+        })
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+/// Lists the commands registered on this server, so clients can tell what is supported before sending it
+pub struct CommandListCommand {
+    commands: Vec<CommandID>,
+}
+
+impl CommandListCommand {
+    pub fn new(mut commands: Vec<CommandID>) -> Self {
+        commands.sort();
+        Self { commands }
+    }
+}
+
+#[async_trait]
+impl Command for CommandListCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, _store: Arc<RwLock<Store>>, _args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let commands = self.commands.iter().map(|command| Bson::String(command.to_string())).collect::<Vec<Bson>>();
+        Some(MessageResponse {
+            content: Some(Bson::Array(commands)),
+            status: OperationStatus::Success,
+        })
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+/// The server's unix time as seconds and the microseconds within that second, so clients can use the server clock
+pub struct TimeCommand {}
+
+#[async_trait]
+impl Command for TimeCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, _store: Arc<RwLock<Store>>, _args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        Some(MessageResponse {
+            content: Some(Bson::Array(vec![Bson::Int64(now.as_secs() as i64), Bson::Int64(now.subsec_micros() as i64)])),
+            status: OperationStatus::Success,
+        })
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+/// Returns the memory the store doesn't use anymore to the allocator, see Store::compact. Holds the write lock while it runs
+pub struct CompactCommand {}
+
+#[async_trait]
+impl Command for CompactCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, _args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let freed = store.write().await.compact();
+        log::info!("Compacted the store, freed about {} bytes", freed);
+        Some(MessageResponse {
+            content: Some(Bson::Int64(freed as i64)),
+            status: OperationStatus::Success,
+        })
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+/// Reports broken store invariants, see Store::check_invariants. An empty array means the store is consistent
+pub struct DebugCheckCommand {}
+
+#[async_trait]
+impl Command for DebugCheckCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, _args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let violations = store.read().await.check_invariants();
+        for violation in &violations {
+            log::warn!("Store invariant violated: {}", violation);
+        }
+        Some(MessageResponse {
+            content: Some(Bson::Array(violations.into_iter().map(Bson::String).collect())),
+            status: OperationStatus::Success,
+        })
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+/// The server version, the protocol version and which optional features are available with the running config.
+/// Unlike the banner it's there for every connection, so clients can check compatibility before relying on something
+pub struct FeaturesCommand {
+    features: Document,
+}
+
+impl FeaturesCommand {
+    pub fn new(config: &Config) -> Self {
+        let features = bson::doc! {
+            "version": env!("CARGO_PKG_VERSION"),
+            "protocol": FrameCapabilities::current(false, false).version as i64,
+            "features": {
+                "tls": false,
+                "pubsub": false,
+                "expiry": true,
+                // The keyspace only lives in memory. Users and ACLs can still be written back, see persist_auth_changes
+                "persistence": false,
+                "watch": true,
+                "pipeline": true,
+                "persist_auth_changes": config.persist_auth_changes,
+                "plaintext": config.plaintext_port.is_some(),
+                "slowlog": config.slowlog_threshold_ms.is_some(),
+                "strict_types": config.strict_types,
+                "fast_hash": config.fast_hash,
+            },
+        };
+        Self { features }
+    }
+}
+
+#[async_trait]
+impl Command for FeaturesCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, _store: Arc<RwLock<Store>>, _args: Bson, _message: &Message) -> Option<MessageResponse> {
+        Some(MessageResponse {
+            content: Some(Bson::Document(self.features.clone())),
+            status: OperationStatus::Success,
+        })
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+/// Whether this server is a primary or a replica, shaped like the ROLE of redis. There is no replication yet,
+/// so it's always a master without replicas. A replica would answer with the address of its primary and its lag
+pub struct RoleCommand {}
+
+#[async_trait]
+impl Command for RoleCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, _store: Arc<RwLock<Store>>, _args: Bson, _message: &Message) -> Option<MessageResponse> {
+        Some(MessageResponse {
+            content: Some(Bson::Document(bson::doc! {
+                "role": "master",
+                "replicas": [],
+            })),
+            status: OperationStatus::Success,
+        })
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+/// Makes writes wait until ResumeWrites or the timeout, while reads go on. For taking a consistent backup.
+/// Answers once the writes that were already running are done
+pub struct PauseWritesCommand {}
+
+#[async_trait]
+impl Command for PauseWritesCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let args: PauseWritesCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+
+        let timeout = args.timeout_secs.map_or(MAX_WRITE_PAUSE, Duration::from_secs);
+        // Not awaited while holding the store lock, running writes need it to finish
+        let write_pause = store.read().await.write_pause();
+        let rsp = if write_pause.pause(timeout).await {
+            log::info!("Writes paused for at most {:?}", timeout.min(MAX_WRITE_PAUSE));
+            MessageResponse {
+                content: None,
+                status: OperationStatus::Success,
+            }
+        } else {
+            MessageResponse {
+                content: Some(Bson::String(String::from("Writes are already paused"))),
+                status: OperationStatus::Failure,
+            }
+        };
+        Some(rsp)
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+/// Ends a pause of PauseWrites early. NotFound if writes weren't paused
+pub struct ResumeWritesCommand {}
+
+#[async_trait]
+impl Command for ResumeWritesCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, _args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let status = if store.read().await.write_pause().resume() {
+            log::info!("Writes resumed");
+            OperationStatus::Success
+        } else {
+            OperationStatus::NotFound
+        };
+        Some(MessageResponse {
+            content: None,
+            status,
+        })
     }
 
     async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
-}
\ No newline at end of file
+}
+
+/// A hash of the whole keyspace, to check whether two servers hold the same data, see Store::digest.
+/// Holds the read lock while it hashes every value
+pub struct DigestCommand {}
+
+#[async_trait]
+impl Command for DigestCommand {
+    async fn pre_exec(&mut self, _connection: &Connection, _encrypted: bool) -> bool { true }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, _args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let digest = store.read().await.digest();
+        Some(MessageResponse {
+            content: Some(Bson::String(digest)),
+            status: OperationStatus::Success,
+        })
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use common::command::str_to_command_id;
+    use super::*;
+    use crate::commands::{run, test_store};
+
+    #[tokio::test]
+    async fn command_list_names_parse_back_to_the_registered_commands() {
+        let rsp = run(&mut CommandListCommand::new(vec![CommandID::Set, CommandID::HGETALL, CommandID::Get]), &test_store(), Bson::Null).await.unwrap();
+        let Some(Bson::Array(names)) = rsp.content else { panic!("expected an array, got {:?}", rsp.content) };
+        let commands = names.into_iter().map(|name| str_to_command_id(name.as_str().unwrap().to_string()).unwrap()).collect::<Vec<_>>();
+        assert_eq!(commands, vec![CommandID::Get, CommandID::Set, CommandID::HGETALL]);
+    }
+
+    #[tokio::test]
+    async fn time_returns_seconds_and_microseconds() {
+        let before = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+        let rsp = run(&mut TimeCommand {}, &test_store(), Bson::Null).await.unwrap();
+        let after = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+        let Some(Bson::Array(time)) = rsp.content else { panic!("expected an array, got {:?}", rsp.content) };
+        let (Bson::Int64(secs), Bson::Int64(micros)) = (&time[0], &time[1]) else { panic!("expected two integers, got {:?}", time) };
+        assert!(before <= *secs && *secs <= after);
+        assert!((0..1_000_000).contains(micros));
+    }
+
+    #[tokio::test]
+    async fn features_reflect_the_config() {
+        let config = Config { strict_types: true, plaintext_port: Some(4000), ..Default::default() };
+        let rsp = run(&mut FeaturesCommand::new(&config), &test_store(), Bson::Null).await.unwrap();
+        let Some(Bson::Document(features)) = rsp.content else { panic!("expected a document, got {:?}", rsp.content) };
+        assert_eq!(features.get_str("version").unwrap(), env!("CARGO_PKG_VERSION"));
+        let flags = features.get_document("features").unwrap();
+        assert!(flags.get_bool("strict_types").unwrap());
+        assert!(flags.get_bool("plaintext").unwrap());
+        assert!(!flags.get_bool("slowlog").unwrap());
+        assert!(!flags.get_bool("fast_hash").unwrap());
+        assert!(flags.get_bool("expiry").unwrap());
+    }
+
+    #[tokio::test]
+    async fn role_reports_a_primary_without_replicas() {
+        let rsp = run(&mut RoleCommand {}, &test_store(), Bson::Null).await.unwrap();
+        assert_eq!(rsp.status, OperationStatus::Success);
+        assert_eq!(rsp.content, Some(Bson::Document(bson::doc! { "role": "master", "replicas": [] })));
+    }
+}
@@ -6,9 +6,13 @@ use common::command_input::UserRemoveCommandInput;
 use common::connection::Connection;
 use common::message::{Message, MessageResponse, OperationStatus};
 use crate::commands::Command;
+use crate::config::AuthSnapshot;
 use crate::store::{Store, UserAble};
 
-pub struct UserRemoveCommand {}
+#[derive(Default)]
+pub struct UserRemoveCommand {
+    snapshot: Option<AuthSnapshot>,
+}
 
 #[async_trait]
 impl Command for UserRemoveCommand {
@@ -22,6 +26,7 @@ impl Command for UserRemoveCommand {
         };
 
         let rsp = if store.user_remove(&args.user) {
+            self.snapshot = store.auth_snapshot();
             MessageResponse {
                 content: None,
                 status: OperationStatus::Success,
@@ -35,5 +40,9 @@ impl Command for UserRemoveCommand {
         Some(rsp)
     }
 
-    async fn post_exec(&mut self, _connection: &mut Connection, _: Option<&MessageResponse>) {}
+    async fn post_exec(&mut self, _connection: &mut Connection, _: Option<&MessageResponse>) {
+        if let Some(snapshot) = self.snapshot.take() {
+            snapshot.persist().await;
+        }
+    }
 }
@@ -0,0 +1,137 @@
+use std::sync::Arc;
+use async_trait::async_trait;
+use bson::Bson;
+use tokio::sync::{Mutex, RwLock};
+use uuid::Uuid;
+use common::command_input::{UnwatchKeyCommandInput, WatchKeyCommandInput};
+use common::connection::Connection;
+use common::message::{Message, MessageResponse, OperationStatus};
+use crate::clients::ClientRegistry;
+use crate::commands::Command;
+use crate::store::Store;
+
+/// Pushes the value of a key to the connection every time it's written, as a KeyChanged response
+pub struct WatchKeyCommand {
+    clients: Arc<Mutex<ClientRegistry>>,
+    conn_id: Option<Uuid>,
+}
+
+impl WatchKeyCommand {
+    pub fn new(clients: Arc<Mutex<ClientRegistry>>) -> Self {
+        Self { clients, conn_id: None }
+    }
+}
+
+#[async_trait]
+impl Command for WatchKeyCommand {
+    async fn pre_exec(&mut self, connection: &Connection, _encrypted: bool) -> bool {
+        self.conn_id = Some(connection.get_id());
+        true
+    }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let args: WatchKeyCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+        let conn_id = self.conn_id?;
+
+        // Only connections of the binary protocol are registered, others can't receive pushes
+        let sender = match self.clients.lock().await.sender(&conn_id) {
+            Some(sender) => sender,
+            None => {
+                return Some(MessageResponse {
+                    content: Some(Bson::String(String::from("This connection can't receive pushed messages"))),
+                    status: OperationStatus::Failure,
+                });
+            }
+        };
+        store.write().await.watch(args.key, conn_id, sender);
+        Some(MessageResponse {
+            content: None,
+            status: OperationStatus::Success,
+        })
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {
+        self.conn_id = None;
+    }
+}
+
+#[derive(Default)]
+pub struct UnwatchKeyCommand {
+    conn_id: Option<Uuid>,
+}
+
+#[async_trait]
+impl Command for UnwatchKeyCommand {
+    async fn pre_exec(&mut self, connection: &Connection, _encrypted: bool) -> bool {
+        self.conn_id = Some(connection.get_id());
+        true
+    }
+
+    async fn execute(&mut self, store: Arc<RwLock<Store>>, args: Bson, _message: &Message) -> Option<MessageResponse> {
+        let args: UnwatchKeyCommandInput = match args.try_into() {
+            Err(_) => { return None; }
+            Ok(doc) => doc
+        };
+        let conn_id = self.conn_id?;
+
+        let status = match store.write().await.unwatch(&args.key, &conn_id) {
+            true => OperationStatus::Success,
+            false => OperationStatus::NotFound,
+        };
+        Some(MessageResponse {
+            content: None,
+            status,
+        })
+    }
+
+    async fn post_exec(&mut self, _connection: &mut Connection, _response: Option<&MessageResponse>) {
+        self.conn_id = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::ClientSignal;
+    use crate::commands::{run, test_connection, test_store};
+    use crate::store::StoreAble;
+
+    #[tokio::test]
+    async fn watched_key_signals_writes_until_unwatched() {
+        let store = test_store();
+        let clients = Arc::new(Mutex::new(ClientRegistry::default()));
+        let (connection, _peer) = test_connection().await;
+        let id = connection.get_id();
+        let mut signals = clients.lock().await.register(id);
+
+        let mut watch = WatchKeyCommand::new(clients.clone());
+        assert!(watch.pre_exec(&connection, false).await);
+        let rsp = run(&mut watch, &store, WatchKeyCommandInput { key: "key".to_string() }).await.unwrap();
+        assert_eq!(rsp.status, OperationStatus::Success);
+        store.write().await.set("key".to_string(), "new".to_string()).unwrap();
+        let Ok(ClientSignal::KeyChanged(key)) = signals.try_recv() else { panic!("expected a KeyChanged signal") };
+        assert_eq!(key, "key");
+        // The worker loop pushes the value it reads after the signal
+        assert_eq!(store.read().await.value_as_bson(&key), Bson::String("new".to_string()));
+
+        let mut unwatch = UnwatchKeyCommand::default();
+        assert!(unwatch.pre_exec(&connection, false).await);
+        let rsp = run(&mut unwatch, &store, UnwatchKeyCommandInput { key: "key".to_string() }).await.unwrap();
+        assert_eq!(rsp.status, OperationStatus::Success);
+        let rsp = run(&mut unwatch, &store, UnwatchKeyCommandInput { key: "key".to_string() }).await.unwrap();
+        assert_eq!(rsp.status, OperationStatus::NotFound);
+        store.write().await.set("key".to_string(), "unwatched".to_string()).unwrap();
+        assert!(signals.try_recv().is_err());
+
+        // Closing the connection removes its watches
+        assert!(watch.pre_exec(&connection, false).await);
+        run(&mut watch, &store, WatchKeyCommandInput { key: "key".to_string() }).await.unwrap();
+        store.write().await.unwatch_all(&id);
+        store.write().await.set("key".to_string(), "closed".to_string()).unwrap();
+        assert!(signals.try_recv().is_err());
+        assert!(!store.write().await.unwatch("key", &id));
+    }
+}
@@ -1,5 +1,8 @@
+use std::collections::BTreeMap;
 use std::net::IpAddr;
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use serde::{Deserialize, Serialize};
 
@@ -18,6 +21,18 @@ pub struct ConfigUser {
     pub acls: Vec<String>,
 }
 
+/// What happens if the private key file is missing
+#[derive(Ord, PartialOrd, Eq, PartialEq, Clone, Hash, Debug, Default, Serialize, Deserialize)]
+pub struct KeyGeneration {
+    /// Generate a new identity. Otherwise the server refuses to start, because clients that trust the old public key
+    /// can't connect to a new one. For example if the volume holding the key wasn't mounted
+    #[serde(default)]
+    pub enabled: bool,
+    /// Stop after generating instead of starting, so the new public key can be handed to the clients first
+    #[serde(default)]
+    pub confirm: bool,
+}
+
 #[derive(Ord, PartialOrd, Eq, PartialEq, Clone, Hash, Debug, Default, Serialize, Deserialize)]
 pub struct Config {
     /// The users that are allowed to connect to the server
@@ -30,8 +45,20 @@ pub struct Config {
     ///     - "HGET"
     ///     - "HSET"
     /// ```
-    /// It's always allowed to send the KEYEXCHANGE, HEARTBEAT and LOGIN Messages
+    /// It's always allowed to send the KEYEXCHANGE, HEARTBEAT, LOGIN, CommandList, ConnStats, Time, SessionCommands, Features, Role and SecurityInfo Messages
     pub users: Vec<ConfigUser>,
+    /// Commands that connections are allowed to execute without logging in
+    /// For example only `GET` to expose a read only endpoint. Nothing is allowed by default
+    #[serde(default)]
+    pub anonymous_acls: Vec<String>,
+    /// Commands nobody may execute, not even users whose ACLs allow them. Answered with NotAllowed
+    /// For example SHUTDOWN to make sure the server can't be stopped remotely
+    #[serde(default)]
+    pub disabled_commands: Vec<String>,
+    /// Additional names for commands, mapping the alias to the command name. Usable wherever commands are named,
+    /// like the ACLs above and the plaintext protocol. An alias can't be a command name or point to another alias
+    #[serde(default)]
+    pub command_aliases: BTreeMap<String, String>,
     /// The port that the server will listen on
     /// Can be overridden by the CLI
     pub port: Option<u16>,
@@ -41,15 +68,259 @@ pub struct Config {
     /// The path to the server's age private key
     /// Can be overridden by the CLI
     pub private_key_loc: Option<String>,
+    /// Directory that a relative private_key_loc is resolved against. Lets the key live on a separate volume
+    /// Can be overridden by the CLI
+    pub key_dir: Option<PathBuf>,
+    /// Whether a missing private key is replaced by a newly generated one. Disabled by default,
+    /// except on the very first start that also creates the default config
+    #[serde(default)]
+    pub key_generation: KeyGeneration,
     /// The effort to put into brotli compression. Needs to be between 0 and 11
     /// Can be overridden by the CLI
     pub brotli_quality: Option<u8>,
+    /// The maximum number of pending connections the OS queues before refusing new ones. Defaults to 1024
+    pub listen_backlog: Option<u32>,
+    /// Commands whose execution takes at least this many milliseconds are recorded in the slow log
+    /// The slow log is disabled if this is not set
+    pub slowlog_threshold_ms: Option<u64>,
+    /// How many entries the slow log keeps before the oldest are dropped. Defaults to 128
+    pub slowlog_max_len: Option<usize>,
+    /// When enabled the first command of every connection has to be a successful KEYEXCHANGE
+    /// Connections sending anything else before are closed. This forces encryption for every real operation
+    #[serde(default)]
+    pub require_key_exchange_first: bool,
+    /// HGETALL, HKEYS and HVALS fail instead of answering when the hash has more fields than this
+    /// Protects the server from building a giant response. Unlimited if not set
+    pub max_collection_response: Option<usize>,
+    /// Hash keys with ahash instead of SipHash. Faster, but clients that know the hashing can craft colliding keys
+    /// and slow down every lookup. Only enable this if all clients are trusted
+    #[serde(default)]
+    pub fast_hash: bool,
+    /// How many string keys can exist at once. Creating more fails, existing ones can still be changed. Unlimited if not set
+    pub max_strings: Option<usize>,
+    /// Same as max_strings, for hashes
+    pub max_hashes: Option<usize>,
+    /// Same as max_strings, for lists
+    pub max_lists: Option<usize>,
+    /// How many elements a single list can hold. Pushing beyond it fails, unless trim_full_lists is set. Unlimited if not set
+    pub max_list_length: Option<usize>,
+    /// How many fields a single hash can hold. Setting new fields beyond it fails, changing existing ones still works. Unlimited if not set
+    pub max_hash_fields: Option<usize>,
+    /// Pushing onto a list at max_list_length drops its oldest elements instead of failing
+    #[serde(default)]
+    pub trim_full_lists: bool,
+    /// Commands on a key holding another type fail with TypeError. By default they act as if the key didn't exist
+    #[serde(default)]
+    pub strict_types: bool,
+    /// Commands that would create a key longer than this many bytes fail. Unlimited if not set
+    pub max_key_length: Option<usize>,
+    /// Outgoing bytes per second per connection. Responses beyond it are delayed, not dropped, so one client pulling
+    /// huge responses can't saturate the uplink. Unlimited if not set
+    pub max_egress_bytes_per_sec: Option<u64>,
+    /// Messages from clients that decompress to more bytes than this are rejected and the connection is closed.
+    /// The frame size only limits the compressed bytes, which can expand a thousandfold. Defaults to 64 MiB
+    pub max_decompressed_size: Option<usize>,
+    /// Port for a line based plaintext protocol, for debugging with nc or telnet. Disabled if not set
+    /// It is unencrypted and has no login, it can only run what anonymous_acls allow
+    pub plaintext_port: Option<u16>,
+    /// When set, every connection first receives a Banner message with this text, the server and protocol version and the node id
+    /// Not sent on the plaintext protocol
+    pub banner: Option<String>,
+    /// Identifies this server in the banner. A random id is generated on every start if not set
+    pub node_id: Option<String>,
+    /// Write the users section back into the config file after ACLSet, ACLRemove, ACLImport or UserRemove changed it
+    /// Everything else in the file is kept, but users the server skipped when loading, for example because of an unhashed password, are dropped
+    #[serde(default)]
+    pub persist_auth_changes: bool,
 }
 
 impl Config {
+    pub fn load(path: &PathBuf) -> Result<Self, Box<dyn std::error::Error>> {
+        let file = std::fs::File::open(path)?;
+        Ok(serde_yaml::from_reader(file)?)
+    }
+
+    /// Writes to a temporary file next to the config and renames it, so a crash never leaves a half written config behind
+    /// The new file gets the permissions of the old one, or 0600 if there was none, since it holds password hashes
     pub fn save(&self, path: &PathBuf) -> Result<(), Box<dyn std::error::Error>> {
-        let file = std::fs::File::create(path)?;
-        serde_yaml::to_writer(file, self)?;
+        let mut tmp = path.clone().into_os_string();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let file = options.open(&tmp)?;
+        match std::fs::metadata(path) {
+            Ok(metadata) => file.set_permissions(metadata.permissions())?,
+            #[cfg(unix)]
+            Err(_) => file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?,
+            #[cfg(not(unix))]
+            Err(_) => {}
+        }
+        serde_yaml::to_writer(&file, self)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+/// Shared by all connections to write runtime changes of users and ACLs back into the config file
+#[derive(Debug)]
+pub struct AuthPersistence {
+    path: PathBuf,
+    /// Generation of the newest snapshot written, so a slower connection doesn't overwrite it with an older one
+    written: Mutex<u64>,
+    next: AtomicU64,
+}
+
+impl AuthPersistence {
+    pub fn new(path: PathBuf) -> Self {
+        AuthPersistence {
+            path,
+            written: Mutex::new(0),
+            next: AtomicU64::new(0),
+        }
+    }
+
+    /// Called by Store::auth_snapshot while holding the write lock, so generations are ordered like the changes themselves
+    pub fn next_generation(&self) -> u64 {
+        self.next.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    /// Replaces the users of the config file with the snapshot, unless a newer one was already written
+    pub fn persist(&self, generation: u64, users: Vec<ConfigUser>) -> Result<(), Box<dyn std::error::Error>> {
+        let mut written = self.written.lock().unwrap();
+        if generation < *written {
+            return Ok(());
+        }
+        let mut config = Config::load(&self.path)?;
+        config.users = users;
+        config.save(&self.path)?;
+        *written = generation;
         Ok(())
     }
 }
+
+/// The users after a change, taken while holding the store's write lock and written once the command is done.
+/// Commands changing users or ACLs keep it in their snapshot field and write it to the config file in post_exec, see persist_auth_changes
+pub struct AuthSnapshot {
+    pub persistence: Arc<AuthPersistence>,
+    pub generation: u64,
+    pub users: Vec<ConfigUser>,
+}
+
+impl AuthSnapshot {
+    /// Runs on the blocking pool, the file I/O and the mutex would otherwise stall the runtime's worker thread
+    pub async fn persist(self) {
+        let result = tokio::task::spawn_blocking(move || {
+            self.persistence
+                .persist(self.generation, self.users)
+                .map_err(|err| err.to_string())
+        })
+        .await;
+        match result {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => log::error!("Error writing users to the config file: {}", err),
+            Err(err) => log::error!("Error writing users to the config file: {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("in-mem-{}-{}.yaml", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn save_creates_new_config_private() {
+        use std::os::unix::fs::PermissionsExt;
+        let path = temp_path("save-new");
+        Config::default().save(&path).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn save_keeps_permissions_of_existing_config() {
+        use std::os::unix::fs::PermissionsExt;
+        let path = temp_path("save-existing");
+        Config::default().save(&path).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o640)).unwrap();
+        Config::default().save(&path).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(mode & 0o777, 0o640);
+    }
+
+    #[tokio::test]
+    async fn snapshot_persist_writes_users() {
+        let path = temp_path("persist");
+        Config::default().save(&path).unwrap();
+        let persistence = Arc::new(AuthPersistence::new(path.clone()));
+        let users = vec![ConfigUser {
+            name: "alice".to_string(),
+            ..Default::default()
+        }];
+        AuthSnapshot {
+            generation: persistence.next_generation(),
+            persistence,
+            users: users.clone(),
+        }
+        .persist()
+        .await;
+        let config = Config::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(config.users, users);
+    }
+
+    #[tokio::test]
+    async fn snapshot_persist_skips_older_generations() {
+        let path = temp_path("persist-order");
+        Config::default().save(&path).unwrap();
+        let persistence = Arc::new(AuthPersistence::new(path.clone()));
+        let older = persistence.next_generation();
+        let newer = persistence.next_generation();
+        let user = |name: &str| ConfigUser {
+            name: name.to_string(),
+            ..Default::default()
+        };
+        AuthSnapshot {
+            persistence: persistence.clone(),
+            generation: newer,
+            users: vec![user("new")],
+        }
+        .persist()
+        .await;
+        AuthSnapshot {
+            persistence,
+            generation: older,
+            users: vec![user("old")],
+        }
+        .persist()
+        .await;
+        let config = Config::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(config.users, vec![user("new")]);
+    }
+
+    #[test]
+    fn load_reads_a_saved_config_and_fails_on_missing_or_invalid_files() {
+        let path = temp_path("load");
+        assert!(Config::load(&path).is_err());
+        Config { port: Some(4100), ..Default::default() }.save(&path).unwrap();
+        assert_eq!(Config::load(&path).unwrap().port, Some(4100));
+        std::fs::write(&path, "port: [not a port").unwrap();
+        let invalid = Config::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(invalid.is_err());
+    }
+}
@@ -0,0 +1,128 @@
+/// Matches text against a redis style glob pattern
+/// Supports `*`, `?`, character classes like `[abc]`, `[a-z]` and `[^a]`, and `\` to escape the next character
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    // The position of the last star in the pattern and the text position it is currently matched up to
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() {
+            match pattern[p] {
+                '*' => {
+                    star = Some((p, t));
+                    p += 1;
+                    continue;
+                }
+                '?' => {
+                    p += 1;
+                    t += 1;
+                    continue;
+                }
+                '[' => {
+                    match match_class(&pattern, p, text[t]) {
+                        Some((true, next)) => {
+                            p = next;
+                            t += 1;
+                            continue;
+                        }
+                        Some((false, _)) => {}
+                        // An unterminated class is matched literally
+                        None => {
+                            if text[t] == '[' {
+                                p += 1;
+                                t += 1;
+                                continue;
+                            }
+                        }
+                    }
+                }
+                '\\' if p + 1 < pattern.len() => {
+                    if pattern[p + 1] == text[t] {
+                        p += 2;
+                        t += 1;
+                        continue;
+                    }
+                }
+                c => {
+                    if c == text[t] {
+                        p += 1;
+                        t += 1;
+                        continue;
+                    }
+                }
+            }
+        }
+        // Mismatch, let the last star swallow one more character
+        match star {
+            Some((star_p, star_t)) => {
+                p = star_p + 1;
+                t = star_t + 1;
+                star = Some((star_p, star_t + 1));
+            }
+            None => return false,
+        }
+    }
+    while p < pattern.len() && pattern[p] == '*' {
+        p += 1;
+    }
+    p == pattern.len()
+}
+
+/// Checks c against the class starting at pattern[start], which is a `[`
+/// Returns whether it matched and the position after the closing `]`, or None if the class is never closed
+fn match_class(pattern: &[char], start: usize, c: char) -> Option<(bool, usize)> {
+    let mut i = start + 1;
+    let negate = i < pattern.len() && pattern[i] == '^';
+    if negate {
+        i += 1;
+    }
+    let mut matched = false;
+    while i < pattern.len() && pattern[i] != ']' {
+        if pattern[i] == '\\' && i + 1 < pattern.len() {
+            matched |= pattern[i + 1] == c;
+            i += 2;
+        } else if i + 2 < pattern.len() && pattern[i + 1] == '-' && pattern[i + 2] != ']' {
+            let (low, high) = if pattern[i] <= pattern[i + 2] { (pattern[i], pattern[i + 2]) } else { (pattern[i + 2], pattern[i]) };
+            matched |= low <= c && c <= high;
+            i += 3;
+        } else {
+            matched |= pattern[i] == c;
+            i += 1;
+        }
+    }
+    if i >= pattern.len() {
+        return None;
+    }
+    Some((matched != negate, i + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards() {
+        assert!(glob_match("user:*", "user:1"));
+        assert!(glob_match("user:*", "user:"));
+        assert!(glob_match("*:name", "user:1:name"));
+        assert!(glob_match("user:?", "user:1"));
+        assert!(!glob_match("user:?", "user:12"));
+        assert!(!glob_match("user:*", "users"));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+    }
+
+    #[test]
+    fn classes_and_escapes() {
+        assert!(glob_match("h[ae]llo", "hallo"));
+        assert!(!glob_match("h[ae]llo", "hillo"));
+        assert!(glob_match("h[a-c]llo", "hbllo"));
+        assert!(glob_match("h[^e]llo", "hallo"));
+        assert!(!glob_match("h[^e]llo", "hello"));
+        assert!(glob_match(r"what\?", "what?"));
+        assert!(!glob_match(r"what\?", "whats"));
+        // An unterminated class is matched literally
+        assert!(glob_match("[abc", "[abc"));
+    }
+}
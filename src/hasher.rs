@@ -0,0 +1,108 @@
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+
+/// HashMap keyed by client controlled strings, hashed with the hasher the store was configured with
+pub type KeyMap<V> = HashMap<String, V, KeyHasher>;
+
+/// Chooses the hasher of the keyspace at runtime
+///
+/// SipHash with a random seed is the default, because keys are chosen by clients and a predictable hash lets them
+/// force collisions, degrading every lookup in the map (HashDoS). ahash is a lot faster, but only makes sense when
+/// every client is trusted
+#[derive(Debug, Clone)]
+pub enum KeyHasher {
+    Sip(RandomState),
+    Fast(ahash::RandomState),
+}
+
+impl KeyHasher {
+    pub fn new(fast: bool) -> Self {
+        if fast {
+            KeyHasher::Fast(ahash::RandomState::new())
+        } else {
+            KeyHasher::Sip(RandomState::new())
+        }
+    }
+}
+
+impl Default for KeyHasher {
+    fn default() -> Self {
+        KeyHasher::new(false)
+    }
+}
+
+impl BuildHasher for KeyHasher {
+    type Hasher = KeyHasherState;
+
+    fn build_hasher(&self) -> Self::Hasher {
+        match self {
+            KeyHasher::Sip(state) => KeyHasherState::Sip(state.build_hasher()),
+            KeyHasher::Fast(state) => KeyHasherState::Fast(state.build_hasher()),
+        }
+    }
+}
+
+pub enum KeyHasherState {
+    Sip(DefaultHasher),
+    Fast(ahash::AHasher),
+}
+
+impl Hasher for KeyHasherState {
+    fn finish(&self) -> u64 {
+        match self {
+            KeyHasherState::Sip(hasher) => hasher.finish(),
+            KeyHasherState::Fast(hasher) => hasher.finish(),
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        match self {
+            KeyHasherState::Sip(hasher) => hasher.write(bytes),
+            KeyHasherState::Fast(hasher) => hasher.write(bytes),
+        }
+    }
+
+    fn write_u8(&mut self, i: u8) {
+        match self {
+            KeyHasherState::Sip(hasher) => hasher.write_u8(i),
+            KeyHasherState::Fast(hasher) => hasher.write_u8(i),
+        }
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        match self {
+            KeyHasherState::Sip(hasher) => hasher.write_usize(i),
+            KeyHasherState::Fast(hasher) => hasher.write_usize(i),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn both_hashers_are_consistent_and_seeded_per_instance() {
+        for fast in [false, true] {
+            let hasher = KeyHasher::new(fast);
+            assert!(matches!(hasher, KeyHasher::Fast(_)) == fast);
+            assert_eq!(hasher.hash_one("key"), hasher.hash_one("key"));
+            assert_eq!(hasher.clone().hash_one("key"), hasher.hash_one("key"));
+            // A fresh seed makes a different hash, with a chance of 2^-64 to be equal
+            assert_ne!(KeyHasher::new(fast).hash_one("key"), hasher.hash_one("key"));
+        }
+    }
+
+    #[test]
+    fn key_map_works_with_both_hashers() {
+        for fast in [false, true] {
+            let mut map: KeyMap<usize> = KeyMap::with_hasher(KeyHasher::new(fast));
+            for i in 0..100 {
+                map.insert(format!("key{}", i), i);
+            }
+            assert_eq!(map.len(), 100);
+            assert_eq!(map.get("key42"), Some(&42));
+        }
+    }
+}
@@ -2,29 +2,44 @@ use std::collections::HashMap;
 use std::fs::create_dir_all;
 use std::io::prelude::Read;
 use std::net::{IpAddr, SocketAddr};
-use std::path::{MAIN_SEPARATOR, PathBuf};
+use std::path::{MAIN_SEPARATOR, Path, PathBuf};
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use age::secrecy::ExposeSecret;
 use age::x25519::{Identity, Recipient};
-use clap::Parser;
+use bson::Bson;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser};
+use clap::parser::ValueSource;
 use directories::ProjectDirs;
-use tokio::net::TcpListener;
-use tokio::sync::RwLock;
+use tokio::net::{TcpListener, TcpSocket};
+use tokio::sync::{Mutex, Notify, RwLock};
+use tokio::task::JoinSet;
 use uuid::Uuid;
 
-use common::command::{CommandID, str_to_command_id};
-use common::connection::Connection;
+use common::command::{CommandAliases, CommandID};
+use common::connection::{Connection, FrameCapabilities};
 use common::init_env_logger;
-use common::message::{Message, MessageContent, MessageResponse, OperationStatus};
+use common::key_file::{warn_if_permissive, write_key_file};
+use common::command_input::PipelineCommandInput;
+use common::message::{Command, Message, MessageContent, MessageResponse, OperationStatus};
 
+use crate::clients::{ClientRegistry, ClientSignal};
+use crate::config::AuthPersistence;
 use crate::commands::{GetCommand, SetCommand, DeleteCommand, HeartbeatCommand, AclListCommand, AclSetCommand, AclRemoveCommand, LoginCommand, KeyExchangeCommand, HashMapGetCommand, HashMapSetCommand, HashMapDeleteCommand, HashMapKeysCommand, HashMapValuesCommand, HashMapLenCommand, HashMapExistsCommand, HashMapGetAllCommand, HashMapIncrByCommand, HashMapStringLenCommand, HashMapUpsertCommand};
-use crate::store::{ACLAble, Store, UserAble};
+use crate::slowlog::SlowLog;
+use crate::store::{ACLAble, Store, TypeLimits, UserAble};
 
 mod store;
+mod clients;
 mod config;
 mod commands;
+mod glob;
+mod hasher;
+mod plaintext;
+mod pause;
+mod slowlog;
 
 #[derive(Parser, Debug)]
 #[command(name = "in-mem", version = "1.0", about = "A small in mem server")]
@@ -43,75 +58,201 @@ struct Cli {
     /// The private key location
     #[arg(env = "PRIVATE_KEY", help = "The location of the private key")]
     private_key_loc: Option<String>,
+    /// The directory relative private key locations are resolved against
+    #[arg(long, env = "KEY_DIR", help = "The directory a relative private key location is resolved against")]
+    key_dir: Option<PathBuf>,
+    /// Load the config from this file instead of the data dir
+    #[arg(long, env = "CONFIG_PATH", help = "Load the config from this file instead of the data dir. It has to exist")]
+    config: Option<PathBuf>,
 }
 
-async fn handle_message(message: Message, connection: &mut Connection, store: &Arc<RwLock<Store>>, encrypted: bool, rsp_id: Uuid, command_registry: &mut HashMap<CommandID, Box<dyn commands::Command>>) -> Option<Message> {
-    let original_message = message.clone();
-    match message.content {
-        MessageContent::Command(cmd) => {
-            log::trace!("Received command: {:?}", cmd);
-            let cmd_id: CommandID = cmd.command_id.try_into().unwrap();
-            // Check if the command is allowed
-            {
-                let store = store.read().await;
-                if store.acl_is_allowed(&connection.get_user().unwrap_or_else(|| "".to_string()), cmd_id) {
-                    log::trace!("Command allowed: {:?}", cmd_id);
-                } else {
-                    log::error!("Command not allowed: {:?}", cmd_id);
-                    let rsp = Message::new_response(rsp_id, MessageResponse {
+async fn handle_message(message: Message, connection: &mut Connection, store: &Arc<RwLock<Store>>, encrypted: bool, rsp_id: Uuid, command_registry: &mut HashMap<CommandID, Box<dyn commands::Command>>, slowlog: &Arc<Mutex<SlowLog>>) -> Option<Message> {
+    match &message.content {
+        MessageContent::Command(cmd) if cmd.command_id == CommandID::PIPELINE => {
+            log::trace!("Received pipeline: {:?}", cmd);
+            connection.record_command(CommandID::PIPELINE);
+            if !is_allowed(connection, store, CommandID::PIPELINE).await {
+                return Some(Message::new_response(rsp_id, MessageResponse {
+                    content: None,
+                    status: OperationStatus::NotAllowed,
+                }));
+            }
+            let pipeline: PipelineCommandInput = match cmd.payload.clone().try_into() {
+                Ok(pipeline) => pipeline,
+                Err(err) => {
+                    log::error!("Error parsing pipeline: {}", err);
+                    return None;
+                }
+            };
+            let mut responses = Vec::with_capacity(pipeline.commands.len());
+            for sub in pipeline.commands {
+                // Sub commands see a message of their own, as if they were sent on their own
+                let sub_message = Message::new_command(message.id, sub.clone());
+                let rsp = execute_command(sub, &sub_message, connection, store, encrypted, command_registry, slowlog).await
+                    .unwrap_or(MessageResponse {
                         content: None,
-                        status: OperationStatus::NotAllowed,
+                        status: OperationStatus::Failure,
                     });
-                    return Some(rsp);
-                }
+                responses.push(bson::to_bson(&rsp).unwrap_or(Bson::Null));
             }
+            Some(Message::new_response(rsp_id, MessageResponse {
+                content: Some(Bson::Array(responses)),
+                status: OperationStatus::Success,
+            }))
+        }
+        MessageContent::Command(cmd) => {
+            let cmd = cmd.clone();
+            execute_command(cmd, &message, connection, store, encrypted, command_registry, slowlog).await
+                .map(|result| Message::new_response(rsp_id, result))
+        }
+        MessageContent::Response(_) => {
+            log::error!("Received unexpected response from client: {}", connection.get_id());
+            None
+        }
+    }
+}
 
+async fn is_allowed(connection: &mut Connection, store: &Arc<RwLock<Store>>, cmd_id: CommandID) -> bool {
+    if let Some(allowed) = connection.cached_acl_decision(cmd_id) {
+        return allowed;
+    }
+    let store = store.read().await;
+    // Read under the lock, so the version belongs to the decision
+    let version = store.acl_version().get();
+    let allowed = store.acl_is_allowed(&connection.get_user().unwrap_or_else(|| "".to_string()), cmd_id);
+    connection.cache_acl_decision(version, cmd_id, allowed);
+    allowed
+}
 
-            let rsvp = command_registry.get_mut(&cmd_id);
-            match rsvp {
-                Some(handler) => {
-                    let early_exit = handler.pre_exec(connection, encrypted).await;
-                    if !early_exit {
-                        let rsp = Message::new_response(rsp_id, MessageResponse {
-                            content: None,
-                            status: OperationStatus::Failure,
-                        });
-                        return Some(rsp);
-                    }
+/// Checks the ACLs and runs a single command through its handler. None if the command is unknown or failed without a response
+async fn execute_command(cmd: Command, original_message: &Message, connection: &mut Connection, store: &Arc<RwLock<Store>>, encrypted: bool, command_registry: &mut HashMap<CommandID, Box<dyn commands::Command>>, slowlog: &Arc<Mutex<SlowLog>>) -> Option<MessageResponse> {
+    log::trace!("Received command: {:?}", cmd);
+    let cmd_id: CommandID = cmd.command_id;
+    connection.record_command(cmd_id);
+    // Check if the command is allowed
+    if is_allowed(connection, store, cmd_id).await {
+        log::trace!("Command allowed: {:?}", cmd_id);
+    } else {
+        log::error!("Command not allowed: {:?}", cmd_id);
+        return Some(MessageResponse {
+            content: None,
+            status: OperationStatus::NotAllowed,
+        });
+    }
 
-                    let result = handler.execute(store.clone(), cmd.payload, &original_message).await;
-                    handler.post_exec(connection, result.as_ref()).await;
-                    match result {
-                        Some(result) => {
-                            Some(Message::new_response(rsp_id, result))
-                        }
-                        None => {
-                            log::error!("Error executing command: {:?}", cmd.command_id);
-                            None
-                        }
-                    }
-                }
-                None => {
-                    log::error!("Received unknown command: {:?}", cmd.command_id);
-                    None
+    // Held until the command is done, so PauseWrites waits for running writes
+    let _write_guard = if cmd_id.is_write() {
+        let write_pause = store.read().await.write_pause();
+        Some(write_pause.enter().await)
+    } else {
+        None
+    };
+
+    let rsvp = command_registry.get_mut(&cmd_id);
+    match rsvp {
+        Some(handler) => {
+            let early_exit = handler.pre_exec(connection, encrypted).await;
+            if !early_exit {
+                return Some(MessageResponse {
+                    content: None,
+                    status: OperationStatus::Failure,
+                });
+            }
+
+            let start = Instant::now();
+            let result = handler.execute(store.clone(), cmd.payload.clone(), original_message).await;
+            let elapsed = start.elapsed();
+            {
+                let mut slowlog = slowlog.lock().await;
+                if slowlog.is_slow(elapsed) {
+                    log::debug!("Slow command {:?} took {:?}", cmd_id, elapsed);
+                    slowlog.record(connection.get_user(), cmd_id, elapsed, &cmd.payload);
                 }
             }
+            handler.post_exec(connection, result.as_ref()).await;
+            if result.is_none() {
+                log::error!("Error executing command: {:?}", cmd.command_id);
+            }
+            result
         }
-        MessageContent::Response(_) => {
-            log::error!("Received unexpected response from client: {}", connection.get_id());
+        None => {
+            log::error!("Received unknown command: {:?}", cmd.command_id);
             None
         }
     }
 }
 
-async fn worker_loop(mut connection: Connection, store: Arc<RwLock<Store>>, key: Identity) {
-    let mut command_registry = populate_command_registry();
+async fn worker_loop(mut connection: Connection, store: Arc<RwLock<Store>>, key: Identity, slowlog: Arc<Mutex<SlowLog>>, config: Arc<config::Config>, clients: Arc<Mutex<ClientRegistry>>, shutdown: Arc<Notify>) {
+    if let Some(banner) = &config.banner {
+        let banner = Message::new_response(Uuid::new_v4(), MessageResponse {
+            content: Some(Bson::Document(bson::doc! {
+                "message": banner,
+                "version": env!("CARGO_PKG_VERSION"),
+                "protocol": FrameCapabilities::current(false, false).version as i64,
+                "node_id": config.node_id.clone(),
+            })),
+            status: OperationStatus::Banner,
+        });
+        if let Err(err) = connection.send_message(&banner).await {
+            log::error!("Error sending banner to {}: {}", connection.get_id(), err);
+            return;
+        }
+    }
+    let mut command_registry = populate_command_registry(&config, &slowlog, &shutdown, &clients);
+    connection.set_acl_version(store.read().await.acl_version());
+    let mut signals = clients.lock().await.register(connection.get_id());
     loop {
-        match connection.read_message(&key).await {
+        let read = tokio::select! {
+            read = connection.read_message(&key) => read,
+            Some(signal) = signals.recv() => {
+                match signal {
+                    ClientSignal::Shutdown => {
+                        log::debug!("Notifying {} about shutdown", connection.get_id());
+                        let notification = Message::new_response(Uuid::new_v4(), MessageResponse {
+                            content: None,
+                            status: OperationStatus::ServerShutdown,
+                        });
+                        if let Err(err) = connection.send_message(&notification).await {
+                            log::error!("Error sending shutdown notification: {}", err);
+                        }
+                        connection.close();
+                        break;
+                    }
+                    ClientSignal::Close => {
+                        log::debug!("Closing idle connection {}", connection.get_id());
+                        connection.close();
+                        break;
+                    }
+                    ClientSignal::KeyChanged(key) => {
+                        let value = store.read().await.value_as_bson(&key);
+                        let push = Message::new_response(Uuid::new_v4(), MessageResponse {
+                            content: Some(Bson::Document(bson::doc! { "key": key, "value": value })),
+                            status: OperationStatus::KeyChanged,
+                        });
+                        if let Err(err) = connection.send_message(&push).await {
+                            log::error!("Error pushing changed key: {}", err);
+                            connection.close();
+                            break;
+                        }
+                        continue;
+                    }
+                }
+            }
+        };
+        match read {
             Ok((message, encrypted)) => {
                 log::trace!("Read from socket: {}", connection.get_id());
+                clients.lock().await.touch(&connection.get_id());
+                if config.require_key_exchange_first && !connection.is_key_exchanged() {
+                    let is_key_exchange = matches!(&message.content, MessageContent::Command(cmd) if cmd.command_id == CommandID::KEYEXCHANGE);
+                    if !is_key_exchange {
+                        log::error!("Closing connection: {}, Command received before KEYEXCHANGE", connection.get_id());
+                        connection.close();
+                        break;
+                    }
+                }
                 let rsp_id = Uuid::new_v4();
-                let resp = handle_message(message, &mut connection, &store, encrypted, rsp_id, &mut command_registry).await;
+                let resp = handle_message(message, &mut connection, &store, encrypted, rsp_id, &mut command_registry, &slowlog).await;
                 match resp {
                     None => {
                         log::trace!("Closing connection: {}, Client behaved badly", connection.get_id());
@@ -133,37 +274,121 @@ async fn worker_loop(mut connection: Connection, store: Arc<RwLock<Store>>, key:
             Err(err) => {
                 log::error!("Error reading from socket: {}", err);
                 connection.close();
+                break;
             }
         }
     }
+    clients.lock().await.unregister(&connection.get_id());
+    store.write().await.unwatch_all(&connection.get_id());
+}
+
+/// Initial sleep after a failed accept, doubled for every consecutive failure
+const ACCEPT_BACKOFF_BASE: Duration = Duration::from_millis(5);
+/// Upper bound for the sleep after a failed accept
+const ACCEPT_BACKOFF_MAX: Duration = Duration::from_secs(1);
+
+/// How long to wait before accepting again after the given number of consecutive accept errors
+fn accept_backoff(consecutive_errors: u32) -> Duration {
+    if consecutive_errors == 0 {
+        return Duration::ZERO;
+    }
+    // Capped shift, so this doesn't overflow for long error streaks
+    let factor = 1u32 << (consecutive_errors - 1).min(16);
+    ACCEPT_BACKOFF_BASE.saturating_mul(factor).min(ACCEPT_BACKOFF_MAX)
+}
+
+/// Used if max_decompressed_size isn't configured
+const DEFAULT_MAX_DECOMPRESSED_SIZE: usize = 64 * 1024 * 1024;
+
+/// How often expired keys are removed, so keys that are never accessed again don't stay in memory
+const EXPIRY_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Removes expired keys every EXPIRY_SWEEP_INTERVAL. Counts as a write, so it waits while writes are paused
+async fn expiry_sweeper(store: Arc<RwLock<Store>>) {
+    let mut interval = tokio::time::interval(EXPIRY_SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+        let write_pause = store.read().await.write_pause();
+        let _write_guard = write_pause.enter().await;
+        let removed = store.write().await.remove_expired();
+        if removed > 0 {
+            log::debug!("Removed {} expired keys", removed);
+        }
+    }
+}
+
+/// How long the clients get to receive the shutdown notification before the server exits anyway
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+fn bind_listener(addr: SocketAddr, backlog: u32) -> std::io::Result<TcpListener> {
+    let socket = if addr.is_ipv4() { TcpSocket::new_v4()? } else { TcpSocket::new_v6()? };
+    // Same as TcpListener::bind, so a restarted server can bind while old connections are in TIME_WAIT
+    #[cfg(unix)]
+    socket.set_reuseaddr(true)?;
+    socket.bind(addr)?;
+    socket.listen(backlog)
 }
 
-async fn socket_listener(host: IpAddr, port: u16, brotli_effort: u8, store: Arc<RwLock<Store>>, key: Identity) {
-    let addr = SocketAddr::from((host, port));
+async fn socket_listener(config: Arc<config::Config>, store: Arc<RwLock<Store>>, key: Identity, slowlog: Arc<Mutex<SlowLog>>, shutdown: Arc<Notify>, clients: Arc<Mutex<ClientRegistry>>) {
+    // All of these are set in merge_config
+    let addr = SocketAddr::from((config.host.unwrap(), config.port.unwrap()));
+    let brotli_effort = config.brotli_quality.unwrap();
     log::info!("Starting server on tcp://{}", addr);
-    let listener = match TcpListener::bind(&addr).await {
+    let listener = match bind_listener(addr, config.listen_backlog.unwrap_or(1024)) {
         Ok(listener) => listener,
         Err(err) => {
             log::error!("Error binding to {}: {}", addr, err);
             return;
         }
     };
+    let mut workers = JoinSet::new();
+    let mut consecutive_errors = 0;
     loop {
-        let (socket, info) = match listener.accept().await {
-            Ok(res) => res,
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = shutdown.notified() => break,
+            _ = tokio::signal::ctrl_c() => break,
+            // Reap finished workers, so the set doesn't grow with every connection ever made
+            Some(_) = workers.join_next() => continue,
+        };
+        let (socket, info) = match accepted {
+            Ok(res) => {
+                consecutive_errors = 0;
+                res
+            }
             Err(err) => {
-                log::error!("Error accepting connection: {}", err);
+                // Errors like fd exhaustion tend to repeat, back off instead of spinning
+                consecutive_errors += 1;
+                let backoff = accept_backoff(consecutive_errors);
+                log::error!("Error accepting connection: {}. Retrying in {:?}", err, backoff);
+                tokio::time::sleep(backoff).await;
                 continue;
             }
         };
         log::debug!("Accepted connection from: {}", info);
-        let connection = Connection::new(socket, Uuid::new_v4(), brotli_effort);
+        let mut connection = Connection::new(socket, Uuid::new_v4(), brotli_effort);
+        connection.set_egress_limit(config.max_egress_bytes_per_sec);
+        connection.set_max_decompressed_size(Some(config.max_decompressed_size.unwrap_or(DEFAULT_MAX_DECOMPRESSED_SIZE)));
         let store = store.clone();
         let key = key.clone();
-        tokio::spawn(async move {
-            worker_loop(connection, store, key).await;
+        let slowlog = slowlog.clone();
+        let config = config.clone();
+        let clients = clients.clone();
+        let shutdown = shutdown.clone();
+        workers.spawn(async move {
+            worker_loop(connection, store, key, slowlog, config, clients, shutdown).await;
         });
     }
+
+    drop(listener);
+    let notified = clients.lock().await.broadcast(ClientSignal::Shutdown);
+    log::info!("Shutting down, notifying {} clients", notified);
+    let drained = tokio::time::timeout(SHUTDOWN_GRACE_PERIOD, async {
+        while workers.join_next().await.is_some() {}
+    }).await;
+    if drained.is_err() {
+        log::warn!("Not all clients were notified within {:?}", SHUTDOWN_GRACE_PERIOD);
+    }
 }
 
 
@@ -192,68 +417,172 @@ fn config_path(file: &str) -> PathBuf {
     path
 }
 
-fn merge_config(config: config::Config, cli: Cli) -> config::Config {
-    let mut config = config;
-    // The CLI Args override the config file
-    // Therefore if port is in the config and different to the CLI port, we override it or if it's just not there
-    if config.port.is_some_and(|x| x != cli.port.unwrap_or(3000)) || config.port.is_none() {
-        config.port = Some(cli.port.unwrap_or(3000));
+/// Resolves a relative key location against the key dir. Absolute locations and a missing key dir leave it untouched
+fn key_path(key_dir: Option<&Path>, key_loc: &str) -> PathBuf {
+    let key_loc = Path::new(key_loc);
+    match key_dir {
+        Some(dir) if key_loc.is_relative() => dir.join(key_loc),
+        _ => key_loc.to_path_buf(),
     }
-    // Same goes for host
-    if config.host.is_some_and(|x| x != cli.host) || config.host.is_none() {
-        config.host = Some(cli.host);
+}
+
+/// Generates and writes a new identity in place of a missing key file, if key_generation allows it
+fn generate_identity(path: &Path, generation: &config::KeyGeneration) -> Result<Identity, String> {
+    if !generation.enabled {
+        return Err(format!("Not generating a new identity at {}, clients trusting the current public key couldn't connect anymore. Enable key_generation in the config if this is intended", path.display()));
     }
-    // Same goes for brotli_effort
-    if config.brotli_quality.is_some_and(|x| x != cli.brotli_effort) || config.brotli_quality.is_none() {
-        config.brotli_quality = Some(cli.brotli_effort);
+    log::warn!("Generating new identity file {}", path.display());
+    let key = Identity::generate();
+    write_key_file(path, key.to_string().expose_secret()).map_err(|err| format!("Error writing identity file: {}", err))?;
+    Ok(key)
+}
+
+/// Picks the value of one setting. Given on the command line or in the environment beats the config file, which beats the default
+fn merge_setting<T: std::fmt::Debug>(name: &str, source: Option<ValueSource>, cli: Option<T>, config: Option<T>, default: Option<T>) -> Option<T> {
+    let (value, from) = match source {
+        Some(ValueSource::CommandLine) => (cli, "command line"),
+        Some(ValueSource::EnvVariable) => (cli, "environment"),
+        _ if config.is_some() => (config, "config file"),
+        _ => (default, "default"),
+    };
+    match &value {
+        Some(value) => log::debug!("Using {} {:?} from the {}", name, value, from),
+        None => log::debug!("{} isn't set", name),
     }
-    // And for the private key loc
-    // This is a little more lengthy because Strings cannot be copied like numbers
-    if config.private_key_loc.clone().is_some_and(|x| x != cli.private_key_loc.clone().unwrap_or(String::from("server-identity.age"))) || config.private_key_loc.is_none() {
-        config.private_key_loc = cli.private_key_loc.map_or_else(|| Some(String::from("server-identity.age")), |x| Some(x));
+    value
+}
+
+/// matches tells which CLI args were actually given, the defaults of clap are only used if the config file doesn't set them either
+fn merge_config(config: config::Config, cli: Cli, matches: &ArgMatches) -> config::Config {
+    let mut config = config;
+    if config.node_id.is_none() {
+        config.node_id = Some(Uuid::new_v4().to_string());
     }
+    config.port = merge_setting("port", matches.value_source("port"), cli.port, config.port, Some(3000));
+    config.host = merge_setting("host", matches.value_source("host"), Some(cli.host), config.host, Some(cli.host));
+    config.brotli_quality = merge_setting("brotli_quality", matches.value_source("brotli_effort"), Some(cli.brotli_effort), config.brotli_quality, Some(cli.brotli_effort));
+    config.private_key_loc = merge_setting("private_key_loc", matches.value_source("private_key_loc"), cli.private_key_loc, config.private_key_loc, Some(String::from("server-identity.age")));
+    // There is no default key dir
+    config.key_dir = merge_setting("key_dir", matches.value_source("key_dir"), cli.key_dir, config.key_dir, None);
     config
 }
 
-fn populate_command_registry() -> HashMap<CommandID, Box<dyn commands::Command>> {
+fn populate_command_registry(config: &config::Config, slowlog: &Arc<Mutex<SlowLog>>, shutdown: &Arc<Notify>, clients: &Arc<Mutex<ClientRegistry>>) -> HashMap<CommandID, Box<dyn commands::Command>> {
     let mut registry: HashMap<CommandID, Box<dyn commands::Command>> = HashMap::new();
     registry.insert(CommandID::Get, Box::new(GetCommand {}));
     registry.insert(CommandID::Set, Box::new(SetCommand {}));
     registry.insert(CommandID::Delete, Box::new(DeleteCommand {}));
     registry.insert(CommandID::Heartbeat, Box::new(HeartbeatCommand {}));
     registry.insert(CommandID::AclList, Box::new(AclListCommand {}));
-    registry.insert(CommandID::AclSet, Box::new(AclSetCommand {}));
-    registry.insert(CommandID::AclRemove, Box::new(AclRemoveCommand {}));
+    registry.insert(CommandID::AclSet, Box::new(AclSetCommand::default()));
+    registry.insert(CommandID::AclRemove, Box::new(AclRemoveCommand::default()));
     registry.insert(CommandID::Login, Box::new(LoginCommand::default()));
     registry.insert(CommandID::KEYEXCHANGE, Box::new(KeyExchangeCommand::default()));
     registry.insert(CommandID::HGET, Box::new(HashMapGetCommand {}));
     registry.insert(CommandID::HSET, Box::new(HashMapSetCommand {}));
     registry.insert(CommandID::HDEL, Box::new(HashMapDeleteCommand {}));
-    registry.insert(CommandID::HKEYS, Box::new(HashMapKeysCommand {}));
-    registry.insert(CommandID::HVALS, Box::new(HashMapValuesCommand {}));
+    registry.insert(CommandID::HKEYS, Box::new(HashMapKeysCommand::new(config.max_collection_response)));
+    registry.insert(CommandID::HVALS, Box::new(HashMapValuesCommand::new(config.max_collection_response)));
     registry.insert(CommandID::HLEN, Box::new(HashMapLenCommand {}));
-    registry.insert(CommandID::HGETALL, Box::new(HashMapGetAllCommand {}));
+    registry.insert(CommandID::HGETALL, Box::new(HashMapGetAllCommand::new(config.max_collection_response)));
     registry.insert(CommandID::HEXISTS, Box::new(HashMapExistsCommand {}));
     registry.insert(CommandID::HINCRBY, Box::new(HashMapIncrByCommand {}));
     registry.insert(CommandID::HSTRLEN, Box::new(HashMapStringLenCommand {}));
     registry.insert(CommandID::HUPSERT, Box::new(HashMapUpsertCommand {}));
-    registry.insert(CommandID::UserRemove, Box::new(commands::UserRemoveCommand {}));
+    registry.insert(CommandID::UserRemove, Box::new(commands::UserRemoveCommand::default()));
     registry.insert(CommandID::ClientID, Box::new(commands::ClientIDCommand::default()));
-    registry.insert(CommandID::Shutdown, Box::new(commands::ShutdownCommand {}));
+    registry.insert(CommandID::Shutdown, Box::new(commands::ShutdownCommand::new(shutdown.clone())));
+    registry.insert(CommandID::LPUSHUNIQUE, Box::new(commands::LPushUniqueCommand {}));
+    registry.insert(CommandID::DRAIN, Box::new(commands::DrainCommand {}));
+    registry.insert(CommandID::JSONGET, Box::new(commands::JsonGetCommand {}));
+    registry.insert(CommandID::JSONSET, Box::new(commands::JsonSetCommand {}));
+    registry.insert(CommandID::COMPARESET, Box::new(commands::CompareSetCommand {}));
+    registry.insert(CommandID::SlowLogGet, Box::new(commands::SlowLogGetCommand::new(slowlog.clone())));
+    registry.insert(CommandID::SlowLogReset, Box::new(commands::SlowLogResetCommand::new(slowlog.clone())));
+    registry.insert(CommandID::HKEYSMATCH, Box::new(commands::HKeysMatchCommand {}));
+    registry.insert(CommandID::KEYINFO, Box::new(commands::KeyInfoCommand {}));
+    registry.insert(CommandID::HGETSORTED, Box::new(commands::HGetSortedCommand {}));
+    registry.insert(CommandID::MULTIHSET, Box::new(commands::MultiHSetCommand {}));
+    registry.insert(CommandID::LGET, Box::new(commands::LGetCommand {}));
+    registry.insert(CommandID::HDUMP, Box::new(commands::HDumpCommand {}));
+    registry.insert(CommandID::HRESTORE, Box::new(commands::HRestoreCommand {}));
+    registry.insert(CommandID::LROTATE, Box::new(commands::LRotateCommand {}));
+    registry.insert(CommandID::MEMORYUSAGE, Box::new(commands::MemoryUsageCommand {}));
+    registry.insert(CommandID::ConnStats, Box::new(commands::ConnStatsCommand::default()));
+    registry.insert(CommandID::MULTILOCK, Box::new(commands::MultiLockCommand {}));
+    registry.insert(CommandID::WATCH, Box::new(commands::WatchKeyCommand::new(clients.clone())));
+    registry.insert(CommandID::UNWATCH, Box::new(commands::UnwatchKeyCommand::default()));
+    registry.insert(CommandID::Time, Box::new(commands::TimeCommand {}));
+    registry.insert(CommandID::TAG, Box::new(commands::TagCommand {}));
+    registry.insert(CommandID::UNTAG, Box::new(commands::UntagCommand {}));
+    registry.insert(CommandID::KEYSBYTAG, Box::new(commands::KeysByTagCommand {}));
+    registry.insert(CommandID::Compact, Box::new(commands::CompactCommand {}));
+    registry.insert(CommandID::HFILTER, Box::new(commands::HFilterCommand {}));
+    registry.insert(CommandID::HSWAP, Box::new(commands::HSwapCommand {}));
+    registry.insert(CommandID::LINTER, Box::new(commands::LInterCommand {}));
+    registry.insert(CommandID::LDIFF, Box::new(commands::LDiffCommand {}));
+    registry.insert(CommandID::GETORSET, Box::new(commands::GetOrSetCommand {}));
+    registry.insert(CommandID::HTOP, Box::new(commands::HTopCommand {}));
+    registry.insert(CommandID::ReKey, Box::new(commands::ReKeyCommand::default()));
+    registry.insert(CommandID::LMULTILEN, Box::new(commands::LMultiLenCommand {}));
+    registry.insert(CommandID::DECRDEL, Box::new(commands::DecrDelCommand {}));
+    registry.insert(CommandID::DebugCheck, Box::new(commands::DebugCheckCommand {}));
+    registry.insert(CommandID::SessionCommands, Box::new(commands::SessionCommandsCommand::default()));
+    registry.insert(CommandID::RecentlyModified, Box::new(commands::RecentlyModifiedCommand {}));
+    registry.insert(CommandID::Features, Box::new(commands::FeaturesCommand::new(config)));
+    registry.insert(CommandID::Role, Box::new(commands::RoleCommand {}));
+    registry.insert(CommandID::PauseWrites, Box::new(commands::PauseWritesCommand {}));
+    registry.insert(CommandID::ResumeWrites, Box::new(commands::ResumeWritesCommand {}));
+    registry.insert(CommandID::Digest, Box::new(commands::DigestCommand {}));
+    registry.insert(CommandID::AclExport, Box::new(commands::AclExportCommand {}));
+    registry.insert(CommandID::AclImport, Box::new(commands::AclImportCommand::default()));
+    registry.insert(CommandID::APPENDIF, Box::new(commands::AppendIfCommand {}));
+    registry.insert(CommandID::KEYSOFTYPE, Box::new(commands::KeysOfTypeCommand {}));
+    registry.insert(CommandID::PROTECT, Box::new(commands::ProtectCommand {}));
+    registry.insert(CommandID::UNPROTECT, Box::new(commands::UnprotectCommand {}));
+    registry.insert(CommandID::LREPLACE, Box::new(commands::LReplaceCommand {}));
+    registry.insert(CommandID::LPOPTOLIST, Box::new(commands::LPopToListCommand {}));
+    registry.insert(CommandID::KEYPAGE, Box::new(commands::KeyPageCommand {}));
+    registry.insert(CommandID::SETWITHVERSION, Box::new(commands::SetWithVersionCommand {}));
+    registry.insert(CommandID::SecurityInfo, Box::new(commands::SecurityInfoCommand::default()));
+    registry.insert(CommandID::LLEN, Box::new(commands::LlenCommand {}));
+    registry.insert(CommandID::LINDEX, Box::new(commands::LindexCommand {}));
+    registry.insert(CommandID::LMOVE, Box::new(commands::LmoveCommand {}));
+    registry.insert(CommandID::LPOP, Box::new(commands::LpopCommand {}));
+    registry.insert(CommandID::LPOS, Box::new(commands::LposCommand {}));
+    registry.insert(CommandID::LPUSH, Box::new(commands::LpushCommand {}));
+    registry.insert(CommandID::LPUSHX, Box::new(commands::LpushxCommand {}));
+    registry.insert(CommandID::LRANGE, Box::new(commands::LrangeCommand {}));
+    registry.insert(CommandID::LREM, Box::new(commands::LremCommand {}));
+    registry.insert(CommandID::LSET, Box::new(commands::LsetCommand {}));
+    registry.insert(CommandID::LTRIM, Box::new(commands::LtrimCommand {}));
+    registry.insert(CommandID::RPOP, Box::new(commands::RpopCommand {}));
+    registry.insert(CommandID::RPUSH, Box::new(commands::RpushCommand {}));
+    registry.insert(CommandID::RPUSHX, Box::new(commands::RpushxCommand {}));
+    registry.insert(CommandID::SIZEHISTOGRAM, Box::new(commands::SizeHistogramCommand {}));
+    registry.insert(CommandID::CloseIdle, Box::new(commands::CloseIdleCommand::new(clients.clone())));
+    registry.insert(CommandID::Expire, Box::new(commands::ExpireCommand {}));
+    registry.insert(CommandID::Ttl, Box::new(commands::TtlCommand {}));
+    registry.insert(CommandID::Persist, Box::new(commands::PersistCommand {}));
+    registry.insert(CommandID::RENAMETYPED, Box::new(commands::RenameTypedCommand {}));
+    registry.insert(CommandID::Exists, Box::new(commands::ExistsCommand {}));
+    registry.insert(CommandID::Type, Box::new(commands::TypeCommand {}));
+    // Has to be last, so it sees every other command
+    let mut supported: Vec<CommandID> = registry.keys().copied().collect();
+    supported.push(CommandID::CommandList);
+    // Not in the registry, handle_message runs it
+    supported.push(CommandID::PIPELINE);
+    registry.insert(CommandID::CommandList, Box::new(commands::CommandListCommand::new(supported)));
 
     registry
 }
 
-#[tokio::main]
-async fn main() {
-    init_env_logger();
-
-    let cli = Cli::parse();
-
+/// Loads config.yaml from the data dir, writing the default config there if there is none
+fn load_default_config() -> config::Config {
     let config_path = config_path("config.yaml");
     log::debug!("Using config file: {}", config_path.display());
     let config_string = std::fs::read_to_string(config_path.clone());
-    let config = match config_string {
+    match config_string {
         Ok(config) => {
             let config = match serde_yaml::from_str(&config) {
                 Ok(config) => config,
@@ -266,7 +595,7 @@ async fn main() {
         }
         Err(_) => {
             log::warn!("No config file found or not readable. Using default config");
-            let conf = config::Config::default();
+            let mut conf = config::Config::default();
             // Save the default config
             let parent = config_path.parent().unwrap();
             if !parent.exists() {
@@ -279,14 +608,41 @@ async fn main() {
                 }
             }
             conf.save(&config_path).unwrap();
+            // A new setup has no key yet either. Only this first start may create one, the saved config doesn't allow it
+            conf.key_generation.enabled = true;
             conf
         }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    init_env_logger();
+
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+
+    let config = if let Some(path) = &cli.config {
+        // An explicitly given config is never replaced by a default, a typo in the path should not go unnoticed
+        log::debug!("Using config file: {}", path.display());
+        match config::Config::load(path) {
+            Ok(config) => config,
+            Err(err) => {
+                log::error!("Error loading config file {}: {}", path.display(), err);
+                std::process::exit(-1);
+            }
+        }
+    } else {
+        load_default_config()
     };
-    let config = merge_config(config, cli);
+    let config_file = cli.config.clone().unwrap_or_else(|| config_path("config.yaml"));
+    let config = merge_config(config, cli, &matches);
     // config.private_key_loc will be some, because it's set in the merging if it's not there
-    log::debug!("Loading private key: {:?}", config.private_key_loc);
-    let private_key = match std::fs::File::open(config.private_key_loc.clone().unwrap()) {
+    let private_key_path = key_path(config.key_dir.as_deref(), config.private_key_loc.as_ref().unwrap());
+    log::debug!("Loading private key: {}", private_key_path.display());
+    let private_key = match std::fs::File::open(&private_key_path) {
         Ok(mut file) => {
+            warn_if_permissive(&private_key_path);
             let mut buf = Vec::new();
             match file.read_to_end(&mut buf) {
                 Ok(_) => {}
@@ -311,25 +667,49 @@ async fn main() {
                 }
             }
         }
-        Err(_) => {
-            log::warn!("No identity file found or not readable. Generating new identity file");
-            let key = Identity::generate();
-            match std::fs::write(config.private_key_loc.unwrap(), key.to_string().expose_secret()) {
-                Ok(_) => {}
+        Err(err) => {
+            log::warn!("Error opening identity file {}: {}", private_key_path.display(), err);
+            let key = match generate_identity(&private_key_path, &config.key_generation) {
+                Ok(key) => key,
                 Err(err) => {
-                    log::error!("Error writing identity file: {}", err);
+                    log::error!("{}", err);
                     std::process::exit(-1);
                 }
+            };
+            if config.key_generation.confirm {
+                log::warn!("Generated identity with public key \"{}\". Hand it to the clients and start the server again", key.to_public());
+                std::process::exit(0);
             }
             key
         }
     };
     let public_key = private_key.to_public();
     log::info!("Public key: \"{}\"", public_key);
-    let store = Arc::new(RwLock::new(Store::default()));
+    let limits = TypeLimits {
+        max_strings: config.max_strings,
+        max_hashes: config.max_hashes,
+        max_lists: config.max_lists,
+        max_list_length: config.max_list_length,
+        max_hash_fields: config.max_hash_fields,
+        trim_full_lists: config.trim_full_lists,
+    };
+    let store = Arc::new(RwLock::new(Store::new(config.fast_hash, limits)));
+
+    let aliases = match CommandAliases::new(&config.command_aliases) {
+        Ok(aliases) => Arc::new(aliases),
+        Err(err) => {
+            log::error!("Error in command_aliases: {}", err);
+            std::process::exit(-1);
+        }
+    };
 
     let mut locked = store.write().await;
-    for user in config.users {
+    locked.set_strict_types(config.strict_types);
+    locked.set_max_key_length(config.max_key_length);
+    if config.persist_auth_changes {
+        locked.set_auth_persistence(AuthPersistence::new(config_file));
+    }
+    for user in config.users.clone() {
         if user.name.is_empty() {
             log::warn!("User has no name. Skipping");
             continue;
@@ -363,7 +743,7 @@ async fn main() {
             }
         }
         for acl in user.acls {
-            let command = str_to_command_id(acl);
+            let command = aliases.resolve(&acl);
             match command {
                 Ok(command) => {
                     locked.acl_add(&user.name, command)
@@ -374,7 +754,382 @@ async fn main() {
             }
         }
     }
+    for acl in config.anonymous_acls.clone() {
+        match aliases.resolve(&acl) {
+            Ok(command) => {
+                log::debug!("Allowing {} for anonymous connections", command);
+                locked.acl_add_anonymous(command)
+            }
+            Err(err) => {
+                log::warn!("Error parsing command: {}", err);
+            }
+        }
+    }
+    for command in config.disabled_commands.clone() {
+        match aliases.resolve(&command) {
+            Ok(command) => {
+                log::info!("Disabling {}", command);
+                locked.acl_disable(command)
+            }
+            Err(err) => {
+                // Continuing would leave a command enabled that the operator wanted gone
+                log::error!("Error parsing disabled command: {}", err);
+                std::process::exit(-1);
+            }
+        }
+    }
     drop(locked);
 
-    socket_listener(config.host.unwrap(), config.port.unwrap(), config.brotli_quality.unwrap(), store, private_key).await;
+    let slowlog = Arc::new(Mutex::new(SlowLog::new(config.slowlog_threshold_ms.map(Duration::from_millis), config.slowlog_max_len.unwrap_or(128))));
+
+    let shutdown = Arc::new(Notify::new());
+
+    let clients = Arc::new(Mutex::new(ClientRegistry::default()));
+
+    tokio::spawn(expiry_sweeper(store.clone()));
+
+    let config = Arc::new(config);
+    if let Some(port) = config.plaintext_port {
+        tokio::spawn(plaintext::plaintext_listener(port, config.clone(), store.clone(), slowlog.clone(), shutdown.clone(), clients.clone(), aliases));
+    }
+
+    socket_listener(config, store, private_key, slowlog, shutdown, clients).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::StoreAble;
+
+    fn parse(args: &[&str]) -> (Cli, ArgMatches) {
+        let matches = Cli::command().try_get_matches_from(std::iter::once("in-mem").chain(args.iter().copied())).unwrap();
+        let cli = Cli::from_arg_matches(&matches).unwrap();
+        (cli, matches)
+    }
+
+    #[test]
+    fn merge_setting_prefers_command_line_and_environment() {
+        assert_eq!(merge_setting("port", Some(ValueSource::CommandLine), Some(1), Some(2), Some(3)), Some(1));
+        assert_eq!(merge_setting("port", Some(ValueSource::EnvVariable), Some(1), Some(2), Some(3)), Some(1));
+    }
+
+    #[test]
+    fn merge_setting_falls_back_to_config_then_default() {
+        assert_eq!(merge_setting("port", Some(ValueSource::DefaultValue), Some(1), Some(2), Some(3)), Some(2));
+        assert_eq!(merge_setting("port", None, None, Some(2), Some(3)), Some(2));
+        assert_eq!(merge_setting("port", Some(ValueSource::DefaultValue), Some(1), None, Some(3)), Some(3));
+        assert_eq!(merge_setting::<u16>("port", None, None, None, None), None);
+    }
+
+    #[test]
+    fn merge_config_cli_port_overrides_config_port() {
+        let (cli, matches) = parse(&["127.0.0.1", "4000"]);
+        let config = config::Config {
+            port: Some(5000),
+            ..Default::default()
+        };
+        assert_eq!(merge_config(config, cli, &matches).port, Some(4000));
+    }
+
+    fn merge(args: &[&str], config: config::Config) -> config::Config {
+        let (cli, matches) = parse(args);
+        merge_config(config, cli, &matches)
+    }
+
+    fn config_with(port: u16, host: &str, brotli_quality: u8) -> config::Config {
+        config::Config {
+            port: Some(port),
+            host: Some(host.parse().unwrap()),
+            brotli_quality: Some(brotli_quality),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn merge_config_keeps_config_only_settings() {
+        let config = merge(&[], config_with(5000, "0.0.0.0", 3));
+        assert_eq!(config.port, Some(5000));
+        assert_eq!(config.host, Some("0.0.0.0".parse().unwrap()));
+        assert_eq!(config.brotli_quality, Some(3));
+    }
+
+    #[test]
+    fn merge_config_uses_cli_only_settings() {
+        let config = merge(&["--brotli-effort", "9", "::1", "4000"], config::Config::default());
+        assert_eq!(config.port, Some(4000));
+        assert_eq!(config.host, Some("::1".parse().unwrap()));
+        assert_eq!(config.brotli_quality, Some(9));
+    }
+
+    #[test]
+    fn merge_config_prefers_cli_when_both_are_set() {
+        let config = merge(&["--brotli-effort", "9", "::1", "4000"], config_with(5000, "0.0.0.0", 3));
+        assert_eq!(config.port, Some(4000));
+        assert_eq!(config.host, Some("::1".parse().unwrap()));
+        assert_eq!(config.brotli_quality, Some(9));
+    }
+
+    #[test]
+    fn merge_config_uses_defaults_when_neither_is_set() {
+        let config = merge(&[], config::Config::default());
+        assert_eq!(config.port, Some(3000));
+        assert_eq!(config.host, Some("127.0.0.1".parse().unwrap()));
+        assert_eq!(config.brotli_quality, Some(6));
+    }
+
+    #[test]
+    fn accept_backoff_doubles_up_to_the_cap() {
+        assert_eq!(accept_backoff(0), Duration::ZERO);
+        assert_eq!(accept_backoff(1), ACCEPT_BACKOFF_BASE);
+        assert_eq!(accept_backoff(2), ACCEPT_BACKOFF_BASE * 2);
+        assert_eq!(accept_backoff(5), ACCEPT_BACKOFF_BASE * 16);
+        // 5ms * 2^8 is past the one second cap
+        assert_eq!(accept_backoff(9), ACCEPT_BACKOFF_MAX);
+        assert_eq!(accept_backoff(17), ACCEPT_BACKOFF_MAX);
+        assert_eq!(accept_backoff(u32::MAX), ACCEPT_BACKOFF_MAX);
+    }
+
+    fn command(command_id: CommandID, payload: impl serde::Serialize) -> Command {
+        Command { command_id, payload: bson::to_bson(&payload).unwrap() }
+    }
+
+    #[tokio::test]
+    async fn pipeline_reads_a_list_range_and_a_hash_in_one_request() {
+        let store = commands::test_store();
+        {
+            let mut store = store.write().await;
+            for command in [CommandID::PIPELINE, CommandID::LPUSH, CommandID::MULTIHSET, CommandID::LRANGE, CommandID::HGETALL] {
+                store.acl_add_anonymous(command);
+            }
+        }
+        let config = config::Config::default();
+        let slowlog = Arc::new(Mutex::new(SlowLog::new(None, 16)));
+        let mut registry = populate_command_registry(&config, &slowlog, &Arc::new(Notify::new()), &Arc::new(Mutex::new(ClientRegistry::default())));
+        let (mut connection, _peer) = commands::test_connection().await;
+
+        let pipeline = PipelineCommandInput {
+            commands: vec![
+                command(CommandID::LPUSH, common::command_input::LPushCommandInput { list: "list".to_string(), values: vec!["a".to_string(), "b".to_string(), "c".to_string()] }),
+                command(CommandID::MULTIHSET, bson::doc! { "hashes": { "hash": { "field": "value" } } }),
+                command(CommandID::LRANGE, common::command_input::LRangeCommandInput { list: "list".to_string(), start: 0, stop: 2 }),
+                command(CommandID::HGETALL, bson::doc! { "key": "hash", "field": "" }),
+                // Not allowed for anonymous connections, without stopping the commands around it
+                command(CommandID::Get, bson::doc! { "key": "list" }),
+                command(CommandID::LRANGE, common::command_input::LRangeCommandInput { list: "missing".to_string(), start: 0, stop: -1 }),
+            ],
+        };
+        let message = Message::new_command(Uuid::new_v4(), command(CommandID::PIPELINE, &pipeline));
+        let rsp = handle_message(message, &mut connection, &store, false, Uuid::new_v4(), &mut registry, &slowlog).await.unwrap();
+        let MessageContent::Response(MessageResponse { content: Some(Bson::Array(responses)), status: OperationStatus::Success }) = rsp.content else {
+            panic!("expected an array of responses, got {:?}", rsp.content);
+        };
+        let responses = responses.into_iter().map(|rsp| bson::from_bson::<MessageResponse>(rsp).unwrap()).collect::<Vec<_>>();
+        let statuses = responses.iter().map(|rsp| rsp.status).collect::<Vec<_>>();
+        assert_eq!(statuses, vec![OperationStatus::Success, OperationStatus::Success, OperationStatus::Success, OperationStatus::Success, OperationStatus::NotAllowed, OperationStatus::Success]);
+        assert_eq!(responses[2].content, Some(Bson::Array(vec![Bson::String("a".to_string()), Bson::String("b".to_string())])));
+        assert_eq!(responses[3].content, Some(Bson::Document(bson::doc! { "field": "value" })));
+        assert_eq!(responses[5].content, Some(Bson::Array(vec![])));
+    }
+
+    #[tokio::test]
+    async fn list_commands_are_registered() {
+        let list_commands = [CommandID::LLEN, CommandID::LINDEX, CommandID::LMOVE, CommandID::LPOP, CommandID::LPOS, CommandID::LPUSH, CommandID::LPUSHX, CommandID::LRANGE, CommandID::LREM, CommandID::LSET, CommandID::LTRIM, CommandID::RPOP, CommandID::RPUSH, CommandID::RPUSHX];
+        let store = commands::test_store();
+        {
+            let mut store = store.write().await;
+            store.acl_add_anonymous(CommandID::PIPELINE);
+            for command in list_commands {
+                store.acl_add_anonymous(command);
+            }
+        }
+        let config = config::Config::default();
+        let slowlog = Arc::new(Mutex::new(SlowLog::new(None, 16)));
+        let mut registry = populate_command_registry(&config, &slowlog, &Arc::new(Notify::new()), &Arc::new(Mutex::new(ClientRegistry::default())));
+        for command in list_commands {
+            assert!(registry.contains_key(&command), "{} isn't registered", command);
+        }
+        let (mut connection, _peer) = commands::test_connection().await;
+
+        let pipeline = PipelineCommandInput {
+            commands: vec![
+                command(CommandID::RPUSH, common::command_input::RPushCommandInput { list: "list".to_string(), values: vec!["a".to_string(), "b".to_string(), "c".to_string()] }),
+                command(CommandID::LPOP, common::command_input::LPopCommandInput { list: "list".to_string(), count: None }),
+                command(CommandID::LLEN, common::command_input::LLenCommandInput { list: "list".to_string() }),
+            ],
+        };
+        let message = Message::new_command(Uuid::new_v4(), command(CommandID::PIPELINE, &pipeline));
+        let rsp = handle_message(message, &mut connection, &store, false, Uuid::new_v4(), &mut registry, &slowlog).await.unwrap();
+        let MessageContent::Response(MessageResponse { content: Some(Bson::Array(responses)), status: OperationStatus::Success }) = rsp.content else {
+            panic!("expected an array of responses, got {:?}", rsp.content);
+        };
+        let responses = responses.into_iter().map(|rsp| bson::from_bson::<MessageResponse>(rsp).unwrap()).collect::<Vec<_>>();
+        assert!(responses.iter().all(|rsp| rsp.status == OperationStatus::Success), "{:?}", responses);
+        // Both pushes append and LPOP takes from the back, so the last pushed value comes out
+        assert_eq!(responses[1].content, Some(Bson::Array(vec![Bson::String("c".to_string())])));
+        assert_eq!(responses[2].content, Some(Bson::String("2".to_string())));
+    }
+
+    /// Runs one command the way a worker loop does, on a connection and command registry of its own
+    async fn execute(store: Arc<RwLock<Store>>, command_id: CommandID, payload: bson::Document) -> OperationStatus {
+        let slowlog = Arc::new(Mutex::new(SlowLog::new(None, 16)));
+        let mut registry = populate_command_registry(&config::Config::default(), &slowlog, &Arc::new(Notify::new()), &Arc::new(Mutex::new(ClientRegistry::default())));
+        let (mut connection, _peer) = commands::test_connection().await;
+        let cmd = command(command_id, payload);
+        let message = Message::new_command(Uuid::new_v4(), cmd.clone());
+        execute_command(cmd, &message, &mut connection, &store, false, &mut registry, &slowlog).await.unwrap().status
+    }
+
+    #[tokio::test]
+    async fn paused_writes_wait_while_reads_proceed() {
+        let store = commands::test_store();
+        {
+            let mut store = store.write().await;
+            for command in [CommandID::Get, CommandID::Set, CommandID::PauseWrites, CommandID::ResumeWrites] {
+                store.acl_add_anonymous(command);
+            }
+        }
+        let set = |value: &str| tokio::spawn(execute(store.clone(), CommandID::Set, bson::doc! { "key": "key", "value": value }));
+        assert_eq!(set("before").await.unwrap(), OperationStatus::Success);
+
+        assert_eq!(execute(store.clone(), CommandID::PauseWrites, bson::doc! {}).await, OperationStatus::Success);
+        let pending = set("resumed");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!pending.is_finished());
+        let get = tokio::time::timeout(Duration::from_secs(1), execute(store.clone(), CommandID::Get, bson::doc! { "key": "key" }));
+        assert_eq!(get.await.expect("reads aren't paused"), OperationStatus::Success);
+        assert_eq!(store.read().await.get("key"), Some(&"before".to_string()));
+        assert_eq!(execute(store.clone(), CommandID::ResumeWrites, bson::doc! {}).await, OperationStatus::Success);
+        assert_eq!(tokio::time::timeout(Duration::from_secs(1), pending).await.unwrap().unwrap(), OperationStatus::Success);
+        assert_eq!(store.read().await.get("key"), Some(&"resumed".to_string()));
+
+        assert_eq!(execute(store.clone(), CommandID::PauseWrites, bson::doc! { "timeout_secs": 1_i64 }).await, OperationStatus::Success);
+        let pending = set("timed out");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!pending.is_finished());
+        assert_eq!(tokio::time::timeout(Duration::from_secs(3), pending).await.unwrap().unwrap(), OperationStatus::Success);
+        assert_eq!(store.read().await.get("key"), Some(&"timed out".to_string()));
+    }
+
+    async fn connect_when_listening(port: u16) -> Connection {
+        loop {
+            match tokio::net::TcpStream::connect(("127.0.0.1", port)).await {
+                Ok(socket) => return Connection::new(socket, Uuid::new_v4(), 6),
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        }
+    }
+
+    async fn request(connection: &mut Connection, key: &Identity, command_id: CommandID) -> OperationStatus {
+        connection.send_message(&Message::new_command(Uuid::new_v4(), Command { command_id, payload: Bson::Null })).await.unwrap();
+        next_status(connection, key).await
+    }
+
+    async fn next_status(connection: &mut Connection, key: &Identity) -> OperationStatus {
+        match connection.read_message(key).await.unwrap().0.content {
+            MessageContent::Response(rsp) => rsp.status,
+            content => panic!("expected a response, got {:?}", content),
+        }
+    }
+
+    /// Runs socket_listener on a free port with the settings of config and anonymous access to commands.
+    /// Returns the port and the public key of the server
+    fn spawn_listener(config: config::Config, commands: &[CommandID]) -> (u16, Recipient, tokio::task::JoinHandle<()>) {
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let config = Arc::new(config::Config {
+            host: Some("127.0.0.1".parse().unwrap()),
+            port: Some(port),
+            brotli_quality: Some(6),
+            ..config
+        });
+        let store = commands::test_store();
+        for command in commands {
+            store.try_write().unwrap().acl_add_anonymous(*command);
+        }
+        let slowlog = Arc::new(Mutex::new(SlowLog::new(None, 16)));
+        let server_key = Identity::generate();
+        let server_public_key = server_key.to_public();
+        let listener = tokio::spawn(socket_listener(config, store, server_key, slowlog, Arc::new(Notify::new()), Arc::new(Mutex::new(ClientRegistry::default()))));
+        (port, server_public_key, listener)
+    }
+
+    #[tokio::test]
+    async fn shutdown_notifies_every_connected_client() {
+        let (port, _, listener) = spawn_listener(config::Config::default(), &[CommandID::Heartbeat, CommandID::Shutdown]);
+        let key = Identity::generate();
+
+        // Answering a heartbeat means the worker is registered for signals
+        let mut idle = connect_when_listening(port).await;
+        assert_eq!(request(&mut idle, &key, CommandID::Heartbeat).await, OperationStatus::Success);
+        let mut requester = connect_when_listening(port).await;
+        assert_eq!(request(&mut requester, &key, CommandID::Shutdown).await, OperationStatus::Success);
+
+        assert_eq!(next_status(&mut requester, &key).await, OperationStatus::ServerShutdown);
+        assert_eq!(next_status(&mut idle, &key).await, OperationStatus::ServerShutdown);
+        tokio::time::timeout(SHUTDOWN_GRACE_PERIOD, listener).await.unwrap().unwrap();
+        assert!(tokio::net::TcpStream::connect(("127.0.0.1", port)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn require_key_exchange_first_closes_connections_that_skip_it() {
+        let config = config::Config { require_key_exchange_first: true, ..Default::default() };
+        let (port, server_public_key, listener) = spawn_listener(config, &[CommandID::Heartbeat, CommandID::KEYEXCHANGE]);
+        let key = Identity::generate();
+
+        let mut skipping = connect_when_listening(port).await;
+        skipping.send_message(&Message::new_command(Uuid::new_v4(), Command { command_id: CommandID::Heartbeat, payload: Bson::Null })).await.unwrap();
+        assert!(skipping.read_message(&key).await.is_err());
+
+        let mut exchanging = connect_when_listening(port).await;
+        exchanging.set_pub_key(server_public_key);
+        let kex = common::command_input::KeyExchangeCommandInput { pub_key: key.to_public().to_string(), checksum: false };
+        exchanging.send_message(&Message::new_command(Uuid::new_v4(), command(CommandID::KEYEXCHANGE, kex))).await.unwrap();
+        assert_eq!(next_status(&mut exchanging, &key).await, OperationStatus::Success);
+        assert_eq!(request(&mut exchanging, &key, CommandID::Heartbeat).await, OperationStatus::Success);
+        listener.abort();
+    }
+
+    #[test]
+    fn key_path_resolves_relative_locations_against_key_dir() {
+        assert_eq!(key_path(Some(Path::new("/keys")), "server-identity.age"), PathBuf::from("/keys/server-identity.age"));
+        assert_eq!(key_path(Some(Path::new("/keys")), "/etc/in-mem/server-identity.age"), PathBuf::from("/etc/in-mem/server-identity.age"));
+        assert_eq!(key_path(None, "server-identity.age"), PathBuf::from("server-identity.age"));
+        let config = merge(&["--key-dir", "/keys"], config::Config { key_dir: Some(PathBuf::from("/config-keys")), ..Default::default() });
+        assert_eq!(config.key_dir, Some(PathBuf::from("/keys")));
+    }
+
+    #[test]
+    fn missing_key_is_only_generated_when_enabled() {
+        let path = std::env::temp_dir().join(format!("in-mem-identity-{}.age", Uuid::new_v4()));
+        let Err(refused) = generate_identity(&path, &config::KeyGeneration::default()) else { panic!("expected a refusal") };
+        assert!(refused.contains(&path.display().to_string()));
+        assert!(!path.exists());
+        let Ok(key) = generate_identity(&path, &config::KeyGeneration { enabled: true, confirm: false }) else { panic!("expected a new identity") };
+        let written = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(Identity::from_str(&written).unwrap().to_public().to_string(), key.to_public().to_string());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn config_path_is_taken_from_the_command_line() {
+        let (cli, _) = parse(&["--config", "/etc/in-mem/config.yaml"]);
+        assert_eq!(cli.config, Some(PathBuf::from("/etc/in-mem/config.yaml")));
+        let (cli, _) = parse(&[]);
+        assert_eq!(cli.config, None);
+    }
+
+    #[tokio::test]
+    async fn banner_is_the_first_message_of_a_connection() {
+        let config = config::Config { banner: Some("Welcome".to_string()), node_id: Some("node-1".to_string()), ..Default::default() };
+        let (port, _, listener) = spawn_listener(config, &[CommandID::Heartbeat]);
+        let key = Identity::generate();
+        let mut connection = connect_when_listening(port).await;
+        let MessageContent::Response(banner) = connection.read_message(&key).await.unwrap().0.content else { panic!("expected a response") };
+        assert_eq!(banner.status, OperationStatus::Banner);
+        let Some(Bson::Document(banner)) = banner.content else { panic!("expected a document, got {:?}", banner.content) };
+        assert_eq!(banner.get_str("message").unwrap(), "Welcome");
+        assert_eq!(banner.get_str("node_id").unwrap(), "node-1");
+        assert_eq!(banner.get_str("version").unwrap(), env!("CARGO_PKG_VERSION"));
+        assert_eq!(request(&mut connection, &key, CommandID::Heartbeat).await, OperationStatus::Success);
+        listener.abort();
+    }
 }
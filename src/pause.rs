@@ -0,0 +1,52 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::{oneshot, OwnedRwLockReadGuard, RwLock};
+
+/// Upper bound for PauseWrites, so a forgotten pause can't block writes forever. Also used if no timeout is given
+pub const MAX_WRITE_PAUSE: Duration = Duration::from_secs(60);
+
+/// Blocks writes while an operator takes a backup. Every write holds a read guard of the gate while it runs,
+/// a pause holds the write guard. So pausing waits for running writes, and new ones wait until the pause ends
+#[derive(Debug, Default)]
+pub struct WritePause {
+    gate: Arc<RwLock<()>>,
+    /// Set while paused, ends the pause early when sent or dropped
+    resume: Mutex<Option<oneshot::Sender<()>>>,
+}
+
+impl WritePause {
+    /// Waits until writes aren't paused. Hold the guard until the write is done
+    pub async fn enter(&self) -> OwnedRwLockReadGuard<()> {
+        self.gate.clone().read_owned().await
+    }
+
+    /// Pauses writes for at most timeout, once the running writes are done. False if they are already paused
+    pub async fn pause(&self, timeout: Duration) -> bool {
+        let (sender, receiver) = oneshot::channel();
+        {
+            let mut resume = self.resume.lock().unwrap();
+            if resume.as_ref().is_some_and(|sender| !sender.is_closed()) {
+                return false;
+            }
+            *resume = Some(sender);
+        }
+        let guard = self.gate.clone().write_owned().await;
+        let timeout = timeout.min(MAX_WRITE_PAUSE);
+        tokio::spawn(async move {
+            if tokio::time::timeout(timeout, receiver).await.is_err() {
+                log::warn!("Writes were paused for {:?} without a resume, resuming them", timeout);
+            }
+            drop(guard);
+        });
+        true
+    }
+
+    /// Ends the pause. False if writes weren't paused
+    pub fn resume(&self) -> bool {
+        match self.resume.lock().unwrap().take() {
+            Some(sender) => sender.send(()).is_ok(),
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use tokio::sync::{Mutex, Notify, RwLock};
+use uuid::Uuid;
+
+use common::command::CommandAliases;
+use common::connection::Connection;
+use common::message::{Message, MessageContent};
+use common::plaintext::{format_response, parse_line};
+
+use crate::clients::ClientRegistry;
+use crate::slowlog::SlowLog;
+use crate::store::{ACLAble, Store};
+use crate::{bind_listener, config, handle_message, populate_command_registry};
+
+/// Serves the line protocol from common::plaintext on its own port. Connections never log in,
+/// so only the anonymous ACLs apply. The task simply ends with the server, clients get no shutdown notification
+pub async fn plaintext_listener(port: u16, config: Arc<config::Config>, store: Arc<RwLock<Store>>, slowlog: Arc<Mutex<SlowLog>>, shutdown: Arc<Notify>, clients: Arc<Mutex<ClientRegistry>>, aliases: Arc<CommandAliases>) {
+    let addr = SocketAddr::from((config.host.unwrap(), port));
+    log::warn!("Starting unencrypted plaintext server on {}", addr);
+    let listener = match bind_listener(addr, config.listen_backlog.unwrap_or(1024)) {
+        Ok(listener) => listener,
+        Err(err) => {
+            log::error!("Error binding to {}: {}", addr, err);
+            return;
+        }
+    };
+    loop {
+        let (socket, info) = match listener.accept().await {
+            Ok(res) => res,
+            Err(err) => {
+                log::error!("Error accepting plaintext connection: {}", err);
+                continue;
+            }
+        };
+        log::debug!("Accepted plaintext connection from: {}", info);
+        // Nothing is compressed on this protocol
+        let mut connection = Connection::new(socket, Uuid::new_v4(), 0);
+        connection.set_egress_limit(config.max_egress_bytes_per_sec);
+        let store = store.clone();
+        let slowlog = slowlog.clone();
+        let config = config.clone();
+        let shutdown = shutdown.clone();
+        let clients = clients.clone();
+        let aliases = aliases.clone();
+        tokio::spawn(async move {
+            plaintext_worker(connection, store, slowlog, config, shutdown, clients, aliases).await;
+        });
+    }
+}
+
+/// The connection is never registered with the clients, so it can't receive signals like pushes of watched keys
+async fn plaintext_worker(mut connection: Connection, store: Arc<RwLock<Store>>, slowlog: Arc<Mutex<SlowLog>>, config: Arc<config::Config>, shutdown: Arc<Notify>, clients: Arc<Mutex<ClientRegistry>>, aliases: Arc<CommandAliases>) {
+    let mut command_registry = populate_command_registry(&config, &slowlog, &shutdown, &clients);
+    connection.set_acl_version(store.read().await.acl_version());
+    loop {
+        let line = match connection.read_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(err) => {
+                log::error!("Error reading from plaintext socket: {}", err);
+                break;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let rsp = match parse_line(&line, &aliases) {
+            Ok(cmd) => {
+                let message = Message::new_command(Uuid::new_v4(), cmd);
+                match handle_message(message, &mut connection, &store, false, Uuid::new_v4(), &mut command_registry, &slowlog).await {
+                    Some(Message { content: MessageContent::Response(rsp), .. }) => format_response(&rsp),
+                    // The binary protocol closes the connection here, for someone typing by hand an error is friendlier
+                    _ => "ERR Failure".to_string(),
+                }
+            }
+            Err(err) => format!("ERR {}", err),
+        };
+        if let Err(err) = connection.write_line(&rsp).await {
+            log::error!("Error sending plaintext response: {}", err);
+            break;
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use bson::{Bson, doc, Document};
+use chrono::{DateTime, Utc};
+
+use common::command::CommandID;
+use common::command_input::LoginCommandInput;
+
+/// How many characters of the arguments are kept in an entry
+const ARGS_SUMMARY_LEN: usize = 128;
+
+#[derive(Debug, Clone)]
+pub struct SlowLogEntry {
+    pub timestamp: DateTime<Utc>,
+    pub user: Option<String>,
+    pub command: CommandID,
+    pub duration: Duration,
+    pub args: String,
+}
+
+impl SlowLogEntry {
+    pub fn to_document(&self) -> Document {
+        doc! {
+            "timestamp": bson::DateTime::from_millis(self.timestamp.timestamp_millis()),
+            "user": self.user.clone().map_or(Bson::Null, Bson::String),
+            "command": self.command.to_string(),
+            "duration_us": self.duration.as_micros() as i64,
+            "args": self.args.clone(),
+        }
+    }
+}
+
+/// Ring buffer of the commands that took longer than the threshold to execute
+#[derive(Debug)]
+pub struct SlowLog {
+    threshold: Option<Duration>,
+    max_len: usize,
+    entries: VecDeque<SlowLogEntry>,
+}
+
+impl SlowLog {
+    /// A threshold of None disables the slow log
+    pub fn new(threshold: Option<Duration>, max_len: usize) -> Self {
+        Self {
+            threshold,
+            max_len,
+            entries: VecDeque::new(),
+        }
+    }
+
+    /// Returns true if a command running for duration should be recorded
+    pub fn is_slow(&self, duration: Duration) -> bool {
+        self.threshold.is_some_and(|threshold| duration >= threshold)
+    }
+
+    pub fn record(&mut self, user: Option<String>, command: CommandID, duration: Duration, args: &Bson) {
+        if self.max_len == 0 {
+            return;
+        }
+        if self.entries.len() >= self.max_len {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(SlowLogEntry {
+            timestamp: Utc::now(),
+            user,
+            command,
+            duration,
+            args: summarize_args(command, args),
+        });
+    }
+
+    /// Returns up to count entries, newest first
+    pub fn entries(&self, count: Option<usize>) -> Vec<SlowLogEntry> {
+        self.entries.iter().rev().take(count.unwrap_or(self.max_len)).cloned().collect()
+    }
+
+    pub fn reset(&mut self) {
+        self.entries.clear();
+    }
+}
+
+/// Creates a short printable summary of the command arguments. Passwords are never included
+fn summarize_args(command: CommandID, args: &Bson) -> String {
+    let summary = match command {
+        CommandID::Login => {
+            match LoginCommandInput::try_from(args.clone()) {
+                Ok(login) => format!("{{ \"user\": \"{}\", \"password\": \"<redacted>\" }}", login.user),
+                Err(_) => String::from("<redacted>"),
+            }
+        }
+        _ => args.to_string(),
+    };
+    summary.chars().take(ARGS_SUMMARY_LEN).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn threshold_decides_what_is_slow() {
+        let slowlog = SlowLog::new(Some(Duration::from_millis(10)), 4);
+        assert!(!slowlog.is_slow(Duration::from_millis(9)));
+        assert!(slowlog.is_slow(Duration::from_millis(10)));
+        assert!(!SlowLog::new(None, 4).is_slow(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn keeps_the_newest_entries_first() {
+        let mut slowlog = SlowLog::new(Some(Duration::ZERO), 2);
+        for command in [CommandID::Get, CommandID::Set, CommandID::Delete] {
+            slowlog.record(None, command, Duration::from_millis(20), &Bson::Null);
+        }
+        let commands = slowlog.entries(None).iter().map(|entry| entry.command).collect::<Vec<_>>();
+        assert_eq!(commands, vec![CommandID::Delete, CommandID::Set]);
+        assert_eq!(slowlog.entries(Some(1)).len(), 1);
+        slowlog.reset();
+        assert!(slowlog.entries(None).is_empty());
+    }
+
+    #[test]
+    fn login_passwords_are_redacted() {
+        let mut slowlog = SlowLog::new(Some(Duration::ZERO), 2);
+        let args = bson::to_bson(&LoginCommandInput { user: "user".to_string(), password: "secret".to_string() }).unwrap();
+        slowlog.record(Some("user".to_string()), CommandID::Login, Duration::from_millis(20), &args);
+        let entry = &slowlog.entries(None)[0];
+        assert!(entry.args.contains("\"user\""));
+        assert!(!entry.args.contains("secret"));
+    }
+}
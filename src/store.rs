@@ -1,21 +1,222 @@
-use std::collections::{HashMap, TryReserveError};
+use std::collections::{HashMap, HashSet, TryReserveError, VecDeque};
+use std::collections::hash_map::Entry;
+use std::fmt::Display;
 use std::num::ParseIntError;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use age::x25519::Recipient;
+use bson::Bson;
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use tokio::sync::mpsc::UnboundedSender;
+use uuid::Uuid;
 
-use common::acl::ACL;
+use common::acl::{AclExport, AclVersion, ACL};
 use common::command::CommandID;
+use common::command_input::{KeyOrder, ListEnd, Predicate};
+
+use crate::clients::ClientSignal;
+use crate::config::{AuthPersistence, AuthSnapshot, ConfigUser};
+use crate::glob::glob_match;
+use crate::hasher::{KeyHasher, KeyMap};
+use crate::pause::WritePause;
+
+/// Estimated per entry cost of a hashmap: the control byte plus the stored hash, rounded up
+const MAP_ENTRY_OVERHEAD: usize = 8;
+
+/// Returned by Store::list_or_empty for keys that don't hold a list
+static EMPTY_LIST: VecDeque<String> = VecDeque::new();
+
+/// Upper bounds of the buckets of Store::size_histogram, in bytes. Larger values go into the last bucket
+const SIZE_BUCKETS: [(usize, &str); 5] = [(64, "<64B"), (1 << 10, "<1KB"), (16 << 10, "<16KB"), (256 << 10, "<256KB"), (4 << 20, "<4MB")];
+
+/// Shrinks the string, returns the freed bytes
+fn shrink_string(s: &mut String) -> usize {
+    let before = s.capacity();
+    s.shrink_to_fit();
+    before - s.capacity()
+}
+
+/// Drops the oldest elements beyond limit. They are at the back if the new elements were pushed to the front
+fn trim_oldest(list: &mut VecDeque<String>, limit: Option<usize>, pushed_front: bool) {
+    match limit {
+        Some(limit) if list.len() > limit => {
+            if pushed_front {
+                list.truncate(limit);
+            } else {
+                list.drain(..list.len() - limit);
+            }
+        }
+        _ => {}
+    }
+}
 
 #[derive(Debug, Clone)]
 enum Type {
     String(String),
-    HashMap(HashMap<String, String>),
-    List(Vec<String>),
+    HashMap(KeyMap<String>),
+    List(VecDeque<String>),
     User((String, Option<Recipient>)),
 }
 
+impl Type {
+    fn kind(&self) -> Kind {
+        match self {
+            Type::String(_) => Kind::String,
+            Type::HashMap(_) => Kind::Hash,
+            Type::List(_) => Kind::List,
+            Type::User(_) => Kind::User,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        self.kind().name()
+    }
+}
+
+/// One key of Store::key_page
+pub struct KeyPageEntry {
+    pub key: String,
+    pub kind: Kind,
+    /// Estimated like memory_usage
+    pub size: usize,
+    pub last_modified: Option<DateTime<Utc>>,
+}
+
+/// Bookkeeping about a key that isn't part of its value
+#[derive(Debug, Clone)]
+struct KeyMetadata {
+    /// When the value was last written. Reading it doesn't change this
+    last_modified: DateTime<Utc>,
+    /// Orders writes that happen within the same millisecond
+    sequence: u64,
+    /// Commands that replace or delete strings fail on the key, see commands::protected
+    protected: bool,
+}
+
+#[derive(Default, Debug, Clone)]
+struct Metadata {
+    keys: HashMap<String, KeyMetadata>,
+    next_sequence: u64,
+}
+
+impl Metadata {
+    fn modified(&mut self, key: &str) -> &mut KeyMetadata {
+        self.next_sequence += 1;
+        let (last_modified, sequence) = (Utc::now(), self.next_sequence);
+        // Looked up first, so writes to existing keys don't allocate
+        if self.keys.contains_key(key) {
+            let metadata = self.keys.get_mut(key).unwrap();
+            metadata.last_modified = last_modified;
+            metadata.sequence = sequence;
+            return metadata;
+        }
+        self.keys.entry(key.to_string()).or_insert(KeyMetadata { last_modified, sequence, protected: false })
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Kind {
+    String,
+    Hash,
+    List,
+    User,
+}
+
+impl Kind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::String => "string",
+            Kind::Hash => "hash",
+            Kind::List => "list",
+            Kind::User => "user",
+        }
+    }
+
+    /// The kind with this name. None for users, they aren't keys clients work with
+    pub fn from_name(name: &str) -> Option<Kind> {
+        match name {
+            "string" => Some(Kind::String),
+            "hash" => Some(Kind::Hash),
+            "list" => Some(Kind::List),
+            _ => None,
+        }
+    }
+}
+
+/// How many keys of each type may exist at once, and how many elements a single list or hash may hold. None is unlimited
+#[derive(Default, Debug, Clone)]
+pub struct TypeLimits {
+    pub max_strings: Option<usize>,
+    pub max_hashes: Option<usize>,
+    pub max_lists: Option<usize>,
+    pub max_list_length: Option<usize>,
+    pub max_hash_fields: Option<usize>,
+    /// Pushing onto a full list drops its oldest elements instead of failing
+    pub trim_full_lists: bool,
+}
+
+/// How many keys of each limited type exist. Users aren't counted
+#[derive(Default, Debug, Clone)]
+struct TypeCounts {
+    strings: usize,
+    hashes: usize,
+    lists: usize,
+}
+
+impl TypeCounts {
+    fn count_and_limit(&mut self, limits: &TypeLimits, kind: Kind) -> Option<(&mut usize, Option<usize>)> {
+        match kind {
+            Kind::String => Some((&mut self.strings, limits.max_strings)),
+            Kind::Hash => Some((&mut self.hashes, limits.max_hashes)),
+            Kind::List => Some((&mut self.lists, limits.max_lists)),
+            Kind::User => None,
+        }
+    }
+
+    /// Counts additional new keys of the kind, or fails without counting if that would exceed its limit
+    fn add(&mut self, limits: &TypeLimits, kind: Kind, additional: usize) -> Result<(), ErrorType> {
+        if let Some((count, limit)) = self.count_and_limit(limits, kind) {
+            if limit.is_some_and(|limit| *count + additional > limit) {
+                return Err(ErrorType::TypeLimit(kind.name()));
+            }
+            *count += additional;
+        }
+        Ok(())
+    }
+
+    fn remove(&mut self, kind: Kind) {
+        if let Some((count, _)) = self.count_and_limit(&TypeLimits::default(), kind) {
+            *count = count.saturating_sub(1);
+        }
+    }
+}
+
+#[derive(Debug)]
 pub enum ErrorType {
     TryReserveError(TryReserveError),
     ParseIntError(ParseIntError),
+    /// The key holds a value of a different type than the operation expects
+    WrongType,
+    /// The result of an arithmetic operation doesn't fit into an i64. Nothing was changed
+    Overflow,
+    /// A new key of this type can't be created, because the configured maximum is reached
+    TypeLimit(&'static str),
+    /// The list or hash would hold more elements than the configured maximum. Nothing was changed
+    SizeLimit(&'static str, usize),
+}
+
+impl Display for ErrorType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ErrorType::TryReserveError(err) => write!(f, "{}", err),
+            ErrorType::ParseIntError(err) => write!(f, "{}", err),
+            ErrorType::WrongType => write!(f, "Key holds a value of another type"),
+            ErrorType::Overflow => write!(f, "Overflow"),
+            ErrorType::TypeLimit(name) => write!(f, "Limit of {} keys reached", name),
+            ErrorType::SizeLimit(name, limit) => write!(f, "A {} can't hold more than {} elements", name, limit),
+        }
+    }
 }
 
 impl From<TryReserveError> for ErrorType {
@@ -32,15 +233,39 @@ impl From<ParseIntError> for ErrorType {
 
 pub trait StoreAble {
     fn get(&self, key: &str) -> Option<&String>;
-    fn set(&mut self, key: String, value: String) -> Result<(), TryReserveError>;
+    fn set(&mut self, key: String, value: String) -> Result<(), ErrorType>;
     fn remove(&mut self, key: &str) -> Option<String>;
+    /// Sets the key to value only if its current value equals expected. If expected is None the key must not exist
+    /// Returns whether the value was set
+    fn compare_set(&mut self, key: String, expected: Option<String>, value: String) -> Result<bool, ErrorType>;
+    /// Sets every key to token if none of them exists, otherwise nothing is set
+    /// Returns the keys that already existed, so an empty vec means all keys were acquired
+    fn multi_lock(&mut self, keys: Vec<String>, token: String) -> Result<Vec<String>, ErrorType>;
+    /// Returns the value of the key, setting it to default first if it doesn't exist. The bool is whether it was set
+    fn get_or_set(&mut self, key: String, default: String) -> Result<(String, bool), ErrorType>;
+    /// Subtracts delta from the number at key and removes the key once it drops to 0 or below. A missing key counts as 0
+    /// Returns the new value and whether the key was removed
+    fn decr_del(&mut self, key: String, delta: i64) -> Result<(i64, bool), ErrorType>;
+    /// Appends the suffix unless the value already ends with it, so repeating it changes nothing. A missing key is set to the suffix
+    /// Returns whether it was appended and the resulting length in bytes
+    fn append_if(&mut self, key: String, suffix: String) -> Result<(bool, usize), ErrorType>;
+    /// Sets the key only if its version, see Store::version, equals expected. 0 stands for a key that doesn't exist
+    /// Returns whether it was set and the version afterwards, which is the current one if it wasn't
+    fn set_with_version(&mut self, key: String, expected: u64, value: String) -> Result<(bool, u64), ErrorType>;
 }
 
 pub trait ACLAble {
     fn acl_add(&mut self, user: &str, command: CommandID);
+    /// Allows connections that aren't logged in to execute the command
+    fn acl_add_anonymous(&mut self, command: CommandID);
+    /// Rejects the command for every connection, including users that have it in their ACLs
+    fn acl_disable(&mut self, command: CommandID);
     fn acl_remove(&mut self, user: &str, command: CommandID);
     fn acl_is_allowed(&self, user: &str, command: CommandID) -> bool;
     fn acl_list(&self, user: &str) -> Vec<CommandID>;
+    fn acl_version(&self) -> AclVersion;
+    fn acl_export(&self) -> AclExport;
+    fn acl_import(&mut self, acls: AclExport, replace: bool);
 }
 
 pub trait UserAble {
@@ -50,89 +275,724 @@ pub trait UserAble {
     fn user_is_valid(&self, user: &str, password: &str) -> bool;
     fn verify_key(&self, user: &str, key: &Recipient) -> bool;
     fn user_has_key(&self, user: &str) -> bool;
+    /// All users with their ACLs, sorted by name, in the format of the config file
+    fn users(&self) -> Vec<ConfigUser>;
 }
 
 // Now I understand why redis used h in front of all the hashmap commands. It's to avoid name conflicts.
 pub trait HashMapAble<T> {
-    fn hadd(&mut self, map_key: String, key: String, value: T) -> Result<(), TryReserveError>;
+    fn hadd(&mut self, map_key: String, key: String, value: T) -> Result<(), ErrorType>;
     fn hremove(&mut self, map_key: String, key: String) -> bool;
     fn hcontains(&self, map_key: String, key: String) -> bool;
     fn hget(&self, map_key: String, key: String) -> Option<&T>;
     fn hget_all(&self, map_key: String) -> Result<HashMap<String, T>, TryReserveError>;
     fn hget_all_values(&self, map_key: String) -> Result<Vec<T>, TryReserveError>;
     fn hkeys(&self, map_key: String) -> Result<Vec<String>, TryReserveError>;
+    /// Same as hkeys, but only returns the fields matching the glob pattern
+    fn hkeys_match(&self, map_key: String, pattern: &str) -> Result<Vec<String>, TryReserveError>;
+    /// Returns the field value pairs sorted lexically by field, skipping offset pairs and returning at most count
+    fn hget_sorted(&self, map_key: String, descending: bool, offset: usize, count: Option<usize>) -> Result<Vec<(String, T)>, TryReserveError>;
+    /// The fields whose value matches the predicate, in no particular order
+    fn hfilter(&self, map_key: String, predicate: &Predicate) -> Result<Vec<(String, T)>, TryReserveError>;
+    /// The count fields with the highest numeric values, highest first. Ties are ordered by field
+    /// Values that aren't numbers are skipped, or result in WrongType if strict
+    fn htop(&self, map_key: String, count: usize, strict: bool) -> Result<Vec<(String, T)>, ErrorType>;
     fn hlen(&self, map_key: String) -> usize;
-    fn hupsert(&mut self, map_key: String, key: String, value: T) -> Result<(), TryReserveError>;
+    fn hupsert(&mut self, map_key: String, key: String, value: T) -> Result<(), ErrorType>;
     fn hstr_len(&self, map_key: String, key: String) -> Option<usize>;
+    /// Exchanges the values of both fields. If only one of them exists its value moves to the other field
+    /// Returns false if the hash doesn't exist
+    fn hswap(&mut self, map_key: String, field_a: String, field_b: String) -> bool;
     fn hincrby(&mut self, map_key: String, key: String, value: i64) -> Result<i64, ErrorType>;
+    /// Sets the fields of several hashes at once. Either all of them are set or none, if a key isn't a hash or memory can't be reserved
+    /// Returns how many fields were set per hash
+    fn hset_multi(&mut self, hashes: HashMap<String, HashMap<String, T>>) -> Result<HashMap<String, usize>, ErrorType>;
+    /// Same as hget_all, but returns None if the key doesn't hold a hash
+    fn hdump(&self, map_key: String) -> Result<Option<HashMap<String, T>>, TryReserveError>;
+    /// Writes the fields into the hash. With replace the key is overwritten whatever it held, except users, otherwise the fields
+    /// are merged into an existing hash and keys of other types result in WrongType
+    fn hrestore(&mut self, map_key: String, fields: HashMap<String, T>, replace: bool) -> Result<(), ErrorType>;
 }
 
-
+/// Lists are stored as VecDeque, so pushing and popping at either end is O(1), amortized for pushes.
+/// Everything that searches or removes in the middle, like lindex, lrem and lpush_unique, is O(n)
 pub trait ListAble {
     fn llen(&self, list_key: String) -> usize;
     fn lindex(&self, list_key: String, value: String) -> Option<usize>;
+    /// The elements of first that are also in second, in the order of first. Missing lists count as empty
+    fn linter(&self, first_key: String, second_key: String, dedup: bool) -> Result<Vec<String>, TryReserveError>;
+    /// The elements of first that aren't in second, in the order of first. Missing lists count as empty
+    fn ldiff(&self, first_key: String, second_key: String) -> Result<Vec<String>, TryReserveError>;
+    /// Returns the element at index. Negative indices count from the end of the list
+    fn lget(&self, list_key: String, index: isize) -> Option<&String>;
+    /// Removes the list and returns all of its elements in order. Returns an empty vec if the list doesn't exist
+    fn drain(&mut self, list_key: String) -> Vec<String>;
     fn lmove(&mut self, src_key: String, dest_key: String, left_right: String, right_left: String) -> Option<String>;
-    /// Removes and returns the first element(s) of the list stored at key. Count has a default of 1
+    /// Removes and returns the last element(s) of the list stored at key, from the tail. Count has a default of 1. O(count)
     fn lpop(&mut self, list_key: String, count: Option<usize>) -> Result<Option<Vec<String>>, TryReserveError>;
+    /// Pops the from end of source and pushes it onto the tail of destination, creating it if needed. Returns the moved element,
+    /// None if source is empty. Keys of other types result in WrongType. O(1)
+    fn lpop_to_list(&mut self, source_key: String, destination_key: String, from: ListEnd) -> Result<Option<String>, ErrorType>;
     /// Actually, I don't understand the redis docs at all for this. I'm just going to implement it as I see fit. Since I'm not going to implement redis I'm allowed to do that.
     fn lpos(&self, list_key: String, value: String, rank: Option<isize>, count: Option<usize>, max_len: Option<usize>) -> Result<Option<Vec<usize>>, TryReserveError>;
-    fn lpush(&mut self, list_key: String, values: Vec<String>) -> Result<(), TryReserveError>;
+    fn lpush(&mut self, list_key: String, values: Vec<String>) -> Result<(), ErrorType>;
+    /// Pushes every value to the front of the list, removing any existing occurrence of it first. So each value is only contained once
+    /// If max_len is given the list is trimmed to that many elements afterwards. Returns the resulting length
+    fn lpush_unique(&mut self, list_key: String, values: Vec<String>, max_len: Option<usize>) -> Result<usize, ErrorType>;
     /// Only inserts when the list already exists, otherwise it does nothing
-    fn lpushx(&mut self, list_key: String, values: Vec<String>) -> Result<(), TryReserveError>;
+    fn lpushx(&mut self, list_key: String, values: Vec<String>) -> Result<(), ErrorType>;
     fn lrange(&self, list_key: String, start: isize, stop: isize) -> Result<Vec<String>, TryReserveError>;
     fn lrem(&mut self, list_key: String, count: isize, value: String) -> usize;
+    /// Moves the first element to the end, or the last to the front if left_to_right is false. Returns the moved element. O(1)
+    fn lrotate(&mut self, list_key: String, left_to_right: bool) -> Option<String>;
     fn lset(&mut self, list_key: String, index: isize, value: String) -> bool;
+    /// Replaces the whole list with values at once, creating it if needed. An empty values deletes the list. Returns the new length
+    /// Keys of other types result in WrongType
+    fn lreplace(&mut self, list_key: String, values: Vec<String>) -> Result<usize, ErrorType>;
     fn ltrim(&mut self, list_key: String, start: isize, stop: isize) -> bool;
 
     fn rpop(&mut self, list_key: String, count: Option<usize>) -> Option<Vec<String>>;
-    fn rpush(&mut self, list_key: String, values: Vec<String>) -> Result<(), TryReserveError>;
-    fn rpushx(&mut self, list_key: String, values: Vec<String>) -> Result<(), TryReserveError>;
+    fn rpush(&mut self, list_key: String, values: Vec<String>) -> Result<(), ErrorType>;
+    fn rpushx(&mut self, list_key: String, values: Vec<String>) -> Result<(), ErrorType>;
+}
+
+pub trait KeyAble {
+    /// Whether the key exists, whatever type it holds, users included. Expired keys count as absent
+    fn exists(&self, key: &str) -> bool;
+}
+
+/// Expired keys act as if they didn't exist. They are removed by the next write of the key or Store::remove_expired
+pub trait Expirable {
+    /// Lets the key expire after ttl, replacing an earlier expiry. False if the key doesn't exist or is a user
+    fn expire(&mut self, key: &str, ttl: Duration) -> bool;
+    /// The time left until the key expires. None if it doesn't exist or doesn't expire
+    fn ttl(&self, key: &str) -> Option<Duration>;
+    /// Removes the expiry of the key, so it's kept forever. False if it doesn't exist or doesn't expire
+    fn persist(&mut self, key: &str) -> bool;
 }
 
 #[derive(Default, Debug, Clone)]
 pub struct Store {
     acl: ACL,
-    values: HashMap<String, Type>,
+    values: KeyMap<Type>,
+    /// Used for the keyspace and every hash in it
+    hasher: KeyHasher,
+    limits: TypeLimits,
+    counts: TypeCounts,
+    /// Whether commands on keys of another type fail instead of acting as if the key didn't exist
+    strict_types: bool,
+    /// Commands that would create a longer key fail, see commands::key_too_long
+    max_key_length: Option<usize>,
+    /// Set if changes to users and ACLs are written back into the config file
+    auth_persistence: Option<Arc<AuthPersistence>>,
+    /// Connections that get a KeyChanged signal whenever the key is written
+    watchers: HashMap<String, HashMap<Uuid, UnboundedSender<ClientSignal>>>,
+    /// Tag to the keys carrying it
+    tags: HashMap<String, HashSet<String>>,
+    /// Key to its tags, so removing a key doesn't have to search every tag
+    key_tags: HashMap<String, HashSet<String>>,
+    /// Only kept for keys that exist and aren't users
+    metadata: Metadata,
+    /// Shared instead of locked with the store, writes wait on it without holding the store lock
+    write_pause: Arc<WritePause>,
+    /// When keys with a time to live expire. Expired keys are treated as absent until a write or remove_expired removes them
+    expires: HashMap<String, Instant>,
 }
 
-impl StoreAble for Store {
-    fn get(&self, key: &str) -> Option<&String> {
-        match self.values.get(key) {
-            Some(Type::String(s)) => Some(s),
-            _ => None
+impl Store {
+    /// fast_hash trades the HashDoS resistance of SipHash for throughput, see KeyHasher
+    pub fn new(fast_hash: bool, limits: TypeLimits) -> Self {
+        let hasher = KeyHasher::new(fast_hash);
+        Self {
+            acl: ACL::default(),
+            values: KeyMap::with_hasher(hasher.clone()),
+            hasher,
+            limits,
+            counts: TypeCounts::default(),
+            strict_types: false,
+            max_key_length: None,
+            auth_persistence: None,
+            watchers: HashMap::new(),
+            tags: HashMap::new(),
+            key_tags: HashMap::new(),
+            metadata: Metadata::default(),
+            write_pause: Arc::new(WritePause::default()),
+            expires: HashMap::new(),
         }
     }
 
-    fn set(&mut self, key: String, value: String) -> Result<(), TryReserveError> {
-        match self.values.try_reserve(1) {
-            Ok(_) => {
-                self.values.insert(key, Type::String(value));
-                Ok(())
+    /// Attaches the tags to the key. None if the key doesn't exist or is a user, otherwise how many tags were new
+    pub fn tag(&mut self, key: &str, tags: Vec<String>) -> Option<usize> {
+        if matches!(self.value(key), None | Some(Type::User(_))) {
+            return None;
+        }
+        let key_tags = self.key_tags.entry(key.to_string()).or_default();
+        let mut added = 0;
+        for tag in tags {
+            if key_tags.insert(tag.clone()) {
+                self.tags.entry(tag).or_default().insert(key.to_string());
+                added += 1;
             }
-            Err(_) => {
-                self.values.shrink_to_fit();
-                match self.values.try_reserve(1) {
-                    Ok(_) => {
-                        self.values.insert(key, Type::String(value));
-                        Ok(())
+        }
+        Some(added)
+    }
+
+    /// Detaches the tags from the key, returns how many it had
+    pub fn untag(&mut self, key: &str, tags: Vec<String>) -> usize {
+        let Some(key_tags) = self.key_tags.get_mut(key) else {
+            return 0;
+        };
+        let mut removed = 0;
+        for tag in tags {
+            if key_tags.remove(&tag) {
+                removed += 1;
+                if let Some(keys) = self.tags.get_mut(&tag) {
+                    keys.remove(key);
+                    if keys.is_empty() {
+                        self.tags.remove(&tag);
                     }
-                    Err(err) => {
-                        return Err(err);
+                }
+            }
+        }
+        if key_tags.is_empty() {
+            self.key_tags.remove(key);
+        }
+        removed
+    }
+
+    /// The keys carrying the tag, sorted
+    pub fn keys_by_tag(&self, tag: &str) -> Vec<String> {
+        let mut keys: Vec<String> = self.tags.get(tag).map_or(Vec::new(), |keys| keys.iter().cloned().collect());
+        keys.sort_unstable();
+        keys
+    }
+
+    /// The keys holding the kind and matching the glob pattern if one is given, sorted
+    pub fn keys_of_type(&self, kind: Kind, pattern: Option<&str>) -> Vec<String> {
+        let mut keys: Vec<String> = self.values.iter()
+            .filter(|(key, value)| value.kind() == kind && kind != Kind::User && !self.is_expired(key) && pattern.is_none_or(|pattern| glob_match(pattern, key)))
+            .map(|(key, _)| key.clone())
+            .collect();
+        keys.sort_unstable();
+        keys
+    }
+
+    /// Walks the store and describes every broken internal invariant, for debugging. Empty if everything is consistent
+    pub fn check_invariants(&self) -> Vec<String> {
+        let mut violations = Vec::new();
+
+        let mut actual = TypeCounts::default();
+        for value in self.values.values() {
+            match value.kind() {
+                Kind::String => actual.strings += 1,
+                Kind::Hash => actual.hashes += 1,
+                Kind::List => actual.lists += 1,
+                Kind::User => {}
+            }
+        }
+        for (kind, counted, actual) in [
+            (Kind::String, self.counts.strings, actual.strings),
+            (Kind::Hash, self.counts.hashes, actual.hashes),
+            (Kind::List, self.counts.lists, actual.lists),
+        ] {
+            if counted != actual {
+                violations.push(format!("{} count is {} but {} exist", kind.name(), counted, actual));
+            }
+        }
+
+        for (tag, keys) in &self.tags {
+            if keys.is_empty() {
+                violations.push(format!("Tag {} has no keys but wasn't removed", tag));
+            }
+            for key in keys {
+                if !self.key_tags.get(key).is_some_and(|tags| tags.contains(tag)) {
+                    violations.push(format!("Tag {} lists key {}, but the key doesn't list the tag", tag, key));
+                }
+            }
+        }
+        for (key, tags) in &self.key_tags {
+            if tags.is_empty() {
+                violations.push(format!("Key {} has no tags but wasn't removed from the tag index", key));
+            }
+            if matches!(self.values.get(key), None | Some(Type::User(_))) {
+                violations.push(format!("Key {} is tagged but doesn't exist", key));
+            }
+            for tag in tags {
+                if !self.tags.get(tag).is_some_and(|keys| keys.contains(key)) {
+                    violations.push(format!("Key {} lists tag {}, but the tag doesn't list the key", key, tag));
+                }
+            }
+        }
+
+        for key in self.metadata.keys.keys() {
+            if matches!(self.values.get(key), None | Some(Type::User(_))) {
+                violations.push(format!("Key {} has metadata but doesn't exist", key));
+            }
+        }
+        for key in self.expires.keys() {
+            if matches!(self.values.get(key), None | Some(Type::User(_))) {
+                violations.push(format!("Key {} has an expiry but doesn't exist", key));
+            }
+        }
+
+        for (key, watchers) in &self.watchers {
+            if watchers.is_empty() {
+                violations.push(format!("Key {} has no watchers but wasn't removed", key));
+            }
+        }
+        violations
+    }
+
+    /// Sha256 over every key and value, as hex. Equal for stores with equal contents, whatever order they were written in
+    /// or which hasher they use, so servers can be compared. Users, tags, metadata and expiries aren't part of it, expired keys are left out
+    pub fn digest(&self) -> String {
+        // Every string is length prefixed, so the boundaries between keys, fields and values are unambiguous
+        fn update(hasher: &mut Sha256, s: &str) {
+            hasher.update((s.len() as u64).to_le_bytes());
+            hasher.update(s.as_bytes());
+        }
+
+        let mut keys: Vec<(&String, &Type)> = self.values.iter()
+            .filter(|(key, value)| !matches!(value, Type::User(_)) && !self.is_expired(key))
+            .collect();
+        keys.sort_unstable_by_key(|(key, _)| *key);
+        let mut hasher = Sha256::new();
+        for (key, value) in keys {
+            update(&mut hasher, key);
+            match value {
+                Type::String(s) => {
+                    hasher.update([0]);
+                    update(&mut hasher, s);
+                }
+                Type::HashMap(map) => {
+                    hasher.update([1]);
+                    let mut fields: Vec<(&String, &String)> = map.iter().collect();
+                    fields.sort_unstable();
+                    hasher.update((fields.len() as u64).to_le_bytes());
+                    for (field, value) in fields {
+                        update(&mut hasher, field);
+                        update(&mut hasher, value);
+                    }
+                }
+                Type::List(list) => {
+                    hasher.update([2]);
+                    hasher.update((list.len() as u64).to_le_bytes());
+                    for element in list {
+                        update(&mut hasher, element);
                     }
                 }
+                Type::User(_) => unreachable!("Users were filtered out"),
+            }
+        }
+        format!("{:x}", hasher.finalize())
+    }
+
+    /// Signals the connection every time the key is written, until it unwatches or closes
+    pub fn watch(&mut self, key: String, id: Uuid, sender: UnboundedSender<ClientSignal>) {
+        self.watchers.entry(key).or_default().insert(id, sender);
+    }
+
+    /// Returns whether the connection was watching the key
+    pub fn unwatch(&mut self, key: &str, id: &Uuid) -> bool {
+        let Some(watchers) = self.watchers.get_mut(key) else {
+            return false;
+        };
+        let removed = watchers.remove(id).is_some();
+        if watchers.is_empty() {
+            self.watchers.remove(key);
+        }
+        removed
+    }
+
+    /// Removes every watch of the connection, called when it closes
+    pub fn unwatch_all(&mut self, id: &Uuid) {
+        self.watchers.retain(|_, watchers| {
+            watchers.remove(id);
+            !watchers.is_empty()
+        });
+    }
+
+    /// Tells the watchers of the key that it was written. They read the new value themselves once the write lock is released
+    fn changed(&self, key: &str) {
+        if let Some(watchers) = self.watchers.get(key) {
+            for sender in watchers.values() {
+                // Fails only if the connection is closing, it unwatches then anyway
+                let _ = sender.send(ClientSignal::KeyChanged(key.to_string()));
+            }
+        }
+    }
+
+    fn is_expired(&self, key: &str) -> bool {
+        self.expires.get(key).is_some_and(|deadline| *deadline <= Instant::now())
+    }
+
+    /// Like values.get, but None for expired keys
+    fn value(&self, key: &str) -> Option<&Type> {
+        if self.is_expired(key) {
+            return None;
+        }
+        self.values.get(key)
+    }
+
+    /// Writes call this before touching the key, so they never see or extend an expired value
+    fn remove_if_expired(&mut self, key: &str) {
+        if self.is_expired(key) {
+            self.remove_value(key);
+        }
+    }
+
+    /// Removes every expired key, returns how many
+    pub fn remove_expired(&mut self) -> usize {
+        let now = Instant::now();
+        let expired: Vec<String> = self.expires.iter()
+            .filter(|(_, deadline)| **deadline <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &expired {
+            self.remove_value(key);
+        }
+        expired.len()
+    }
+
+    /// Like values.get_mut, but None for expired keys. Callers mark the key with modified once they actually changed it
+    fn get_mut_value(&mut self, key: &str) -> Option<&mut Type> {
+        self.remove_if_expired(key);
+        self.values.get_mut(key)
+    }
+
+    /// Counts as a write of the key, so its version changes and its watchers are notified. Does nothing if the key doesn't exist
+    fn modified(&mut self, key: &str) {
+        if self.values.contains_key(key) {
+            self.changed(key);
+            self.metadata.modified(key);
+        }
+    }
+
+    /// Moves the value of source to destination, if destination doesn't exist or holds the same type. Otherwise WrongType.
+    /// Like deleting source and setting destination, so only the tags of destination are kept, but the expiry of source moves along.
+    /// False if source doesn't exist or is a user
+    pub fn rename_typed(&mut self, source: &str, destination: String) -> Result<bool, ErrorType> {
+        let kind = match self.value(source) {
+            None | Some(Type::User(_)) => return Ok(false),
+            Some(value) => value.kind(),
+        };
+        if self.value(&destination).is_some_and(|value| value.kind() != kind) {
+            return Err(ErrorType::WrongType);
+        }
+        if source == destination {
+            return Ok(true);
+        }
+        let expires = self.expires.get(source).copied();
+        let value = self.remove_value(source).expect("The source was checked to exist");
+        // Can't fail, source was removed so its type is below the limit
+        self.insert_value(destination.clone(), value)?;
+        if let Some(deadline) = expires {
+            self.expires.insert(destination, deadline);
+        }
+        Ok(true)
+    }
+
+    /// Marks the key as protected or removes the mark. Returns false if the key doesn't exist or is a user.
+    /// Only strings can be protected, the list and hash commands don't check the mark. WrongType for other types
+    pub fn set_protected(&mut self, key: &str, protected: bool) -> Result<bool, ErrorType> {
+        match self.value(key) {
+            None | Some(Type::User(_)) => return Ok(false),
+            Some(Type::String(_)) => {}
+            Some(_) if protected => return Err(ErrorType::WrongType),
+            Some(_) => {}
+        }
+        match self.metadata.keys.get_mut(key) {
+            Some(metadata) => metadata.protected = protected,
+            None => self.metadata.modified(key).protected = protected,
+        }
+        Ok(true)
+    }
+
+    /// Expired keys aren't protected anymore, even before they are removed
+    pub fn is_protected(&self, key: &str) -> bool {
+        !self.is_expired(key) && self.metadata.keys.get(key).is_some_and(|metadata| metadata.protected)
+    }
+
+    /// Changes with every write of the key and never repeats, since it's the sequence of the last write. 0 if the key doesn't exist or expired
+    pub fn version(&self, key: &str) -> u64 {
+        if self.is_expired(key) {
+            return 0;
+        }
+        self.metadata.keys.get(key).map_or(0, |metadata| metadata.sequence)
+    }
+
+    /// Up to count keys, the most recently modified first, with the time they were modified. Users and expired keys are left out
+    pub fn recently_modified(&self, count: usize) -> Vec<(String, DateTime<Utc>)> {
+        let mut keys: Vec<(&String, &KeyMetadata)> = self.metadata.keys.iter().filter(|(key, _)| !self.is_expired(key)).collect();
+        keys.sort_unstable_by_key(|(_, metadata)| std::cmp::Reverse(metadata.sequence));
+        keys.into_iter().take(count).map(|(key, metadata)| (key.clone(), metadata.last_modified)).collect()
+    }
+
+    /// The keys of kind, or of every type if it's None, sorted by order and paged with offset and count.
+    /// Also returns how many keys there are before paging. Users are left out
+    pub fn key_page(&self, kind: Option<Kind>, order: KeyOrder, offset: usize, count: usize) -> (Vec<KeyPageEntry>, usize) {
+        let mut keys: Vec<(&String, Kind, usize, Option<&KeyMetadata>)> = self.values.iter()
+            .filter(|(key, value)| value.kind() != Kind::User && !self.is_expired(key) && kind.is_none_or(|kind| value.kind() == kind))
+            .map(|(key, value)| (key, value.kind(), self.memory_usage(key).unwrap_or(0), self.metadata.keys.get(key)))
+            .collect();
+        match order {
+            KeyOrder::Name => keys.sort_unstable_by(|a, b| a.0.cmp(b.0)),
+            // Ties are sorted by name, so pages stay stable
+            KeyOrder::Size => keys.sort_unstable_by(|a, b| b.2.cmp(&a.2).then_with(|| a.0.cmp(b.0))),
+            KeyOrder::Modified => keys.sort_unstable_by_key(|(_, _, _, metadata)| std::cmp::Reverse(metadata.map(|metadata| metadata.sequence))),
+        }
+        let total = keys.len();
+        let page = keys.into_iter().skip(offset).take(count)
+            .map(|(key, kind, size, metadata)| KeyPageEntry {
+                key: key.clone(),
+                kind,
+                size,
+                last_modified: metadata.map(|metadata| metadata.last_modified),
+            })
+            .collect();
+        (page, total)
+    }
+
+    /// The value at key as bson. A string, a document for hashes and an array for lists. Null if the key doesn't exist or is a user
+    pub fn value_as_bson(&self, key: &str) -> Bson {
+        match self.value(key) {
+            Some(Type::String(value)) => Bson::String(value.clone()),
+            Some(Type::HashMap(map)) => Bson::Document(map.iter().map(|(field, value)| (field.clone(), Bson::String(value.clone()))).collect()),
+            Some(Type::List(list)) => Bson::Array(list.iter().cloned().map(Bson::String).collect()),
+            Some(Type::User(_)) | None => Bson::Null,
+        }
+    }
+
+    pub fn set_strict_types(&mut self, strict_types: bool) {
+        self.strict_types = strict_types;
+    }
+
+    pub fn set_max_key_length(&mut self, max_key_length: Option<usize>) {
+        self.max_key_length = max_key_length;
+    }
+
+    pub fn max_key_length(&self) -> Option<usize> {
+        self.max_key_length
+    }
+
+    pub fn set_auth_persistence(&mut self, persistence: AuthPersistence) {
+        self.auth_persistence = Some(Arc::new(persistence));
+    }
+
+    pub fn write_pause(&self) -> Arc<WritePause> {
+        self.write_pause.clone()
+    }
+
+    /// The users to write into the config file, if persist_auth_changes is enabled. Take it right after changing them,
+    /// while still holding the write lock, so the snapshots are ordered like the changes
+    pub fn auth_snapshot(&self) -> Option<AuthSnapshot> {
+        let persistence = self.auth_persistence.as_ref()?;
+        Some(AuthSnapshot {
+            persistence: persistence.clone(),
+            generation: persistence.next_generation(),
+            users: self.users(),
+        })
+    }
+
+    /// True if strict types are enabled and the key exists with another type than expected. Users are treated as absent
+    pub fn is_wrong_type(&self, key: &str, expected: Kind) -> bool {
+        self.strict_types && self.value(key).is_some_and(|value| !matches!(value, Type::User(_)) && value.kind() != expected)
+    }
+
+    /// Inserts the value, replacing whatever was stored at the key. Fails if the key held another type and the limit of this type is reached
+    /// Replacing a value also removes its expiry
+    fn insert_value(&mut self, key: String, value: Type) -> Result<(), ErrorType> {
+        self.remove_if_expired(&key);
+        let kind = value.kind();
+        let previous = self.value(&key).map(Type::kind);
+        if previous != Some(kind) {
+            self.counts.add(&self.limits, kind, 1)?;
+            if let Some(previous) = previous {
+                self.counts.remove(previous);
+            }
+        }
+        self.changed(&key);
+        self.metadata.modified(&key);
+        self.expires.remove(&key);
+        self.values.insert(key, value);
+        Ok(())
+    }
+
+    fn remove_value(&mut self, key: &str) -> Option<Type> {
+        self.expires.remove(key);
+        let value = self.values.remove(key)?;
+        self.changed(key);
+        self.metadata.keys.remove(key);
+        if let Some(tags) = self.key_tags.get(key) {
+            let tags: Vec<String> = tags.iter().cloned().collect();
+            self.untag(key, tags);
+        }
+        self.counts.remove(value.kind());
+        Some(value)
+    }
+
+    /// Returns the value at key, creating an empty hash or list if the key doesn't exist yet.
+    /// Counts as a write unless the key holds another type
+    fn get_or_create(&mut self, key: String, kind: Kind) -> Result<&mut Type, ErrorType> {
+        self.remove_if_expired(&key);
+        self.values.try_reserve(1)?;
+        if self.values.get(&key).is_none_or(|value| value.kind() == kind) {
+            self.changed(&key);
+        }
+        match self.values.entry(key) {
+            Entry::Occupied(entry) => {
+                if entry.get().kind() == kind {
+                    self.metadata.modified(entry.key());
+                }
+                Ok(entry.into_mut())
+            }
+            Entry::Vacant(entry) => {
+                self.counts.add(&self.limits, kind, 1)?;
+                self.metadata.modified(entry.key());
+                let value = match kind {
+                    Kind::Hash => Type::HashMap(KeyMap::with_hasher(self.hasher.clone())),
+                    Kind::List => Type::List(VecDeque::new()),
+                    Kind::String | Kind::User => unreachable!("Only containers are created empty"),
+                };
+                Ok(entry.insert(value))
+            }
+        }
+    }
+
+    /// Shrinks the keyspace and every value to fit their contents, e.g. after deleting a lot of keys.
+    /// Returns an estimate of the freed bytes, like memory_usage. The allocator decides whether it actually goes back to the OS
+    pub fn compact(&mut self) -> usize {
+        let mut freed = 0;
+        for value in self.values.values_mut() {
+            match value {
+                Type::String(s) => freed += shrink_string(s),
+                Type::HashMap(map) => {
+                    freed += map.values_mut().map(shrink_string).sum::<usize>();
+                    let before = map.capacity();
+                    map.shrink_to_fit();
+                    freed += before.saturating_sub(map.capacity()) * (2 * size_of::<String>() + MAP_ENTRY_OVERHEAD);
+                }
+                Type::List(list) => {
+                    freed += list.iter_mut().map(shrink_string).sum::<usize>();
+                    let before = list.capacity();
+                    list.shrink_to_fit();
+                    freed += (before - list.capacity()) * size_of::<String>();
+                }
+                Type::User(_) => {}
+            }
+        }
+        let before = self.values.capacity();
+        self.values.shrink_to_fit();
+        freed += before.saturating_sub(self.values.capacity()) * (size_of::<String>() + size_of::<Type>() + MAP_ENTRY_OVERHEAD);
+        freed
+    }
+
+    /// The list at key, empty if the key doesn't hold a list
+    fn list_or_empty(&self, list_key: &str) -> &VecDeque<String> {
+        match self.value(list_key) {
+            Some(Type::List(list)) => list,
+            _ => &EMPTY_LIST,
+        }
+    }
+
+    /// Checks a list that is about to hold new_len elements against max_list_length. If full lists are trimmed,
+    /// returns the length to trim it to after the push. Otherwise fails if the list would get too long
+    fn list_length_limit(&self, new_len: impl FnOnce() -> usize) -> Result<Option<usize>, ErrorType> {
+        match self.limits.max_list_length {
+            Some(limit) if self.limits.trim_full_lists => Ok(Some(limit)),
+            Some(limit) if new_len() > limit => Err(ErrorType::SizeLimit(Kind::List.name(), limit)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Fails if setting the fields would leave the hash with more than max_hash_fields. Only fields it doesn't contain yet count
+    pub fn check_hash_fields<'a>(&self, map_key: &str, fields: impl IntoIterator<Item = &'a String>) -> Result<(), ErrorType> {
+        let Some(limit) = self.limits.max_hash_fields else {
+            return Ok(());
+        };
+        let new_len = match self.value(map_key) {
+            Some(Type::HashMap(map)) => map.len() + fields.into_iter().filter(|field| !map.contains_key(*field)).count(),
+            _ => fields.into_iter().count(),
+        };
+        if new_len > limit {
+            return Err(ErrorType::SizeLimit(Kind::Hash.name(), limit));
+        }
+        Ok(())
+    }
+
+    /// Approximate number of bytes the key and its value occupy, including the bookkeeping of the maps and vecs
+    /// None if the key doesn't exist or is a user
+    pub fn memory_usage(&self, key: &str) -> Option<usize> {
+        let string_size = |s: &String| size_of::<String>() + s.capacity();
+        let value = match self.value(key)? {
+            Type::String(s) => s.capacity(),
+            Type::HashMap(map) => {
+                let unused = map.capacity() - map.len();
+                map.iter().map(|(k, v)| string_size(k) + string_size(v) + MAP_ENTRY_OVERHEAD).sum::<usize>()
+                    + unused * (2 * size_of::<String>() + MAP_ENTRY_OVERHEAD)
+            }
+            Type::List(list) => {
+                list.iter().map(|s| s.capacity()).sum::<usize>() + list.capacity() * size_of::<String>()
             }
+            Type::User(_) => return None,
+        };
+        Some(key.len() + size_of::<String>() + size_of::<Type>() + MAP_ENTRY_OVERHEAD + value)
+    }
+
+    /// How many keys fall into each size bucket, estimated like memory_usage. Every bucket is listed, even empty ones. Users are left out
+    pub fn size_histogram(&self) -> Vec<(&'static str, usize)> {
+        let mut histogram: Vec<(&'static str, usize)> = SIZE_BUCKETS.iter().map(|(_, name)| (*name, 0)).collect();
+        histogram.push((">=4MB", 0));
+        for key in self.values.keys() {
+            let Some(size) = self.memory_usage(key) else { continue };
+            let bucket = SIZE_BUCKETS.iter().position(|(bound, _)| size < *bound).unwrap_or(SIZE_BUCKETS.len());
+            histogram[bucket].1 += 1;
         }
+        histogram
+    }
+
+    /// Returns the type name of the value at key, string, hash, list or user, or None if the key doesn't exist
+    pub fn type_of(&self, key: &str) -> Option<&'static str> {
+        self.value(key).map(Type::name)
+    }
+
+    /// Like type_of for every key, but users are reported as absent
+    pub fn key_types(&self, keys: Vec<String>) -> Vec<(String, Option<&'static str>)> {
+        keys.into_iter().map(|key| {
+            let name = match self.value(&key) {
+                None | Some(Type::User(_)) => None,
+                Some(value) => Some(value.name()),
+            };
+            (key, name)
+        }).collect()
+    }
+}
+
+impl StoreAble for Store {
+    fn get(&self, key: &str) -> Option<&String> {
+        match self.value(key) {
+            Some(Type::String(s)) => Some(s),
+            _ => None
+        }
+    }
+
+    fn set(&mut self, key: String, value: String) -> Result<(), ErrorType> {
+        if self.values.try_reserve(1).is_err() {
+            self.values.shrink_to_fit();
+            self.values.try_reserve(1)?;
+        }
+        self.insert_value(key, Type::String(value))
     }
 
     fn remove(&mut self, key: &str) -> Option<String> {
-        match self.values.get(key) {
+        match self.value(key) {
             None => {
                 None
             }
             Some(value) => {
                 match value {
                     Type::String(_) => {
-                        self.values.remove(key).map(|v| {
+                        self.remove_value(key).map(|v| {
                             match v {
                                 Type::String(s) => s,
                                 _ => unreachable!("Value was not a string, although is was a string when checked previously")
@@ -144,6 +1004,101 @@ impl StoreAble for Store {
             }
         }
     }
+
+    fn compare_set(&mut self, key: String, expected: Option<String>, value: String) -> Result<bool, ErrorType> {
+        let matches = match (self.value(&key), expected) {
+            (Some(Type::String(current)), Some(expected)) => *current == expected,
+            (Some(Type::String(_)), None) => false,
+            (Some(_), _) => return Err(ErrorType::WrongType),
+            (None, expected) => expected.is_none(),
+        };
+        if matches {
+            self.set(key, value)?;
+        }
+        Ok(matches)
+    }
+
+    fn set_with_version(&mut self, key: String, expected: u64, value: String) -> Result<(bool, u64), ErrorType> {
+        let current = match self.value(&key) {
+            Some(Type::String(_)) | None => self.version(&key),
+            Some(_) => return Err(ErrorType::WrongType),
+        };
+        if current != expected {
+            return Ok((false, current));
+        }
+        self.set(key.clone(), value)?;
+        Ok((true, self.version(&key)))
+    }
+
+    fn multi_lock(&mut self, mut keys: Vec<String>, token: String) -> Result<Vec<String>, ErrorType> {
+        keys.sort_unstable();
+        keys.dedup();
+        for key in keys.iter() {
+            self.remove_if_expired(key);
+        }
+        let blocked: Vec<String> = keys.iter().filter(|key| self.values.contains_key(*key)).cloned().collect();
+        if !blocked.is_empty() {
+            return Ok(blocked);
+        }
+        // Everything that can fail happens before the first key is written
+        self.values.try_reserve(keys.len())?;
+        self.counts.add(&self.limits, Kind::String, keys.len())?;
+        for key in keys {
+            self.changed(&key);
+            self.metadata.modified(&key);
+            self.values.insert(key, Type::String(token.clone()));
+        }
+        Ok(blocked)
+    }
+
+    fn get_or_set(&mut self, key: String, default: String) -> Result<(String, bool), ErrorType> {
+        match self.value(&key) {
+            Some(Type::String(value)) => Ok((value.clone(), false)),
+            Some(_) => Err(ErrorType::WrongType),
+            None => {
+                self.set(key, default.clone())?;
+                Ok((default, true))
+            }
+        }
+    }
+
+    fn decr_del(&mut self, key: String, delta: i64) -> Result<(i64, bool), ErrorType> {
+        let current = match self.value(&key) {
+            Some(Type::String(value)) => value.parse::<i64>()?,
+            Some(_) => return Err(ErrorType::WrongType),
+            None => 0,
+        };
+        let new_value = current.checked_sub(delta).ok_or(ErrorType::Overflow)?;
+        if new_value <= 0 {
+            self.remove_value(&key);
+            return Ok((new_value, true));
+        }
+        self.set(key, new_value.to_string())?;
+        Ok((new_value, false))
+    }
+
+    fn append_if(&mut self, key: String, suffix: String) -> Result<(bool, usize), ErrorType> {
+        match self.value(&key) {
+            Some(Type::String(value)) if value.ends_with(&suffix) => return Ok((false, value.len())),
+            Some(Type::String(_)) => {}
+            Some(_) => return Err(ErrorType::WrongType),
+            None => {
+                let len = suffix.len();
+                self.set(key, suffix)?;
+                return Ok((true, len));
+            }
+        }
+        let len = match self.get_mut_value(&key) {
+            Some(Type::String(value)) => {
+                value.try_reserve(suffix.len())?;
+                value.push_str(&suffix);
+                value.len()
+            }
+            _ => unreachable!("Value was not a string, although is was a string when checked previously"),
+        };
+        self.modified(&key);
+        Ok((true, len))
+    }
 }
 
 impl ACLAble for Store {
@@ -151,6 +1106,14 @@ impl ACLAble for Store {
         self.acl.add(user, command);
     }
 
+    fn acl_add_anonymous(&mut self, command: CommandID) {
+        self.acl.add_anonymous(command);
+    }
+
+    fn acl_disable(&mut self, command: CommandID) {
+        self.acl.disable(command);
+    }
+
     fn acl_remove(&mut self, user: &str, command: CommandID) {
         self.acl.remove(user, command);
     }
@@ -162,6 +1125,18 @@ impl ACLAble for Store {
     fn acl_list(&self, user: &str) -> Vec<CommandID> {
         self.acl.list(user)
     }
+
+    fn acl_version(&self) -> AclVersion {
+        self.acl.version()
+    }
+
+    fn acl_export(&self) -> AclExport {
+        self.acl.export()
+    }
+
+    fn acl_import(&mut self, acls: AclExport, replace: bool) {
+        self.acl.import(acls, replace);
+    }
 }
 
 impl UserAble for Store {
@@ -174,7 +1149,7 @@ impl UserAble for Store {
     }
 
     fn user_remove(&mut self, user: &str) -> bool {
-        match self.values.get(user) {
+        match self.value(user) {
             Some(Type::User(_)) => {
                 self.values.remove(user);
                 true
@@ -184,7 +1159,7 @@ impl UserAble for Store {
     }
 
     fn user_is_valid(&self, user: &str, password: &str) -> bool {
-        match self.values.get(user) {
+        match self.value(user) {
             Some(Type::User((p, _))) => {
                 p == password
             }
@@ -193,7 +1168,7 @@ impl UserAble for Store {
     }
 
     fn verify_key(&self, user: &str, key: &Recipient) -> bool {
-        match self.values.get(user) {
+        match self.value(user) {
             Some(Type::User((_, Some(k)))) => {
                 k == key
             }
@@ -202,17 +1177,35 @@ impl UserAble for Store {
     }
 
     fn user_has_key(&self, user: &str) -> bool {
-        match self.values.get(user) {
+        match self.value(user) {
             Some(Type::User((_, Some(_)))) => true,
             _ => false
         }
     }
+
+    fn users(&self) -> Vec<ConfigUser> {
+        let mut users = self.values.iter().filter_map(|(name, value)| match value {
+            Type::User((password, public_key)) => {
+                let mut acls = self.acl.list(name).iter().map(|command| command.to_string()).collect::<Vec<String>>();
+                acls.sort();
+                Some(ConfigUser {
+                    name: name.clone(),
+                    password: password.clone(),
+                    public_key: public_key.as_ref().map(|key| key.to_string()),
+                    acls,
+                })
+            }
+            _ => None
+        }).collect::<Vec<ConfigUser>>();
+        users.sort_by(|a, b| a.name.cmp(&b.name));
+        users
+    }
 }
 
 impl HashMapAble<String> for Store {
-    fn hadd(&mut self, map_key: String, key: String, value: String) -> Result<(), TryReserveError> {
-        self.values.try_reserve(1)?;
-        if let Type::HashMap(ref mut map) = self.values.entry(map_key).or_insert(Type::HashMap(HashMap::new())) {
+    fn hadd(&mut self, map_key: String, key: String, value: String) -> Result<(), ErrorType> {
+        self.check_hash_fields(&map_key, [&key])?;
+        if let Type::HashMap(map) = self.get_or_create(map_key, Kind::Hash)? {
             map.try_reserve(1)?;
             map.insert(key, value);
         }
@@ -220,17 +1213,18 @@ impl HashMapAble<String> for Store {
     }
 
     fn hremove(&mut self, map_key: String, key: String) -> bool {
-        match self.values.get_mut(&map_key) {
-            Some(Type::HashMap(map)) => {
-                map.remove(&key);
-                true
-            }
-            _ => false
+        let removed = match self.get_mut_value(&map_key) {
+            Some(Type::HashMap(map)) => map.remove(&key).is_some(),
+            _ => return false
+        };
+        if removed {
+            self.modified(&map_key);
         }
+        true
     }
 
     fn hcontains(&self, map_key: String, key: String) -> bool {
-        match self.values.get(&map_key) {
+        match self.value(&map_key) {
             Some(Type::HashMap(map)) => {
                 map.contains_key(&key)
             }
@@ -239,7 +1233,7 @@ impl HashMapAble<String> for Store {
     }
 
     fn hget(&self, map_key: String, key: String) -> Option<&String> {
-        match self.values.get(&map_key) {
+        match self.value(&map_key) {
             Some(Type::HashMap(map)) => {
                 map.get(&key)
             }
@@ -248,7 +1242,7 @@ impl HashMapAble<String> for Store {
     }
 
     fn hget_all(&self, map_key: String) -> Result<HashMap<String, String>, TryReserveError> {
-        match self.values.get(&map_key) {
+        match self.value(&map_key) {
             Some(Type::HashMap(map)) => {
                 let mut new_map = HashMap::new();
                 new_map.try_reserve(map.len())?;
@@ -265,7 +1259,7 @@ impl HashMapAble<String> for Store {
     }
 
     fn hget_all_values(&self, map_key: String) -> Result<Vec<String>, TryReserveError> {
-        match self.values.get(&map_key) {
+        match self.value(&map_key) {
             Some(Type::HashMap(map)) => {
                 let mut values = Vec::new();
                 values.try_reserve_exact(map.len())?;
@@ -281,7 +1275,7 @@ impl HashMapAble<String> for Store {
     }
 
     fn hkeys(&self, map_key: String) -> Result<Vec<String>, TryReserveError> {
-        match self.values.get(&map_key) {
+        match self.value(&map_key) {
             Some(Type::HashMap(map)) => {
                 let mut keys = Vec::new();
                 keys.try_reserve_exact(map.len())?;
@@ -296,25 +1290,172 @@ impl HashMapAble<String> for Store {
         }
     }
 
-    fn hlen(&self, map_key: String) -> usize {
-        match self.values.get(&map_key) {
-            Some(Type::HashMap(map)) => map.len(),
-            // This also captures the case where the key does exist, but has a different type
-            _ => 0
+    fn hfilter(&self, map_key: String, predicate: &Predicate) -> Result<Vec<(String, String)>, TryReserveError> {
+        let mut matches = Vec::new();
+        if let Some(Type::HashMap(map)) = self.value(&map_key) {
+            for (field, value) in map.iter().filter(|(_, value)| predicate.matches(value)) {
+                matches.try_reserve(1)?;
+                matches.push((field.clone(), value.clone()));
+            }
+        }
+        Ok(matches)
+    }
+
+    fn htop(&self, map_key: String, count: usize, strict: bool) -> Result<Vec<(String, String)>, ErrorType> {
+        let Some(Type::HashMap(map)) = self.value(&map_key) else {
+            return Ok(Vec::new());
+        };
+        let mut scores = Vec::new();
+        scores.try_reserve(map.len())?;
+        for (field, value) in map.iter() {
+            match value.trim().parse::<f64>() {
+                Ok(score) if !score.is_nan() => scores.push((score, field, value)),
+                _ if strict => return Err(ErrorType::WrongType),
+                _ => {}
+            }
+        }
+        scores.sort_unstable_by(|a, b| b.0.total_cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+        let mut top = Vec::new();
+        top.try_reserve(count.min(scores.len()))?;
+        top.extend(scores.into_iter().take(count).map(|(_, field, value)| (field.clone(), value.clone())));
+        Ok(top)
+    }
+
+    fn hget_sorted(&self, map_key: String, descending: bool, offset: usize, count: Option<usize>) -> Result<Vec<(String, String)>, TryReserveError> {
+        match self.value(&map_key) {
+            Some(Type::HashMap(map)) => {
+                // Only sort references, so just the requested page gets cloned
+                let mut entries = Vec::new();
+                entries.try_reserve_exact(map.len())?;
+                entries.extend(map.iter());
+                entries.sort_unstable_by(|a, b| a.0.cmp(b.0));
+                if descending {
+                    entries.reverse();
+                }
+                let page = entries.into_iter().skip(offset).take(count.unwrap_or(usize::MAX));
+                let mut pairs = Vec::new();
+                pairs.try_reserve_exact(page.len())?;
+                pairs.extend(page.map(|(k, v)| (k.clone(), v.clone())));
+                Ok(pairs)
+            }
+            _ => Ok(Vec::new())
+        }
+    }
+
+    fn hkeys_match(&self, map_key: String, pattern: &str) -> Result<Vec<String>, TryReserveError> {
+        match self.value(&map_key) {
+            Some(Type::HashMap(map)) => {
+                let mut keys = Vec::new();
+                for k in map.keys().filter(|k| glob_match(pattern, k)) {
+                    keys.try_reserve(1)?;
+                    keys.push(k.clone());
+                }
+                Ok(keys)
+            }
+            _ => Ok(Vec::new())
+        }
+    }
+
+    fn hlen(&self, map_key: String) -> usize {
+        match self.value(&map_key) {
+            Some(Type::HashMap(map)) => map.len(),
+            // This also captures the case where the key does exist, but has a different type
+            _ => 0
+        }
+    }
+
+    fn hupsert(&mut self, map_key: String, key: String, value: String) -> Result<(), ErrorType> {
+        self.check_hash_fields(&map_key, [&key])?;
+        if let Type::HashMap(map) = self.get_or_create(map_key, Kind::Hash)? {
+            map.try_reserve(1)?;
+            map.insert(key, value);
+        }
+        Ok(())
+    }
+
+    fn hset_multi(&mut self, hashes: HashMap<String, HashMap<String, String>>) -> Result<HashMap<String, usize>, ErrorType> {
+        for map_key in hashes.keys() {
+            self.remove_if_expired(map_key);
+        }
+        // Everything that can fail happens before the first field is written
+        let mut new_hashes = 0;
+        for (map_key, fields) in hashes.iter() {
+            match self.value(map_key) {
+                Some(Type::HashMap(_)) => {}
+                Some(_) => return Err(ErrorType::WrongType),
+                None => new_hashes += 1,
+            }
+            self.check_hash_fields(map_key, fields.keys())?;
+        }
+        self.values.try_reserve(new_hashes)?;
+        let mut counts = HashMap::new();
+        counts.try_reserve(hashes.len())?;
+        let mut staged = Vec::new();
+        staged.try_reserve_exact(new_hashes)?;
+        for (map_key, fields) in hashes.iter() {
+            match self.values.get_mut(map_key) {
+                Some(Type::HashMap(map)) => map.try_reserve(fields.len())?,
+                _ => {
+                    let mut map = KeyMap::with_hasher(self.hasher.clone());
+                    map.try_reserve(fields.len())?;
+                    staged.push((map_key.clone(), map));
+                }
+            }
+        }
+        // Counted last, because nothing may fail once the new hashes are counted
+        self.counts.add(&self.limits, Kind::Hash, new_hashes)?;
+        for (map_key, map) in staged {
+            self.values.insert(map_key, Type::HashMap(map));
+        }
+
+        for (map_key, fields) in hashes {
+            if let Some(Type::HashMap(map)) = self.get_mut_value(&map_key) {
+                counts.insert(map_key.clone(), fields.len());
+                map.extend(fields);
+                self.modified(&map_key);
+            }
+        }
+        Ok(counts)
+    }
+
+    fn hdump(&self, map_key: String) -> Result<Option<HashMap<String, String>>, TryReserveError> {
+        match self.value(&map_key) {
+            Some(Type::HashMap(_)) => self.hget_all(map_key).map(Some),
+            _ => Ok(None)
         }
     }
 
-    fn hupsert(&mut self, map_key: String, key: String, value: String) -> Result<(), TryReserveError> {
-        self.values.try_reserve(1)?;
-        if let Type::HashMap(ref mut map) = self.values.entry(map_key).or_insert(Type::HashMap(HashMap::new())) {
-            map.try_reserve(1)?;
-            map.insert(key, value);
+    fn hrestore(&mut self, map_key: String, fields: HashMap<String, String>, replace: bool) -> Result<(), ErrorType> {
+        if replace {
+            // The fields replace the whole hash, so the ones it holds now don't count
+            if let Some(limit) = self.limits.max_hash_fields.filter(|limit| fields.len() > *limit) {
+                return Err(ErrorType::SizeLimit(Kind::Hash.name(), limit));
+            }
+        } else {
+            self.check_hash_fields(&map_key, fields.keys())?;
+        }
+        match self.get_mut_value(&map_key) {
+            Some(Type::HashMap(map)) if !replace => {
+                map.try_reserve(fields.len())?;
+                map.extend(fields);
+                self.modified(&map_key);
+                Ok(())
+            }
+            Some(_) if !replace => Err(ErrorType::WrongType),
+            // Users share the keyspace, but they are never replaced by a hash
+            Some(Type::User(_)) => Err(ErrorType::WrongType),
+            _ => {
+                self.values.try_reserve(1)?;
+                let mut map = KeyMap::with_hasher(self.hasher.clone());
+                map.try_reserve(fields.len())?;
+                map.extend(fields);
+                self.insert_value(map_key, Type::HashMap(map))
+            }
         }
-        Ok(())
     }
 
     fn hstr_len(&self, map_key: String, key: String) -> Option<usize> {
-        match self.values.get(&map_key) {
+        match self.value(&map_key) {
             Some(map) => {
                 match map {
                     Type::HashMap(map) => {
@@ -330,13 +1471,35 @@ impl HashMapAble<String> for Store {
         }
     }
 
+    fn hswap(&mut self, map_key: String, field_a: String, field_b: String) -> bool {
+        let swapped = match self.get_mut_value(&map_key) {
+            Some(Type::HashMap(map)) => {
+                let a = map.remove(&field_a);
+                let b = map.remove(&field_b);
+                let swapped = a.is_some() || b.is_some();
+                if let Some(a) = a {
+                    map.insert(field_b, a);
+                }
+                if let Some(b) = b {
+                    map.insert(field_a, b);
+                }
+                swapped
+            }
+            _ => return false,
+        };
+        if swapped {
+            self.modified(&map_key);
+        }
+        true
+    }
+
     fn hincrby(&mut self, map_key: String, key: String, value: i64) -> Result<i64, ErrorType> {
-        self.values.try_reserve(1)?;
-        if let Type::HashMap(ref mut map) = self.values.entry(map_key).or_insert(Type::HashMap(HashMap::new())) {
+        self.check_hash_fields(&map_key, [&key])?;
+        if let Type::HashMap(map) = self.get_or_create(map_key, Kind::Hash)? {
             map.try_reserve(1)?;
             let new_value = match map.get(&key) {
                 Some(v) => {
-                    let new_value = v.parse::<i64>()?.checked_add(value).unwrap_or(0);
+                    let new_value = v.parse::<i64>()?.checked_add(value).ok_or(ErrorType::Overflow)?;
                     map.insert(key, new_value.to_string());
                     new_value
                 }
@@ -347,15 +1510,14 @@ impl HashMapAble<String> for Store {
             };
             Ok(new_value)
         } else {
-            // This should never happen as the entry is always a hashmap since we are inserting a hashmap
-            unreachable!("This should never happen, because a just inserted hashmap is not a hashmap, which is nonsensical")
+            Err(ErrorType::WrongType)
         }
     }
 }
 
 impl ListAble for Store {
     fn llen(&self, list_key: String) -> usize {
-        match self.values.get(&list_key) {
+        match self.value(&list_key) {
             Some(list) => {
                 match list {
                     Type::List(l) => l.len(),
@@ -367,7 +1529,7 @@ impl ListAble for Store {
     }
 
     fn lindex(&self, list_key: String, value: String) -> Option<usize> {
-        match self.values.get(&list_key) {
+        match self.value(&list_key) {
             Some(list) => {
                 match list {
                     Type::List(l) => {
@@ -380,6 +1542,51 @@ impl ListAble for Store {
         }
     }
 
+    fn linter(&self, first_key: String, second_key: String, dedup: bool) -> Result<Vec<String>, TryReserveError> {
+        let second: HashSet<&String> = self.list_or_empty(&second_key).iter().collect();
+        let mut seen = HashSet::new();
+        let mut common = Vec::new();
+        for item in self.list_or_empty(&first_key) {
+            if second.contains(item) && (!dedup || seen.insert(item)) {
+                common.try_reserve(1)?;
+                common.push(item.clone());
+            }
+        }
+        Ok(common)
+    }
+
+    fn ldiff(&self, first_key: String, second_key: String) -> Result<Vec<String>, TryReserveError> {
+        let second: HashSet<&String> = self.list_or_empty(&second_key).iter().collect();
+        let mut diff = Vec::new();
+        for item in self.list_or_empty(&first_key).iter().filter(|item| !second.contains(item)) {
+            diff.try_reserve(1)?;
+            diff.push(item.clone());
+        }
+        Ok(diff)
+    }
+
+    fn lget(&self, list_key: String, index: isize) -> Option<&String> {
+        match self.value(&list_key) {
+            Some(Type::List(l)) => {
+                let index = if index < 0 { l.len().checked_sub(index.unsigned_abs())? } else { index as usize };
+                l.get(index)
+            }
+            _ => None
+        }
+    }
+
+    fn drain(&mut self, list_key: String) -> Vec<String> {
+        match self.value(&list_key) {
+            Some(Type::List(_)) => {
+                match self.remove_value(&list_key) {
+                    Some(Type::List(list)) => list.into(),
+                    _ => unreachable!("Value was not a list, although is was a list when checked previously")
+                }
+            }
+            _ => Vec::new()
+        }
+    }
+
     fn lmove(&mut self, src_key: String, dest_key: String, left_right: String, right_left: String) -> Option<String> {
         // left_right needs to be either "left" or "right"
         if !left_right.eq_ignore_ascii_case("right") && !left_right.eq_ignore_ascii_case("left") {
@@ -389,62 +1596,99 @@ impl ListAble for Store {
         if !right_left.eq_ignore_ascii_case("right") && !right_left.eq_ignore_ascii_case("left") {
             return None;
         }
-        let mut src = match self.values.remove(&src_key) {
-            Some(Type::List(src_list)) => {
-                Some(src_list)
-            }
-            _ => None
-        };
-
-        if src.is_none() {
+        if !matches!(self.value(&dest_key), Some(Type::List(_))) {
             return None;
         }
-        let src_list = src.as_mut().unwrap();
-        let ret = match self.values.get_mut(&dest_key) {
-            Some(Type::List(dest_list)) => {
+        let element = match self.get_mut_value(&src_key) {
+            Some(Type::List(src_list)) if !src_list.is_empty() => {
                 if left_right.eq_ignore_ascii_case("left") {
-                    if right_left.eq_ignore_ascii_case("right") {
-                        dest_list.push(src_list.remove(0));
-                    } else {
-                        dest_list.insert(0, src_list.remove(0));
-                    }
+                    src_list.pop_front()?
                 } else {
-                    if right_left.eq_ignore_ascii_case("right") {
-                        dest_list.push(src_list.pop().unwrap());
-                    } else {
-                        dest_list.insert(0, src_list.pop().unwrap());
-                    }
+                    src_list.pop_back()?
                 }
-                Some(dest_list.last().unwrap().clone())
             }
-            _ => None
+            _ => return None
         };
-        self.values.insert(src_key, Type::List(src_list.clone()));
-
-        ret
+        if let Some(Type::List(dest_list)) = self.get_mut_value(&dest_key) {
+            if right_left.eq_ignore_ascii_case("right") {
+                dest_list.push_back(element.clone());
+            } else {
+                dest_list.push_front(element.clone());
+            }
+        }
+        self.modified(&src_key);
+        self.modified(&dest_key);
+        Some(element)
     }
 
     fn lpop(&mut self, list_key: String, count: Option<usize>) -> Result<Option<Vec<String>>, TryReserveError> {
         let count = count.unwrap_or(1);
-        match self.values.get_mut(&list_key) {
+        let popped = match self.get_mut_value(&list_key) {
             Some(Type::List(list)) => {
                 let mut popped = Vec::new();
                 popped.try_reserve_exact(count)?;
                 for _ in 0..count {
-                    if let Some(v) = list.pop() {
+                    if let Some(v) = list.pop_back() {
                         popped.push(v);
                     } else {
                         break;
                     }
                 }
-                Ok(Some(popped))
+                popped
             }
-            _ => Ok(None)
+            _ => return Ok(None)
+        };
+        if !popped.is_empty() {
+            self.modified(&list_key);
+        }
+        Ok(Some(popped))
+    }
+
+    fn lpop_to_list(&mut self, source_key: String, destination_key: String, from: ListEnd) -> Result<Option<String>, ErrorType> {
+        for key in [&source_key, &destination_key] {
+            match self.value(key) {
+                Some(Type::List(_)) | None => {}
+                Some(_) => return Err(ErrorType::WrongType),
+            }
+        }
+        if self.list_or_empty(&source_key).is_empty() {
+            return Ok(None);
+        }
+        if source_key == destination_key {
+            return Ok(match from {
+                ListEnd::Left => self.lrotate(source_key, true),
+                // Popping the tail and pushing it back onto the tail leaves the list as it is
+                ListEnd::Right => self.list_or_empty(&source_key).back().cloned(),
+            });
+        }
+        // Everything that can fail happens before the element is popped
+        let trim_to = self.list_length_limit(|| self.list_or_empty(&destination_key).len() + 1)?;
+        if let Type::List(list) = self.get_or_create(destination_key.clone(), Kind::List)? {
+            list.try_reserve(1)?;
+        }
+
+        let value = match self.get_mut_value(&source_key) {
+            Some(Type::List(list)) => match from {
+                ListEnd::Left => list.pop_front(),
+                ListEnd::Right => list.pop_back(),
+            },
+            _ => None,
+        }.expect("The source was checked to be a non empty list");
+        if self.list_or_empty(&source_key).is_empty() {
+            self.remove_value(&source_key);
+        } else {
+            self.modified(&source_key);
         }
+        // get_or_create already counted the destination as written
+        if let Some(Type::List(list)) = self.get_mut_value(&destination_key) {
+            list.push_back(value.clone());
+            trim_oldest(list, trim_to, false);
+        }
+        Ok(Some(value))
     }
 
     fn lpos(&self, list_key: String, value: String, rank: Option<isize>, count: Option<usize>, max_len: Option<usize>) -> Result<Option<Vec<usize>>, TryReserveError> {
-        let list = match self.values.get(&list_key) {
+        let list = match self.value(&list_key) {
             Some(Type::List(l)) => l,
             _ => return Ok(None),
         };
@@ -490,41 +1734,69 @@ impl ListAble for Store {
         }
     }
 
-    fn lpush(&mut self, list_key: String, values: Vec<String>) -> Result<(), TryReserveError> {
-        self.values.try_reserve(1)?;
-        if let Type::List(ref mut list) = self.values.entry(list_key).or_insert(Type::List(Vec::new())) {
+    fn lpush(&mut self, list_key: String, values: Vec<String>) -> Result<(), ErrorType> {
+        let trim_to = self.list_length_limit(|| self.list_or_empty(&list_key).len() + values.len())?;
+        if let Type::List(list) = self.get_or_create(list_key, Kind::List)? {
             list.try_reserve(values.len())?;
             list.extend(values.into_iter());
+            // The values are appended, so the oldest elements are at the front
+            trim_oldest(list, trim_to, false);
         }
         Ok(())
     }
 
-    fn lpushx(&mut self, list_key: String, values: Vec<String>) -> Result<(), TryReserveError> {
-        match self.values.get_mut(&list_key) {
-            Some(Type::List(list)) => {
-                list.try_reserve(values.len())?;
-                list.extend(values.into_iter());
-                Ok(())
+    fn lpush_unique(&mut self, list_key: String, values: Vec<String>, max_len: Option<usize>) -> Result<usize, ErrorType> {
+        let trim_to = self.list_length_limit(|| {
+            let unique: HashSet<&String> = values.iter().collect();
+            unique.len() + self.list_or_empty(&list_key).iter().filter(|x| !unique.contains(x)).count()
+        })?;
+        if let Type::List(list) = self.get_or_create(list_key, Kind::List)? {
+            list.try_reserve(values.len())?;
+            for value in values {
+                list.retain(|x| x != &value);
+                list.push_front(value);
+            }
+            trim_oldest(list, trim_to, true);
+            if let Some(max_len) = max_len {
+                list.truncate(max_len);
+            }
+            Ok(list.len())
+        } else {
+            Ok(0)
+        }
+    }
+
+    fn lpushx(&mut self, list_key: String, values: Vec<String>) -> Result<(), ErrorType> {
+        let trim_to = self.list_length_limit(|| self.list_or_empty(&list_key).len() + values.len())?;
+        let pushed = !values.is_empty();
+        if let Some(Type::List(list)) = self.get_mut_value(&list_key) {
+            list.try_reserve(values.len())?;
+            list.extend(values.into_iter());
+            trim_oldest(list, trim_to, false);
+            if pushed {
+                self.modified(&list_key);
             }
-            _ => Ok(())
         }
+        Ok(())
     }
 
     fn lrange(&self, list_key: String, start: isize, stop: isize) -> Result<Vec<String>, TryReserveError> {
-        match self.values.get(&list_key) {
+        match self.value(&list_key) {
             Some(Type::List(list)) => {
                 let mut new_list = Vec::new();
                 new_list.try_reserve_exact(list.len())?;
                 if start.is_negative() {
+                    // A start further back than the list is long begins at the head
+                    let start = (start + list.len() as isize).max(0);
                     if stop.is_negative() {
-                        for i in (start + list.len() as isize)..(stop + list.len() as isize) {
+                        for i in start..(stop + list.len() as isize) {
                             if i > list.len() as isize - 1 {
                                 break;
                             }
                             new_list.push(list[i as usize].clone());
                         }
                     } else {
-                        for i in (start + list.len() as isize)..stop {
+                        for i in start..stop {
                             if i > list.len() as isize - 1 {
                                 break;
                             }
@@ -554,8 +1826,25 @@ impl ListAble for Store {
         }
     }
 
+    fn lrotate(&mut self, list_key: String, left_to_right: bool) -> Option<String> {
+        let moved = match self.get_mut_value(&list_key) {
+            Some(Type::List(list)) if !list.is_empty() => {
+                if left_to_right {
+                    list.rotate_left(1);
+                    list.back().cloned()
+                } else {
+                    list.rotate_right(1);
+                    list.front().cloned()
+                }
+            }
+            _ => return None
+        };
+        self.modified(&list_key);
+        moved
+    }
+
     fn lrem(&mut self, list_key: String, count: isize, value: String) -> usize {
-        match self.values.get_mut(&list_key) {
+        let removed = match self.get_mut_value(&list_key) {
             Some(Type::List(list)) => {
                 let mut removed = 0;
                 let mut indicies = Vec::new();
@@ -584,11 +1873,15 @@ impl ListAble for Store {
                 removed
             }
             _ => 0
+        };
+        if removed > 0 {
+            self.modified(&list_key);
         }
+        removed
     }
 
     fn lset(&mut self, list_key: String, index: isize, value: String) -> bool {
-        match self.values.get_mut(&list_key) {
+        match self.get_mut_value(&list_key) {
             Some(Type::List(list)) => {
                 if index.is_negative() {
                     let i = index + list.len() as isize;
@@ -596,21 +1889,21 @@ impl ListAble for Store {
                         return false;
                     }
                     list[i as usize] = value;
-                    true
                 } else {
-                    if index as usize > list.len() - 1 {
+                    if index as usize >= list.len() {
                         return false;
                     }
                     list[index as usize] = value;
-                    true
                 }
             }
-            _ => false
+            _ => return false
         }
+        self.modified(&list_key);
+        true
     }
 
     fn ltrim(&mut self, list_key: String, start: isize, stop: isize) -> bool {
-        match self.values.get_mut(&list_key) {
+        let trimmed = match self.get_mut_value(&list_key) {
             Some(Type::List(list)) => {
                 let len = list.len() as isize;
                 let start = if start < 0 { len + start } else { start };
@@ -624,51 +1917,876 @@ impl ListAble for Store {
                     list.drain(..start);
                     list.drain((stop - start + 1)..);
                 }
-                true
+                list.len() as isize != len
             }
-            _ => false,
+            _ => return false,
+        };
+        if trimmed {
+            self.modified(&list_key);
         }
+        true
     }
 
     fn rpop(&mut self, list_key: String, count: Option<usize>) -> Option<Vec<String>> {
         let count = count.unwrap_or(1);
-        match self.values.get_mut(&list_key) {
+        let popped = match self.get_mut_value(&list_key) {
             Some(Type::List(list)) => {
                 let mut popped = Vec::new();
                 for _ in 0..count {
-                    if let Some(v) = list.pop() {
+                    if let Some(v) = list.pop_back() {
                         popped.push(v);
                     } else {
                         break;
                     }
                 }
-                if popped.is_empty() {
-                    None
-                } else {
-                    Some(popped)
-                }
+                popped
             }
-            _ => None,
+            _ => return None,
+        };
+        if popped.is_empty() {
+            return None;
         }
+        self.modified(&list_key);
+        Some(popped)
     }
 
-    fn rpush(&mut self, list_key: String, values: Vec<String>) -> Result<(), TryReserveError> {
+    fn lreplace(&mut self, list_key: String, values: Vec<String>) -> Result<usize, ErrorType> {
+        match self.value(&list_key) {
+            Some(Type::List(_)) | None => {}
+            Some(_) => return Err(ErrorType::WrongType),
+        }
+        let mut values = VecDeque::from(values);
+        let trim_to = self.list_length_limit(|| values.len())?;
+        trim_oldest(&mut values, trim_to, false);
+        if values.is_empty() {
+            self.remove_value(&list_key);
+            return Ok(0);
+        }
         self.values.try_reserve(1)?;
-        if let Type::List(ref mut list) = self.values.entry(list_key).or_insert(Type::List(Vec::new())) {
+        let len = values.len();
+        self.insert_value(list_key, Type::List(values))?;
+        Ok(len)
+    }
+
+    fn rpush(&mut self, list_key: String, values: Vec<String>) -> Result<(), ErrorType> {
+        let trim_to = self.list_length_limit(|| self.list_or_empty(&list_key).len() + values.len())?;
+        if let Type::List(list) = self.get_or_create(list_key, Kind::List)? {
             list.try_reserve(values.len())?;
             list.extend(values.into_iter());
+            trim_oldest(list, trim_to, false);
         }
         Ok(())
     }
 
-    fn rpushx(&mut self, list_key: String, values: Vec<String>) -> Result<(), TryReserveError> {
-        match self.values.get_mut(&list_key) {
-            Some(Type::List(list)) => {
-                list.try_reserve(values.len())?;
-                list.extend(values.into_iter());
-                Ok(())
+    fn rpushx(&mut self, list_key: String, values: Vec<String>) -> Result<(), ErrorType> {
+        let trim_to = self.list_length_limit(|| self.list_or_empty(&list_key).len() + values.len())?;
+        let pushed = !values.is_empty();
+        if let Some(Type::List(list)) = self.get_mut_value(&list_key) {
+            list.try_reserve(values.len())?;
+            list.extend(values.into_iter());
+            trim_oldest(list, trim_to, false);
+            if pushed {
+                self.modified(&list_key);
             }
-            _ => Ok(()),
         }
+        Ok(())
+    }
+}
+
+impl KeyAble for Store {
+    fn exists(&self, key: &str) -> bool {
+        match self.value(key) {
+            Some(Type::String(_) | Type::HashMap(_) | Type::List(_) | Type::User(_)) => true,
+            None => false,
+        }
+    }
+}
+
+impl Expirable for Store {
+    fn expire(&mut self, key: &str, ttl: Duration) -> bool {
+        self.remove_if_expired(key);
+        if matches!(self.values.get(key), None | Some(Type::User(_))) {
+            return false;
+        }
+        match Instant::now().checked_add(ttl) {
+            Some(deadline) => { self.expires.insert(key.to_string(), deadline); }
+            // Too far in the future to represent, it wouldn't expire anyway
+            None => { self.expires.remove(key); }
+        }
+        true
+    }
+
+    fn ttl(&self, key: &str) -> Option<Duration> {
+        self.value(key)?;
+        let deadline = self.expires.get(key)?;
+        Some(deadline.saturating_duration_since(Instant::now()))
+    }
+
+    fn persist(&mut self, key: &str) -> bool {
+        self.remove_if_expired(key);
+        self.expires.remove(key).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> Store {
+        Store::new(false, TypeLimits::default())
+    }
+
+    #[test]
+    fn hincrby_on_other_types_is_wrong_type() {
+        let mut store = store();
+        store.set("string".to_string(), "1".to_string()).unwrap();
+        store.rpush("list".to_string(), vec!["1".to_string()]).unwrap();
+        assert!(matches!(store.hincrby("string".to_string(), "field".to_string(), 1), Err(ErrorType::WrongType)));
+        assert!(matches!(store.hincrby("list".to_string(), "field".to_string(), 1), Err(ErrorType::WrongType)));
+        assert_eq!(store.get("string"), Some(&"1".to_string()));
+        assert_eq!(store.llen("list".to_string()), 1);
+    }
+
+    #[test]
+    fn hincrby_near_max_overflows_without_change() {
+        let mut store = store();
+        assert_eq!(store.hincrby("hash".to_string(), "field".to_string(), i64::MAX - 1).unwrap(), i64::MAX - 1);
+        assert_eq!(store.hincrby("hash".to_string(), "field".to_string(), 1).unwrap(), i64::MAX);
+        assert!(matches!(store.hincrby("hash".to_string(), "field".to_string(), 1), Err(ErrorType::Overflow)));
+        assert_eq!(store.hget("hash".to_string(), "field".to_string()), Some(&i64::MAX.to_string()));
+    }
+
+    fn list(store: &mut Store, key: &str, values: &[&str]) {
+        store.rpush(key.to_string(), values.iter().map(|v| v.to_string()).collect()).unwrap();
+    }
+
+    fn elements(store: &Store, key: &str) -> Vec<String> {
+        match store.value(key) {
+            Some(Type::List(list)) => list.iter().cloned().collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    #[test]
+    fn lset_on_missing_list_fails() {
+        let mut store = store();
+        assert!(!store.lset("list".to_string(), 0, "a".to_string()));
+        assert!(!store.lset("list".to_string(), -1, "a".to_string()));
+        assert!(!store.exists("list"));
+    }
+
+    #[test]
+    fn lset_in_range() {
+        let mut store = store();
+        list(&mut store, "list", &["a", "b", "c"]);
+        assert!(store.lset("list".to_string(), 0, "x".to_string()));
+        assert!(store.lset("list".to_string(), 2, "y".to_string()));
+        assert!(store.lset("list".to_string(), -2, "z".to_string()));
+        assert_eq!(store.lrange("list".to_string(), 0, 3).unwrap(), vec!["x", "z", "y"]);
+    }
+
+    #[test]
+    fn lset_out_of_range_fails() {
+        let mut store = store();
+        list(&mut store, "list", &["a", "b", "c"]);
+        assert!(!store.lset("list".to_string(), 3, "x".to_string()));
+        assert!(!store.lset("list".to_string(), -4, "x".to_string()));
+        assert_eq!(store.lrange("list".to_string(), 0, 3).unwrap(), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn lrange_start_before_head_is_clamped() {
+        let mut store = store();
+        list(&mut store, "list", &["a", "b", "c"]);
+        assert_eq!(store.lrange("list".to_string(), -10, 2).unwrap(), vec!["a", "b"]);
+        assert_eq!(store.lrange("list".to_string(), -10, -1).unwrap(), vec!["a", "b"]);
+        assert_eq!(store.lrange("list".to_string(), -2, 3).unwrap(), vec!["b", "c"]);
+    }
+
+    #[test]
+    fn expired_keys_read_as_absent() {
+        let mut store = store();
+        store.set("string".to_string(), "1".to_string()).unwrap();
+        list(&mut store, "list", &["a"]);
+        store.hadd("hash".to_string(), "field".to_string(), "1".to_string()).unwrap();
+        for key in ["string", "list", "hash"] {
+            assert!(store.expire(key, Duration::ZERO));
+            assert!(!store.exists(key));
+            assert_eq!(store.ttl(key), None);
+        }
+        assert_eq!(store.get("string"), None);
+        assert_eq!(store.llen("list".to_string()), 0);
+        assert_eq!(store.hget("hash".to_string(), "field".to_string()), None);
+        // Still held until a write or the sweeper removes them
+        assert_eq!(store.values.len(), 3);
+    }
+
+    #[test]
+    fn write_after_expiry_starts_fresh() {
+        let mut store = store();
+        list(&mut store, "list", &["a", "b"]);
+        store.expire("list", Duration::ZERO);
+        list(&mut store, "list", &["c"]);
+        assert_eq!(store.lrange("list".to_string(), 0, 10).unwrap(), vec!["c"]);
+        assert_eq!(store.ttl("list"), None);
+    }
+
+    #[test]
+    fn set_clears_the_ttl() {
+        let mut store = store();
+        store.set("key".to_string(), "1".to_string()).unwrap();
+        assert!(store.expire("key", Duration::from_secs(60)));
+        assert!(store.ttl("key").is_some_and(|ttl| ttl <= Duration::from_secs(60)));
+        store.set("key".to_string(), "2".to_string()).unwrap();
+        assert_eq!(store.ttl("key"), None);
+    }
+
+    #[test]
+    fn persist_keeps_the_key() {
+        let mut store = store();
+        store.set("key".to_string(), "1".to_string()).unwrap();
+        assert!(!store.persist("key"));
+        store.expire("key", Duration::from_secs(60));
+        assert!(store.persist("key"));
+        assert_eq!(store.ttl("key"), None);
+        assert_eq!(store.remove_expired(), 0);
+        assert_eq!(store.get("key"), Some(&"1".to_string()));
+    }
+
+    #[test]
+    fn expire_missing_keys_and_users_fails() {
+        let mut store = store();
+        store.user_add("alice", "password", None);
+        assert!(!store.expire("missing", Duration::from_secs(60)));
+        assert!(!store.expire("alice", Duration::ZERO));
+        assert!(store.user_is_valid("alice", "password"));
+    }
+
+    #[test]
+    fn remove_expired_removes_only_expired_keys() {
+        let mut store = store();
+        for key in ["a", "b", "c"] {
+            store.set(key.to_string(), "1".to_string()).unwrap();
+        }
+        store.expire("a", Duration::ZERO);
+        store.expire("b", Duration::from_secs(60));
+        assert_eq!(store.remove_expired(), 1);
+        assert!(!store.values.contains_key("a"));
+        assert!(!store.expires.contains_key("a"));
+        assert_eq!(store.get("b"), Some(&"1".to_string()));
+        assert_eq!(store.get("c"), Some(&"1".to_string()));
+        assert_eq!(store.remove_expired(), 0);
+    }
+
+    #[test]
+    fn exists_for_every_type() {
+        let mut store = store();
+        store.set("string".to_string(), "1".to_string()).unwrap();
+        store.hadd("hash".to_string(), "field".to_string(), "1".to_string()).unwrap();
+        list(&mut store, "list", &["a"]);
+        store.user_add("alice", "password", None);
+        for key in ["string", "hash", "list", "alice"] {
+            assert!(store.exists(key), "{}", key);
+        }
+        assert!(!store.exists("missing"));
+    }
+
+    #[test]
+    fn type_of_every_type() {
+        let mut store = store();
+        store.set("string".to_string(), "1".to_string()).unwrap();
+        store.hadd("hash".to_string(), "field".to_string(), "1".to_string()).unwrap();
+        list(&mut store, "list", &["a"]);
+        store.user_add("alice", "password", None);
+        assert_eq!(store.type_of("string"), Some("string"));
+        assert_eq!(store.type_of("hash"), Some("hash"));
+        assert_eq!(store.type_of("list"), Some("list"));
+        assert_eq!(store.type_of("alice"), Some("user"));
+        assert_eq!(store.type_of("missing"), None);
+    }
+
+    #[test]
+    fn rename_typed_moves_same_type() {
+        let mut store = store();
+        list(&mut store, "source", &["a", "b"]);
+        list(&mut store, "destination", &["c"]);
+        assert!(store.rename_typed("source", "destination".to_string()).unwrap());
+        assert!(!store.exists("source"));
+        assert_eq!(store.lrange("destination".to_string(), 0, 10).unwrap(), vec!["a", "b"]);
+        assert!(store.rename_typed("destination", "fresh".to_string()).unwrap());
+        assert_eq!(store.llen("fresh".to_string()), 2);
+    }
+
+    #[test]
+    fn rename_typed_rejects_other_types() {
+        let mut store = store();
+        list(&mut store, "source", &["a"]);
+        store.set("string".to_string(), "1".to_string()).unwrap();
+        store.user_add("alice", "password", None);
+        assert!(matches!(store.rename_typed("source", "string".to_string()), Err(ErrorType::WrongType)));
+        assert!(matches!(store.rename_typed("source", "alice".to_string()), Err(ErrorType::WrongType)));
+        assert_eq!(store.llen("source".to_string()), 1);
+        assert_eq!(store.get("string"), Some(&"1".to_string()));
+        assert!(store.user_is_valid("alice", "password"));
+    }
+
+    #[test]
+    fn rename_typed_missing_source_or_user_is_false() {
+        let mut store = store();
+        store.user_add("alice", "password", None);
+        assert!(!store.rename_typed("missing", "destination".to_string()).unwrap());
+        assert!(!store.rename_typed("alice", "destination".to_string()).unwrap());
+        assert!(!store.exists("destination"));
+        assert!(store.user_is_valid("alice", "password"));
+    }
+
+    #[test]
+    fn rename_typed_moves_the_expiry() {
+        let mut store = store();
+        store.set("source".to_string(), "1".to_string()).unwrap();
+        store.set("destination".to_string(), "2".to_string()).unwrap();
+        store.expire("source", Duration::from_secs(60));
+        store.expire("destination", Duration::from_secs(5));
+        assert!(store.rename_typed("source", "destination".to_string()).unwrap());
+        assert_eq!(store.ttl("source"), None);
+        assert!(store.ttl("destination").is_some_and(|ttl| ttl > Duration::from_secs(5)));
+
+        store.set("persistent".to_string(), "3".to_string()).unwrap();
+        assert!(store.rename_typed("persistent", "destination".to_string()).unwrap());
+        assert_eq!(store.ttl("destination"), None);
+    }
+
+    #[test]
+    fn rename_typed_drops_the_tags_of_source() {
+        let mut store = store();
+        store.set("source".to_string(), "1".to_string()).unwrap();
+        store.set("destination".to_string(), "2".to_string()).unwrap();
+        store.tag("source", vec!["moved".to_string()]);
+        store.tag("destination", vec!["kept".to_string()]);
+        assert!(store.rename_typed("source", "destination".to_string()).unwrap());
+        assert!(store.keys_by_tag("moved").is_empty());
+        assert_eq!(store.keys_by_tag("kept"), vec!["destination"]);
+    }
+
+    /// A million pops from the head, the end that was O(n) to pop while lists were Vecs. LPOP takes the tail, so this goes
+    /// through LPOPTOLIST. Run with cargo test --release -- --ignored
+    #[test]
+    #[ignore]
+    fn bench_pop_head_of_large_list() {
+        let mut store = store();
+        let values: Vec<String> = (0..1_000_000).map(|i| i.to_string()).collect();
+        store.rpush("source".to_string(), values).unwrap();
+        let start = Instant::now();
+        while store.lpop_to_list("source".to_string(), "destination".to_string(), ListEnd::Left).unwrap().is_some() {}
+        let elapsed = start.elapsed();
+        assert_eq!(store.llen("destination".to_string()), 1_000_000);
+        assert!(elapsed < Duration::from_secs(10), "took {:?}", elapsed);
+    }
+
+    fn page_keys(store: &Store, kind: Option<Kind>, order: KeyOrder, offset: usize, count: usize) -> (Vec<String>, usize) {
+        let (page, total) = store.key_page(kind, order, offset, count);
+        (page.into_iter().map(|entry| entry.key).collect(), total)
+    }
+
+    #[test]
+    fn key_page_sorts_and_pages() {
+        let mut store = store();
+        store.set("b".to_string(), "1".to_string()).unwrap();
+        store.set("a".to_string(), "x".repeat(1024)).unwrap();
+        list(&mut store, "c", &["1", "2", "3", "4"]);
+        store.hadd("d".to_string(), "field".to_string(), "value".to_string()).unwrap();
+        store.set("b".to_string(), "2".to_string()).unwrap();
+        store.user_add("user", "password", None);
+
+        // Users are neither listed nor counted
+        assert_eq!(page_keys(&store, None, KeyOrder::Name, 0, 10), (vec!["a".to_string(), "b".to_string(), "c".to_string(), "d".to_string()], 4));
+        assert_eq!(page_keys(&store, None, KeyOrder::Name, 1, 2), (vec!["b".to_string(), "c".to_string()], 4));
+        assert_eq!(page_keys(&store, None, KeyOrder::Name, 4, 2), (vec![], 4));
+        assert_eq!(page_keys(&store, None, KeyOrder::Modified, 0, 10).0, vec!["b".to_string(), "d".to_string(), "c".to_string(), "a".to_string()]);
+        assert_eq!(page_keys(&store, Some(Kind::List), KeyOrder::Name, 0, 10), (vec!["c".to_string()], 1));
+
+        let (page, total) = store.key_page(None, KeyOrder::Size, 0, 10);
+        assert_eq!(total, 4);
+        assert_eq!(page[0].key, "a");
+        for (entry, next) in page.iter().zip(page.iter().skip(1)) {
+            assert!(entry.size > next.size || (entry.size == next.size && entry.key < next.key), "{} sorted before {}", entry.key, next.key);
+            assert_eq!(Some(entry.size), store.memory_usage(&entry.key));
+        }
+    }
+
+    #[test]
+    fn lpush_unique_moves_existing_values_to_the_front() {
+        let mut store = store();
+        list(&mut store, "list", &["a", "b", "c"]);
+        assert_eq!(store.lpush_unique("list".to_string(), vec!["c".to_string(), "d".to_string(), "d".to_string()], None).unwrap(), 4);
+        assert_eq!(elements(&store, "list"), vec!["d", "c", "a", "b"]);
+        assert_eq!(store.lpush_unique("list".to_string(), vec!["a".to_string()], Some(2)).unwrap(), 2);
+        assert_eq!(elements(&store, "list"), vec!["a", "d"]);
+    }
+
+    #[test]
+    fn drain_returns_the_list_and_removes_it() {
+        let mut store = store();
+        list(&mut store, "list", &["a", "b", "c"]);
+        store.set("string".to_string(), "value".to_string()).unwrap();
+        assert_eq!(store.drain("list".to_string()), vec!["a", "b", "c"]);
+        assert!(!store.exists("list"));
+        assert!(store.drain("list".to_string()).is_empty());
+        assert!(store.drain("string".to_string()).is_empty());
+        assert_eq!(store.get("string"), Some(&"value".to_string()));
+    }
+
+    #[test]
+    fn compare_set_only_sets_on_match() {
+        let mut store = store();
+        assert!(store.compare_set("key".to_string(), None, "1".to_string()).unwrap());
+        assert!(!store.compare_set("key".to_string(), None, "2".to_string()).unwrap());
+        assert!(!store.compare_set("key".to_string(), Some("2".to_string()), "3".to_string()).unwrap());
+        assert_eq!(store.get("key"), Some(&"1".to_string()));
+        assert!(store.compare_set("key".to_string(), Some("1".to_string()), "2".to_string()).unwrap());
+        assert_eq!(store.get("key"), Some(&"2".to_string()));
+        assert!(!store.compare_set("missing".to_string(), Some("1".to_string()), "2".to_string()).unwrap());
+        assert!(!store.exists("missing"));
+        list(&mut store, "list", &["1"]);
+        assert!(matches!(store.compare_set("list".to_string(), Some("1".to_string()), "2".to_string()), Err(ErrorType::WrongType)));
+    }
+
+    fn hash(store: &mut Store, key: &str, fields: &[(&str, &str)]) {
+        for (field, value) in fields {
+            store.hadd(key.to_string(), field.to_string(), value.to_string()).unwrap();
+        }
+    }
+
+    #[test]
+    fn hkeys_match_filters_fields_by_glob() {
+        let mut store = store();
+        hash(&mut store, "hash", &[("name:first", "a"), ("name:last", "b"), ("age", "c")]);
+        let mut fields = store.hkeys_match("hash".to_string(), "name:*").unwrap();
+        fields.sort();
+        assert_eq!(fields, vec!["name:first", "name:last"]);
+        assert!(store.hkeys_match("hash".to_string(), "x*").unwrap().is_empty());
+        assert!(store.hkeys_match("missing".to_string(), "*").unwrap().is_empty());
+    }
+
+    #[test]
+    fn hget_sorted_pages_in_field_order() {
+        let mut store = store();
+        hash(&mut store, "hash", &[("c", "3"), ("a", "1"), ("d", "4"), ("b", "2")]);
+        let fields = |pairs: Vec<(String, String)>| pairs.into_iter().map(|(field, _)| field).collect::<Vec<_>>();
+        assert_eq!(store.hget_sorted("hash".to_string(), false, 0, None).unwrap(), vec![
+            ("a".to_string(), "1".to_string()), ("b".to_string(), "2".to_string()), ("c".to_string(), "3".to_string()), ("d".to_string(), "4".to_string()),
+        ]);
+        assert_eq!(fields(store.hget_sorted("hash".to_string(), false, 1, Some(2)).unwrap()), vec!["b", "c"]);
+        assert_eq!(fields(store.hget_sorted("hash".to_string(), true, 0, Some(3)).unwrap()), vec!["d", "c", "b"]);
+        assert!(store.hget_sorted("hash".to_string(), false, 4, None).unwrap().is_empty());
+        assert!(store.hget_sorted("missing".to_string(), false, 0, None).unwrap().is_empty());
+    }
+
+    fn fields(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(field, value)| (field.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn hset_multi_sets_every_hash_or_none() {
+        let mut store = store();
+        hash(&mut store, "existing", &[("a", "1")]);
+        let counts = store.hset_multi(HashMap::from([
+            ("existing".to_string(), fields(&[("b", "2")])),
+            ("new".to_string(), fields(&[("c", "3"), ("d", "4")])),
+        ])).unwrap();
+        assert_eq!(counts, HashMap::from([("existing".to_string(), 1), ("new".to_string(), 2)]));
+        assert_eq!(store.hget("existing".to_string(), "b".to_string()), Some(&"2".to_string()));
+        assert_eq!(store.hlen("new".to_string()), 2);
+
+        store.set("string".to_string(), "1".to_string()).unwrap();
+        let result = store.hset_multi(HashMap::from([
+            ("existing".to_string(), fields(&[("e", "5")])),
+            ("other".to_string(), fields(&[("f", "6")])),
+            ("string".to_string(), fields(&[("g", "7")])),
+        ]));
+        assert!(matches!(result, Err(ErrorType::WrongType)));
+        assert_eq!(store.hget("existing".to_string(), "e".to_string()), None);
+        assert!(!store.exists("other"));
+    }
+
+    #[test]
+    fn lget_counts_negative_indices_from_the_tail() {
+        let mut store = store();
+        list(&mut store, "list", &["a", "b", "c"]);
+        assert_eq!(store.lget("list".to_string(), 0), Some(&"a".to_string()));
+        assert_eq!(store.lget("list".to_string(), 2), Some(&"c".to_string()));
+        assert_eq!(store.lget("list".to_string(), -1), Some(&"c".to_string()));
+        assert_eq!(store.lget("list".to_string(), -3), Some(&"a".to_string()));
+        assert_eq!(store.lget("list".to_string(), 3), None);
+        assert_eq!(store.lget("list".to_string(), -4), None);
+        assert_eq!(store.lget("missing".to_string(), 0), None);
+    }
+
+    #[test]
+    fn type_limits_cap_new_keys_per_type() {
+        let mut store = Store::new(false, TypeLimits { max_strings: Some(2), max_lists: Some(1), ..Default::default() });
+        store.set("a".to_string(), "1".to_string()).unwrap();
+        store.set("b".to_string(), "1".to_string()).unwrap();
+        assert!(matches!(store.set("c".to_string(), "1".to_string()), Err(ErrorType::TypeLimit("string"))));
+        // Overwriting doesn't create a key, removing one frees its slot
+        store.set("a".to_string(), "2".to_string()).unwrap();
+        store.remove("a");
+        store.set("c".to_string(), "1".to_string()).unwrap();
+
+        list(&mut store, "list", &["a"]);
+        assert!(matches!(store.rpush("other".to_string(), vec!["a".to_string()]), Err(ErrorType::TypeLimit("list"))));
+        assert!(!store.exists("other"));
+        // Hashes aren't limited
+        for i in 0..10 {
+            store.hadd(format!("hash{}", i), "field".to_string(), "1".to_string()).unwrap();
+        }
+    }
+
+    #[test]
+    fn lrotate_moves_an_element_between_head_and_tail() {
+        let mut store = store();
+        list(&mut store, "list", &["a", "b", "c"]);
+        assert_eq!(store.lrotate("list".to_string(), true), Some("a".to_string()));
+        assert_eq!(elements(&store, "list"), vec!["b", "c", "a"]);
+        assert_eq!(store.lrotate("list".to_string(), false), Some("a".to_string()));
+        assert_eq!(elements(&store, "list"), vec!["a", "b", "c"]);
+        assert_eq!(store.lrotate("missing".to_string(), true), None);
+        list(&mut store, "empty", &[]);
+        assert_eq!(store.lrotate("empty".to_string(), true), None);
+    }
+
+    #[test]
+    fn memory_usage_grows_with_the_value() {
+        let mut store = store();
+        store.set("small".to_string(), "x".to_string()).unwrap();
+        store.set("large".to_string(), "x".repeat(1000)).unwrap();
+        let small = store.memory_usage("small").unwrap();
+        let large = store.memory_usage("large").unwrap();
+        assert!(large >= small + 999, "{} vs {}", large, small);
+        list(&mut store, "list", &["a"]);
+        let one = store.memory_usage("list").unwrap();
+        list(&mut store, "list", &["b", "c", "d", "e"]);
+        assert!(store.memory_usage("list").unwrap() > one);
+        hash(&mut store, "hash", &[("field", "value")]);
+        assert!(store.memory_usage("hash").unwrap() > "field".len() + "value".len());
+        store.user_add("user", "password", None);
+        assert_eq!(store.memory_usage("user"), None);
+        assert_eq!(store.memory_usage("missing"), None);
+    }
+
+    #[test]
+    fn multi_lock_acquires_all_keys_or_none() {
+        let mut store = store();
+        assert!(store.multi_lock(vec!["a".to_string(), "b".to_string()], "token".to_string()).unwrap().is_empty());
+        assert_eq!(store.get("a"), Some(&"token".to_string()));
+        assert_eq!(store.get("b"), Some(&"token".to_string()));
+        let held = store.multi_lock(vec!["b".to_string(), "c".to_string()], "other".to_string()).unwrap();
+        assert_eq!(held, vec!["b"]);
+        assert!(!store.exists("c"));
+        assert_eq!(store.get("b"), Some(&"token".to_string()));
+    }
+
+    #[test]
+    fn tags_follow_their_keys() {
+        let mut store = store();
+        store.set("a".to_string(), "1".to_string()).unwrap();
+        list(&mut store, "b", &["1"]);
+        store.user_add("user", "password", None);
+        assert_eq!(store.tag("a", vec!["red".to_string(), "blue".to_string()]), Some(2));
+        assert_eq!(store.tag("a", vec!["red".to_string()]), Some(0));
+        assert_eq!(store.tag("b", vec!["red".to_string()]), Some(1));
+        assert_eq!(store.tag("missing", vec!["red".to_string()]), None);
+        assert_eq!(store.tag("user", vec!["red".to_string()]), None);
+        assert_eq!(store.keys_by_tag("red"), vec!["a", "b"]);
+
+        assert_eq!(store.untag("a", vec!["red".to_string(), "green".to_string()]), 1);
+        assert_eq!(store.keys_by_tag("red"), vec!["b"]);
+        store.remove("a");
+        assert!(store.keys_by_tag("blue").is_empty());
+    }
+
+    #[test]
+    fn compact_frees_unused_capacity_and_keeps_values() {
+        let mut store = store();
+        let values = (0..1000).map(|i| i.to_string()).collect::<Vec<_>>();
+        store.rpush("list".to_string(), values).unwrap();
+        store.rpop("list".to_string(), Some(998)).unwrap();
+        let before = store.memory_usage("list").unwrap();
+        assert!(store.compact() > 0);
+        assert!(store.memory_usage("list").unwrap() < before);
+        assert_eq!(elements(&store, "list"), vec!["0", "1"]);
+        assert_eq!(store.compact(), 0);
+    }
+
+    #[test]
+    fn hswap_exchanges_or_moves_field_values() {
+        let mut store = store();
+        hash(&mut store, "hash", &[("a", "1"), ("b", "2")]);
+        assert!(store.hswap("hash".to_string(), "a".to_string(), "b".to_string()));
+        assert_eq!(store.hget_all("hash".to_string()).unwrap(), fields(&[("a", "2"), ("b", "1")]));
+        assert!(store.hswap("hash".to_string(), "a".to_string(), "c".to_string()));
+        assert_eq!(store.hget_all("hash".to_string()).unwrap(), fields(&[("b", "1"), ("c", "2")]));
+        assert!(store.hswap("hash".to_string(), "b".to_string(), "b".to_string()));
+        assert_eq!(store.hget("hash".to_string(), "b".to_string()), Some(&"1".to_string()));
+        assert!(!store.hswap("missing".to_string(), "a".to_string(), "b".to_string()));
+    }
+
+    #[test]
+    fn linter_keeps_the_order_of_first() {
+        let mut store = store();
+        list(&mut store, "first", &["c", "a", "b", "a"]);
+        list(&mut store, "second", &["a", "c", "x"]);
+        assert_eq!(store.linter("first".to_string(), "second".to_string(), false).unwrap(), vec!["c", "a", "a"]);
+        assert_eq!(store.linter("first".to_string(), "second".to_string(), true).unwrap(), vec!["c", "a"]);
+        assert!(store.linter("first".to_string(), "missing".to_string(), false).unwrap().is_empty());
+        assert!(store.linter("missing".to_string(), "second".to_string(), false).unwrap().is_empty());
+    }
+
+    #[test]
+    fn ldiff_keeps_the_order_of_first() {
+        let mut store = store();
+        list(&mut store, "first", &["c", "a", "b", "a", "d"]);
+        list(&mut store, "second", &["a", "x"]);
+        assert_eq!(store.ldiff("first".to_string(), "second".to_string()).unwrap(), vec!["c", "b", "d"]);
+        assert_eq!(store.ldiff("first".to_string(), "missing".to_string()).unwrap(), vec!["c", "a", "b", "a", "d"]);
+        assert!(store.ldiff("missing".to_string(), "second".to_string()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn htop_orders_by_numeric_value() {
+        let mut store = store();
+        hash(&mut store, "hash", &[("a", "10"), ("b", "-5"), ("c", "100"), ("d", "10"), ("e", "text")]);
+        let top = store.htop("hash".to_string(), 3, false).unwrap();
+        assert_eq!(top, vec![("c".to_string(), "100".to_string()), ("a".to_string(), "10".to_string()), ("d".to_string(), "10".to_string())]);
+        assert_eq!(store.htop("hash".to_string(), 10, false).unwrap().len(), 4);
+        assert!(matches!(store.htop("hash".to_string(), 10, true), Err(ErrorType::WrongType)));
+        assert!(store.htop("missing".to_string(), 3, false).unwrap().is_empty());
+    }
+
+    #[test]
+    fn decr_del_removes_the_key_at_zero() {
+        let mut store = store();
+        store.set("count".to_string(), "3".to_string()).unwrap();
+        assert_eq!(store.decr_del("count".to_string(), 2).unwrap(), (1, false));
+        assert_eq!(store.get("count"), Some(&"1".to_string()));
+        assert_eq!(store.decr_del("count".to_string(), 1).unwrap(), (0, true));
+        assert!(!store.exists("count"));
+        // A missing key counts as 0, so it is never created
+        assert_eq!(store.decr_del("count".to_string(), 1).unwrap(), (-1, true));
+        assert!(!store.exists("count"));
+
+        store.set("text".to_string(), "abc".to_string()).unwrap();
+        assert!(matches!(store.decr_del("text".to_string(), 1), Err(ErrorType::ParseIntError(_))));
+        store.set("min".to_string(), i64::MIN.to_string()).unwrap();
+        assert!(matches!(store.decr_del("min".to_string(), 1), Err(ErrorType::Overflow)));
+        assert_eq!(store.get("min"), Some(&i64::MIN.to_string()));
+    }
+
+    #[test]
+    fn check_invariants_holds_after_writes_and_finds_corruption() {
+        let mut store = store();
+        store.set("a".to_string(), "1".to_string()).unwrap();
+        store.tag("a", vec!["red".to_string()]);
+        store.expire("a", Duration::from_secs(60));
+        store.rename_typed("a", "b".to_string()).unwrap();
+        list(&mut store, "list", &["1", "2"]);
+        store.tag("list", vec!["red".to_string()]);
+        store.drain("list".to_string());
+        hash(&mut store, "hash", &[("field", "1")]);
+        store.user_add("user", "password", None);
+        assert_eq!(store.check_invariants(), Vec::<String>::new());
+
+        store.counts.hashes += 1;
+        store.tags.entry("blue".to_string()).or_default().insert("hash".to_string());
+        let violations = store.check_invariants();
+        assert_eq!(violations.len(), 2, "{:?}", violations);
+        assert!(violations.contains(&"hash count is 2 but 1 exist".to_string()));
+    }
+
+    #[test]
+    fn recently_modified_lists_the_latest_writes_first() {
+        let mut store = store();
+        store.set("a".to_string(), "1".to_string()).unwrap();
+        list(&mut store, "b", &["1"]);
+        hash(&mut store, "c", &[("field", "1")]);
+        store.user_add("user", "password", None);
+        store.set("a".to_string(), "2".to_string()).unwrap();
+        let _ = store.get("b");
+        let keys = |store: &Store, count| store.recently_modified(count).into_iter().map(|(key, _)| key).collect::<Vec<_>>();
+        assert_eq!(keys(&store, 10), vec!["a", "c", "b"]);
+        assert_eq!(keys(&store, 2), vec!["a", "c"]);
+        store.remove("a");
+        assert_eq!(keys(&store, 10), vec!["c", "b"]);
+    }
+
+    #[test]
+    fn digest_depends_on_contents_not_on_write_order() {
+        let mut first = store();
+        first.set("a".to_string(), "1".to_string()).unwrap();
+        hash(&mut first, "h", &[("x", "1"), ("y", "2")]);
+        list(&mut first, "l", &["1", "2"]);
+        let mut second = Store::new(true, TypeLimits::default());
+        list(&mut second, "l", &["1", "2"]);
+        hash(&mut second, "h", &[("y", "2"), ("x", "1")]);
+        second.set("a".to_string(), "1".to_string()).unwrap();
+        second.user_add("user", "password", None);
+        assert_eq!(first.digest(), second.digest());
+
+        // Same strings, split differently between key and value
+        let mut third = store();
+        third.set("a1".to_string(), "".to_string()).unwrap();
+        let mut fourth = store();
+        fourth.set("a".to_string(), "1".to_string()).unwrap();
+        assert_ne!(third.digest(), fourth.digest());
+        list(&mut second, "l", &["3"]);
+        assert_ne!(first.digest(), second.digest());
+    }
+
+    #[test]
+    fn append_if_is_idempotent() {
+        let mut store = store();
+        assert_eq!(store.append_if("path".to_string(), "/".to_string()).unwrap(), (true, 1));
+        store.set("path".to_string(), "/usr".to_string()).unwrap();
+        assert_eq!(store.append_if("path".to_string(), "/".to_string()).unwrap(), (true, 5));
+        assert_eq!(store.append_if("path".to_string(), "/".to_string()).unwrap(), (false, 5));
+        assert_eq!(store.get("path"), Some(&"/usr/".to_string()));
+        list(&mut store, "list", &["a"]);
+        assert!(matches!(store.append_if("list".to_string(), "/".to_string()), Err(ErrorType::WrongType)));
+    }
+
+    #[test]
+    fn keys_of_type_filters_by_kind_and_pattern() {
+        let mut store = store();
+        store.set("user:2".to_string(), "1".to_string()).unwrap();
+        store.set("user:1".to_string(), "1".to_string()).unwrap();
+        store.set("other".to_string(), "1".to_string()).unwrap();
+        list(&mut store, "user:list", &["a"]);
+        store.user_add("user", "password", None);
+        assert_eq!(store.keys_of_type(Kind::String, None), vec!["other", "user:1", "user:2"]);
+        assert_eq!(store.keys_of_type(Kind::String, Some("user:*")), vec!["user:1", "user:2"]);
+        assert_eq!(store.keys_of_type(Kind::List, None), vec!["user:list"]);
+        assert!(store.keys_of_type(Kind::User, None).is_empty());
+        assert_eq!(Kind::from_name("hash"), Some(Kind::Hash));
+        assert_eq!(Kind::from_name("user"), None);
+    }
+
+    #[test]
+    fn lreplace_swaps_contents_and_deletes_on_empty() {
+        let mut store = store();
+        list(&mut store, "list", &["a", "b"]);
+        assert_eq!(store.lreplace("list".to_string(), vec!["x".to_string(), "y".to_string(), "z".to_string()]).unwrap(), 3);
+        assert_eq!(elements(&store, "list"), vec!["x", "y", "z"]);
+        assert_eq!(store.lreplace("new".to_string(), vec!["1".to_string()]).unwrap(), 1);
+        assert_eq!(elements(&store, "new"), vec!["1"]);
+        assert_eq!(store.lreplace("list".to_string(), Vec::new()).unwrap(), 0);
+        assert!(!store.exists("list"));
+        assert_eq!(store.check_invariants(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn size_limits_reject_or_trim() {
+        let mut store = Store::new(false, TypeLimits { max_list_length: Some(3), max_hash_fields: Some(2), ..Default::default() });
+        list(&mut store, "list", &["1", "2", "3"]);
+        assert!(matches!(store.rpush("list".to_string(), vec!["4".to_string()]), Err(ErrorType::SizeLimit("list", 3))));
+        assert_eq!(elements(&store, "list"), vec!["1", "2", "3"]);
+        hash(&mut store, "hash", &[("a", "1"), ("b", "1")]);
+        // Overwriting a field doesn't grow the hash
+        store.hadd("hash".to_string(), "a".to_string(), "2".to_string()).unwrap();
+        assert!(matches!(store.hadd("hash".to_string(), "c".to_string(), "1".to_string()), Err(ErrorType::SizeLimit("hash", 2))));
+        assert_eq!(store.hlen("hash".to_string()), 2);
+
+        let mut store = Store::new(false, TypeLimits { max_list_length: Some(3), trim_full_lists: true, ..Default::default() });
+        list(&mut store, "list", &["1", "2", "3", "4", "5"]);
+        assert_eq!(elements(&store, "list"), vec!["3", "4", "5"]);
+        // lpush appends as well, so the front is dropped again
+        store.lpush("list".to_string(), vec!["0".to_string()]).unwrap();
+        assert_eq!(elements(&store, "list"), vec!["4", "5", "0"]);
+    }
+
+    #[test]
+    fn lpop_to_list_moves_the_given_end_onto_the_tail() {
+        let mut store = store();
+        list(&mut store, "source", &["a", "b", "c"]);
+        list(&mut store, "destination", &["x"]);
+        assert_eq!(store.lpop_to_list("source".to_string(), "destination".to_string(), ListEnd::Left).unwrap(), Some("a".to_string()));
+        assert_eq!(elements(&store, "source"), vec!["b", "c"]);
+        assert_eq!(elements(&store, "destination"), vec!["x", "a"]);
+        // The same end LPOP takes from
+        assert_eq!(store.lpop_to_list("source".to_string(), "destination".to_string(), ListEnd::Right).unwrap(), Some("c".to_string()));
+        assert_eq!(elements(&store, "destination"), vec!["x", "a", "c"]);
+        assert_eq!(store.lpop_to_list("destination".to_string(), "destination".to_string(), ListEnd::Right).unwrap(), Some("c".to_string()));
+        assert_eq!(elements(&store, "destination"), vec!["x", "a", "c"]);
+        assert_eq!(store.lpop_to_list("source".to_string(), "new".to_string(), ListEnd::Left).unwrap(), Some("b".to_string()));
+        assert_eq!(elements(&store, "new"), vec!["b"]);
+        assert_eq!(store.lpop_to_list("source".to_string(), "new".to_string(), ListEnd::Left).unwrap(), None);
+        assert_eq!(store.lpop_to_list("missing".to_string(), "other".to_string(), ListEnd::Right).unwrap(), None);
+        assert!(!store.exists("other"));
+        assert_eq!(store.check_invariants(), Vec::<String>::new());
+    }
+
+
+    #[test]
+    fn set_with_version_rejects_stale_versions() {
+        let mut store = store();
+        let (stored, version) = store.set_with_version("key".to_string(), 0, "1".to_string()).unwrap();
+        assert!(stored);
+        assert_ne!(version, 0);
+        assert_eq!(store.set_with_version("key".to_string(), 0, "2".to_string()).unwrap(), (false, version));
+        assert_eq!(store.get("key"), Some(&"1".to_string()));
+        let (stored, newer) = store.set_with_version("key".to_string(), version, "2".to_string()).unwrap();
+        assert!(stored);
+        assert!(newer > version);
+        assert_eq!(store.get("key"), Some(&"2".to_string()));
+        list(&mut store, "list", &["1"]);
+        assert!(matches!(store.set_with_version("list".to_string(), 0, "2".to_string()), Err(ErrorType::WrongType)));
+    }
+
+
+    #[test]
+    fn size_histogram_buckets_every_value_but_users() {
+        let mut store = store();
+        store.set("small".to_string(), "x".to_string()).unwrap();
+        store.set("medium".to_string(), "x".repeat(2000)).unwrap();
+        store.set("large".to_string(), "x".repeat(300 << 10)).unwrap();
+        store.set("huge".to_string(), "x".repeat(5 << 20)).unwrap();
+        store.user_add("user", "password", None);
+        let histogram = store.size_histogram();
+        let names = histogram.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+        assert_eq!(names, vec!["<64B", "<1KB", "<16KB", "<256KB", "<4MB", ">=4MB"]);
+        let counts = histogram.iter().map(|(_, count)| *count).collect::<Vec<_>>();
+        // The small string may cross 64 bytes with the key and bookkeeping overhead
+        assert_eq!(counts[0] + counts[1], 1);
+        assert_eq!(counts[2..], [1, 0, 1, 1]);
+    }
+
+
+    #[test]
+    fn wrong_type_and_no_op_writes_keep_the_version() {
+        let mut store = store();
+        store.set("string".to_string(), "value".to_string()).unwrap();
+        list(&mut store, "list", &["a", "b"]);
+        hash(&mut store, "hash", &[("field", "value")]);
+        let versions = |store: &Store| ["string", "list", "hash"].map(|key| store.version(key));
+        let before = versions(&store);
+
+        assert!(!store.hremove("string".to_string(), "field".to_string()));
+        assert!(!store.hswap("string".to_string(), "a".to_string(), "b".to_string()));
+        assert_eq!(store.lpop("string".to_string(), None).unwrap(), None);
+        assert!(!store.lset("string".to_string(), 0, "x".to_string()));
+        assert_eq!(store.lrem("string".to_string(), 0, "x".to_string()), 0);
+        assert!(!store.ltrim("string".to_string(), 0, 0));
+        store.rpush("string".to_string(), vec!["x".to_string()]).unwrap();
+        assert!(!store.lset("list".to_string(), 5, "x".to_string()));
+        assert_eq!(store.lrem("list".to_string(), 0, "missing".to_string()), 0);
+        assert!(store.ltrim("list".to_string(), 0, -1));
+        assert!(store.hremove("hash".to_string(), "missing".to_string()));
+        assert!(store.hswap("hash".to_string(), "a".to_string(), "b".to_string()));
+        assert_eq!(versions(&store), before);
+        assert!(store.recently_modified(3).iter().map(|(key, _)| key).eq(["hash", "list", "string"].iter()));
+
+        assert!(store.lset("list".to_string(), 0, "x".to_string()));
+        assert!(store.version("list") > before[1]);
+        assert!(store.hremove("hash".to_string(), "field".to_string()));
+        assert!(store.version("hash") > before[2]);
     }
-}
\ No newline at end of file
+}